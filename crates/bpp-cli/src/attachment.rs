@@ -0,0 +1,170 @@
+use crate::error_def::BppCliError;
+use bpp_proto::bpp::{AttachRequest, Attachment};
+use error_stack::{report, Result};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Size of the chunks of data sent by `bpp attach`
+const CHUNK_BYTES: usize = 64 * 1024;
+
+/// Media types guessed from the extension of attached files, the server
+/// defaults to application/octet-stream
+static MEDIA_TYPES: [(&str, &str); 16] = [
+    ("txt", "text/plain"),
+    ("md", "text/markdown"),
+    ("csv", "text/csv"),
+    ("html", "text/html"),
+    ("json", "application/json"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("svg", "image/svg+xml"),
+    ("webp", "image/webp"),
+    ("mp3", "audio/mpeg"),
+    ("mp4", "video/mp4"),
+];
+
+/// The requests uploading `path` to the note `note_id`: the first one
+/// names the attachment, every one carries a chunk of the file
+pub fn attach_requests(
+    note_id: &str,
+    path: &Path,
+    name: Option<&str>,
+    media_type: Option<&str>,
+) -> Result<Vec<AttachRequest>, BppCliError> {
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .ok_or_else(|| {
+                report!(BppCliError::InvalidParameters(format!(
+                    "{} is not a file, use --name",
+                    path.display()
+                )))
+            })?,
+    };
+    let media_type = media_type.map(str::to_string).unwrap_or_else(|| {
+        path.extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .and_then(|extension| {
+                MEDIA_TYPES
+                    .iter()
+                    .find(|(known, _)| *known == extension)
+                    .map(|(_, media_type)| media_type.to_string())
+            })
+            .unwrap_or_default()
+    });
+
+    let data = fs::read(path).map_err(|err| {
+        report!(err).change_context(BppCliError::FailedToTransfer(format!(
+            "Failed to read {}",
+            path.display()
+        )))
+    })?;
+    let mut requests: Vec<AttachRequest> = data
+        .chunks(CHUNK_BYTES)
+        .map(|chunk| AttachRequest {
+            data: chunk.to_vec(),
+            ..Default::default()
+        })
+        .collect();
+    if requests.is_empty() {
+        requests.push(AttachRequest::default());
+    }
+    requests[0].note_id = note_id.to_string();
+    requests[0].name = name;
+    requests[0].media_type = media_type;
+    Ok(requests)
+}
+
+/// A fetched attachment being written to a new file, which is removed
+/// unless the received data matches the digest of the attachment
+pub struct Download {
+    file: File,
+    path: PathBuf,
+    hasher: Sha256,
+    complete: bool,
+}
+
+impl Download {
+    /// Never overwrites an existing file
+    pub fn create(path: &Path) -> Result<Self, BppCliError> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(|err| {
+                report!(err).change_context(BppCliError::FailedToTransfer(format!(
+                    "Failed to create {}",
+                    path.display()
+                )))
+            })?;
+        Ok(Download {
+            file,
+            path: path.to_path_buf(),
+            hasher: Sha256::new(),
+            complete: false,
+        })
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<(), BppCliError> {
+        self.hasher.update(data);
+        self.file.write_all(data).map_err(|err| {
+            report!(err).change_context(BppCliError::FailedToTransfer(format!(
+                "Failed to write {}",
+                self.path.display()
+            )))
+        })
+    }
+
+    pub fn finish(mut self, attachment: &Attachment) -> Result<(), BppCliError> {
+        let digest = format!("{:x}", self.hasher.clone().finalize());
+        if digest != attachment.digest {
+            return Err(report!(BppCliError::FailedToTransfer(format!(
+                "{} does not match its digest, received {digest}, expected {}",
+                attachment.name, attachment.digest
+            ))));
+        }
+        self.file
+            .flush()
+            .and_then(|_| self.file.sync_all())
+            .map_err(|err| {
+                report!(err).change_context(BppCliError::FailedToTransfer(format!(
+                    "Failed to write {}",
+                    self.path.display()
+                )))
+            })?;
+        self.complete = true;
+        Ok(())
+    }
+}
+
+impl Drop for Download {
+    fn drop(&mut self) {
+        if !self.complete {
+            fs::remove_file(&self.path).ok();
+        }
+    }
+}
+
+/// Sizes as shown by `bpp attachments`, eg. 1.5 MiB
+pub fn format_size(size: u64) -> String {
+    let units = ["KiB", "MiB", "GiB"];
+    if size < 1024 {
+        return format!("{size} B");
+    }
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < units.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", units[unit])
+}
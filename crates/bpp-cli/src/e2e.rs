@@ -0,0 +1,213 @@
+use crate::error_def::BppCliError;
+use bpp_proto::bpp::Note;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use error_stack::{report, Result};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+const NONCE_LEN: usize = 12;
+/// Prefix of contents encrypted with the note id as associated data.
+/// Contents without it are rejected, so a ciphertext cannot be moved to
+/// another note by dropping the prefix
+const BOUND_PREFIX: &str = "v2:";
+
+/// Symmetric key used to encrypt note contents on the client before they
+/// are sent to the server. The server only ever sees the ciphertext.
+pub struct NoteKey {
+    cipher: ChaCha20Poly1305,
+}
+
+impl NoteKey {
+    /// Path of the key file. Defaults to `~/.bpp/note.key` and can be
+    /// overwritten with `BPP_KEY_FILE`
+    pub fn path() -> PathBuf {
+        if let Ok(path) = env::var("BPP_KEY_FILE") {
+            return PathBuf::from(path);
+        }
+        let home = env::var("HOME").unwrap_or_else(|_| ".".to_string());
+        PathBuf::from(home).join(".bpp").join("note.key")
+    }
+
+    /// Generate a new random key and write it to `NoteKey::path()`.
+    /// An existing key is only replaced if `force` is set, as notes
+    /// encrypted with it would become unreadable. The key is written to a
+    /// temporary file renamed over the previous one, so the previous key
+    /// is kept should writing fail.
+    pub fn generate(force: bool) -> Result<PathBuf, BppCliError> {
+        let path = Self::path();
+        if path.exists() && !force {
+            return Err(report!(BppCliError::EncryptionFailed(format!(
+                "Key file {} already exists",
+                path.display()
+            ))));
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| {
+                report!(err).change_context(BppCliError::EncryptionFailed(
+                    "Failed to create key directory".to_string(),
+                ))
+            })?;
+        }
+
+        let to_write_error = |err: io::Error| {
+            report!(err).change_context(BppCliError::EncryptionFailed(format!(
+                "Failed to write key file {}",
+                path.display()
+            )))
+        };
+        // The key is only ever readable by the user, it is created with
+        // these permissions rather than changed to them once written
+        let tmp_path = path.with_file_name(format!(
+            ".{}.{}",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            Uuid::new_v4()
+        ));
+        let key = ChaCha20Poly1305::generate_key(&mut OsRng);
+        let written = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&tmp_path)
+            .and_then(|mut file| {
+                file.write_all(base64::encode(key).as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&tmp_path, &path));
+        if let Err(err) = written {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(to_write_error(err));
+        }
+        Ok(path)
+    }
+
+    /// Load the key from `NoteKey::path()`
+    pub fn load() -> Result<Self, BppCliError> {
+        let path = Self::path();
+        let encoded = fs::read_to_string(&path).map_err(|err| {
+            report!(err).change_context(BppCliError::EncryptionFailed(format!(
+                "Failed to read key file {}. Run `bpp keygen` to create one",
+                path.display()
+            )))
+        })?;
+        let bytes = base64::decode(encoded.trim()).map_err(|err| {
+            report!(err).change_context(BppCliError::EncryptionFailed(
+                "Key file is not valid base64".to_string(),
+            ))
+        })?;
+        if bytes.len() != 32 {
+            return Err(report!(BppCliError::EncryptionFailed(
+                "Key file must contain a 32 bytes key".to_string()
+            )));
+        }
+
+        Ok(NoteKey {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&bytes)),
+        })
+    }
+
+    /// Encrypt `plaintext` into a base64 string of `nonce || ciphertext`.
+    /// The id of the note is authenticated with it, so the content cannot
+    /// be moved to another note
+    pub fn encrypt(&self, id: &str, plaintext: &str) -> Result<String, BppCliError> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: plaintext.as_bytes(),
+            aad: id.as_bytes(),
+        };
+        let ciphertext = self.cipher.encrypt(&nonce, payload).map_err(|_| {
+            report!(BppCliError::EncryptionFailed(
+                "Failed to encrypt content".to_string()
+            ))
+        })?;
+
+        let mut out = nonce.to_vec();
+        out.extend(ciphertext);
+        Ok(format!("{BOUND_PREFIX}{}", base64::encode(out)))
+    }
+
+    /// Decrypt a string produced by `NoteKey::encrypt` for the note `id`
+    pub fn decrypt(&self, id: &str, encoded: &str) -> Result<String, BppCliError> {
+        let encoded = encoded.strip_prefix(BOUND_PREFIX).ok_or_else(|| {
+            report!(BppCliError::EncryptionFailed(
+                "Encrypted content is not bound to a note id".to_string()
+            ))
+        })?;
+        let bytes = base64::decode(encoded).map_err(|err| {
+            report!(err).change_context(BppCliError::EncryptionFailed(
+                "Encrypted content is not valid base64".to_string(),
+            ))
+        })?;
+        if bytes.len() < NONCE_LEN {
+            return Err(report!(BppCliError::EncryptionFailed(
+                "Encrypted content is too short".to_string()
+            )));
+        }
+
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: id.as_bytes(),
+                },
+            )
+            .map_err(|_| {
+                report!(BppCliError::EncryptionFailed(
+                    "Failed to decrypt content. Wrong key, or content of another note?".to_string()
+                ))
+            })?;
+        String::from_utf8(plaintext).map_err(|err| {
+            report!(err).change_context(BppCliError::EncryptionFailed(
+                "Decrypted content is not valid UTF-8".to_string(),
+            ))
+        })
+    }
+
+    /// Return a copy of `note` with its content decrypted. Notes that are
+    /// not encrypted are returned as they are.
+    pub fn decrypt_note(&self, note: &Note) -> Result<Note, BppCliError> {
+        if !note.encrypted {
+            return Ok(note.clone());
+        }
+        Ok(Note {
+            content: self.decrypt(&note.id, &note.content)?,
+            encrypted: false,
+            ..note.clone()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> NoteKey {
+        NoteKey {
+            cipher: ChaCha20Poly1305::new(&ChaCha20Poly1305::generate_key(&mut OsRng)),
+        }
+    }
+
+    #[test]
+    fn decrypts_what_it_encrypted_for_the_same_note() {
+        let key = key();
+        let encrypted = key.encrypt("a", "secret").unwrap();
+        assert!(encrypted.starts_with(BOUND_PREFIX));
+        assert_eq!(key.decrypt("a", &encrypted).unwrap(), "secret");
+        assert!(key.decrypt("b", &encrypted).is_err());
+    }
+
+    #[test]
+    fn rejects_content_without_the_bound_prefix() {
+        let key = key();
+        let encrypted = key.encrypt("a", "secret").unwrap();
+        let unbound = encrypted.strip_prefix(BOUND_PREFIX).unwrap();
+        assert!(key.decrypt("a", unbound).is_err());
+    }
+}
@@ -1,26 +1,140 @@
+use bpp_proto::{RESOURCE_NAME_KEY, RESOURCE_TYPE_KEY, RETRY_AFTER_KEY};
 use std::error::Error;
 use std::fmt;
 use std::fmt::Formatter;
+use tonic::{Code, Status};
+use tonic_types::{ErrorDetail, StatusExt};
 
 #[derive(Debug)]
 pub enum BppCliError {
     FailedToAddNote,
-    FailedToRmNote,
     InvalidParameters(String),
     FailedToConnect(String),
+    EncryptionFailed(String),
+    FailedToExport(String),
+    NoteNotFound(String),
+    AttachmentNotFound(String),
+    FailedToTransfer(String),
+    TemplateNotFound(String),
+    TemplateExists(String),
+    InvalidArgument { field: String, description: String },
+    ServerUnavailable(String),
+    ServerBusy(String),
+    QuotaExceeded(String),
+    ServerError(String),
 }
 
 impl fmt::Display for BppCliError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             BppCliError::FailedToAddNote => f.write_str("Failed to Add Note"),
-            BppCliError::FailedToRmNote => f.write_str("Failed to Remove Note"),
             BppCliError::InvalidParameters(msg) => {
                 f.write_str(format!("Invalid Parameters: {msg}").as_str())
             }
             BppCliError::FailedToConnect(msg) => f.write_str(msg),
+            BppCliError::EncryptionFailed(msg) => {
+                f.write_str(format!("Encryption failed: {msg}").as_str())
+            }
+            BppCliError::FailedToExport(msg) => {
+                f.write_str(format!("Export failed: {msg}").as_str())
+            }
+            BppCliError::NoteNotFound(id) => f.write_str(
+                format!("Note {id} not found. Use `bpp search` to look up note ids").as_str(),
+            ),
+            BppCliError::AttachmentNotFound(name) => f.write_str(
+                format!("Attachment {name} not found. Use `bpp attachments` to list them").as_str(),
+            ),
+            BppCliError::FailedToTransfer(msg) => {
+                f.write_str(format!("Transfer failed: {msg}").as_str())
+            }
+            BppCliError::TemplateNotFound(name) => f.write_str(
+                format!("Template {name} not found. Use `bpp template list` to list them").as_str(),
+            ),
+            BppCliError::TemplateExists(name) => f.write_str(
+                format!("Template {name} already exists. Use `bpp template update` to change it")
+                    .as_str(),
+            ),
+            BppCliError::InvalidArgument { field, description } => {
+                f.write_str(format!("Invalid {field}: {description}").as_str())
+            }
+            BppCliError::ServerUnavailable(msg) => f.write_str(
+                format!("{msg}. Check that bpp-server is running and try again").as_str(),
+            ),
+            BppCliError::ServerBusy(msg) => {
+                f.write_str(format!("{msg}. The server is busy, try again later").as_str())
+            }
+            BppCliError::QuotaExceeded(msg) => f.write_str(
+                format!("Quota exceeded: {msg}. Remove notes you no longer need to make room")
+                    .as_str(),
+            ),
+            BppCliError::ServerError(msg) => f.write_str(format!("Server error: {msg}").as_str()),
         }
     }
 }
 
 impl Error for BppCliError {}
+
+/// Build the error matching a failed rpc, using the resource and details
+/// attached by the server when there are any
+impl From<&Status> for BppCliError {
+    fn from(status: &Status) -> Self {
+        let resource = resource(status);
+        match status.code() {
+            Code::NotFound => match resource {
+                Some((resource_type, name)) if resource_type == "attachment" => {
+                    BppCliError::AttachmentNotFound(name)
+                }
+                Some((resource_type, name)) if resource_type == "template" => {
+                    BppCliError::TemplateNotFound(name)
+                }
+                Some((_, name)) => BppCliError::NoteNotFound(name),
+                None => BppCliError::NoteNotFound(String::new()),
+            },
+            // Note ids are chosen by the client only for encrypted notes,
+            // random uuids, so templates are the ones users run into
+            Code::AlreadyExists => match resource {
+                Some((resource_type, _)) if resource_type == "note" => {
+                    BppCliError::ServerError(status.message().to_string())
+                }
+                Some((_, name)) => BppCliError::TemplateExists(name),
+                None => BppCliError::TemplateExists(String::new()),
+            },
+            Code::InvalidArgument => {
+                let violation = status
+                    .get_error_details_vec()
+                    .into_iter()
+                    .find_map(|detail| match detail {
+                        ErrorDetail::BadRequest(request) => {
+                            request.field_violations.into_iter().next()
+                        }
+                        _ => None,
+                    });
+                match violation {
+                    Some(violation) => BppCliError::InvalidArgument {
+                        field: violation.field,
+                        description: violation.description,
+                    },
+                    None => BppCliError::InvalidParameters(status.message().to_string()),
+                }
+            }
+            // Rate limited rpcs are the ones the server asks to retry
+            Code::ResourceExhausted if status.metadata().get(RETRY_AFTER_KEY).is_some() => {
+                BppCliError::ServerBusy(status.message().to_string())
+            }
+            Code::ResourceExhausted => BppCliError::QuotaExceeded(status.message().to_string()),
+            Code::Unavailable => BppCliError::ServerUnavailable(status.message().to_string()),
+            _ => BppCliError::ServerError(status.message().to_string()),
+        }
+    }
+}
+
+/// Type and name of the resource a failed rpc was about
+fn resource(status: &Status) -> Option<(String, String)> {
+    let metadata = status.metadata();
+    let resource_type = metadata.get(RESOURCE_TYPE_KEY)?.to_str().ok()?;
+    let name = metadata.get_bin(RESOURCE_NAME_KEY)?.to_bytes().ok()?;
+    Some((
+        resource_type.to_string(),
+        String::from_utf8_lossy(&name).into_owned(),
+    ))
+}
@@ -0,0 +1,315 @@
+use crate::error_def::BppCliError;
+use bpp_proto::bpp::Note;
+use bpp_proto::EXPORT_VERSION;
+use error_stack::{report, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// First entry of a tar export, holds an `ExportHeader`
+pub static MANIFEST_FILE: &str = "manifest.json";
+/// Directory of the markdown notes in a tar export
+pub static TAR_NOTES_DIR: &str = "notes";
+pub static FRONT_MATTER_DELIMITER: &str = "---";
+/// Front matter key holding the export version of a markdown note
+pub static VERSION_KEY: &str = "bpp_export_version";
+
+/// Formats written by `bpp export`, all tagged with `EXPORT_VERSION`:
+///  - md: one markdown file per note in a directory, with the other
+///    fields of the note in a yaml front matter
+///  - json: a single document, the same one `bpp-server export` writes
+///  - jsonl: an `ExportHeader` line, then one note per line
+///  - tar: a `manifest.json` with the `ExportHeader`, then the md files
+///    under `notes/`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+    Jsonl,
+    Tar,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> std::result::Result<Self, Self::Err> {
+        match format {
+            "md" => Ok(ExportFormat::Markdown),
+            "json" => Ok(ExportFormat::Json),
+            "jsonl" => Ok(ExportFormat::Jsonl),
+            "tar" => Ok(ExportFormat::Tar),
+            other => Err(format!("unknown format {other:?}")),
+        }
+    }
+}
+
+/// A note as written in json and jsonl exports
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportedNote {
+    pub id: String,
+    pub title: String,
+    pub content: String,
+    #[serde(default)]
+    pub encrypted: bool,
+    #[serde(default)]
+    pub notebook: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub created_ms: i64,
+    #[serde(default)]
+    pub updated_ms: i64,
+}
+
+impl From<Note> for ExportedNote {
+    fn from(note: Note) -> Self {
+        ExportedNote {
+            id: note.id,
+            title: note.title,
+            content: note.content,
+            encrypted: note.encrypted,
+            notebook: note.notebook,
+            tags: note.tags,
+            created_ms: note.created_ms,
+            updated_ms: note.updated_ms,
+        }
+    }
+}
+
+/// Json export, the layout of `bpp-server export`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportDocument {
+    pub version: u32,
+    pub notes: Vec<ExportedNote>,
+}
+
+/// First line of a jsonl export and content of the tar manifest
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportHeader {
+    pub version: u32,
+}
+
+/// Writes notes in an `ExportFormat` as they are received. Nothing is
+/// complete before `Exporter::finish`
+pub enum Exporter {
+    Markdown {
+        dir: PathBuf,
+        names: HashSet<String>,
+    },
+    Json {
+        writer: Box<dyn Write>,
+        notes: Vec<ExportedNote>,
+    },
+    Jsonl {
+        writer: Box<dyn Write>,
+    },
+    Tar {
+        builder: tar::Builder<Box<dyn Write>>,
+        names: HashSet<String>,
+    },
+}
+
+impl Exporter {
+    /// `output` is a directory for markdown exports, which is required,
+    /// and a file for the others, stdout when unset
+    pub fn new(format: ExportFormat, output: Option<&Path>) -> Result<Self, BppCliError> {
+        if format == ExportFormat::Markdown {
+            let dir = output.ok_or_else(|| {
+                report!(BppCliError::InvalidParameters(
+                    "md exports need an --output directory".to_string()
+                ))
+            })?;
+            fs::create_dir_all(dir).map_err(|err| {
+                report!(err).change_context(BppCliError::FailedToExport(format!(
+                    "Failed to create {}",
+                    dir.display()
+                )))
+            })?;
+            return Ok(Exporter::Markdown {
+                dir: dir.to_path_buf(),
+                names: HashSet::new(),
+            });
+        }
+
+        let mut writer: Box<dyn Write> = match output {
+            Some(path) => Box::new(BufWriter::new(File::create(path).map_err(|err| {
+                report!(err).change_context(BppCliError::FailedToExport(format!(
+                    "Failed to create {}",
+                    path.display()
+                )))
+            })?)),
+            None => Box::new(BufWriter::new(io::stdout())),
+        };
+        let header = ExportHeader {
+            version: EXPORT_VERSION,
+        };
+        Ok(match format {
+            ExportFormat::Json => Exporter::Json {
+                writer,
+                notes: vec![],
+            },
+            ExportFormat::Jsonl => {
+                write_json_line(&mut writer, &header)?;
+                Exporter::Jsonl { writer }
+            }
+            ExportFormat::Tar => {
+                let mut builder = tar::Builder::new(writer);
+                append_tar(&mut builder, MANIFEST_FILE, &to_json(&header)?)?;
+                Exporter::Tar {
+                    builder,
+                    names: HashSet::new(),
+                }
+            }
+            ExportFormat::Markdown => unreachable!("markdown exports are written to a directory"),
+        })
+    }
+
+    pub fn write(&mut self, note: Note) -> Result<(), BppCliError> {
+        match self {
+            Exporter::Markdown { dir, names } => {
+                let path = dir.join(file_name(&note.id, names));
+                // An export never replaces existing files
+                OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)
+                    .and_then(|mut file| file.write_all(markdown(&note).as_bytes()))
+                    .map_err(|err| {
+                        report!(err).change_context(BppCliError::FailedToExport(format!(
+                            "Failed to write {}",
+                            path.display()
+                        )))
+                    })
+            }
+            Exporter::Json { notes, .. } => {
+                notes.push(note.into());
+                Ok(())
+            }
+            Exporter::Jsonl { writer } => write_json_line(writer, &ExportedNote::from(note)),
+            Exporter::Tar { builder, names } => {
+                let path = format!("{TAR_NOTES_DIR}/{}", file_name(&note.id, names));
+                append_tar(builder, &path, markdown(&note).as_bytes())
+            }
+        }
+    }
+
+    pub fn finish(self) -> Result<(), BppCliError> {
+        let mut writer = match self {
+            Exporter::Markdown { .. } => return Ok(()),
+            Exporter::Json { mut writer, notes } => {
+                let document = ExportDocument {
+                    version: EXPORT_VERSION,
+                    notes,
+                };
+                serde_json::to_writer_pretty(&mut writer, &document).map_err(|err| {
+                    report!(err).change_context(BppCliError::FailedToExport(
+                        "Failed to write the export".to_string(),
+                    ))
+                })?;
+                writer
+            }
+            Exporter::Jsonl { writer } => writer,
+            Exporter::Tar { builder, .. } => builder.into_inner().map_err(|err| {
+                report!(err).change_context(BppCliError::FailedToExport(
+                    "Failed to write the archive".to_string(),
+                ))
+            })?,
+        };
+        writer.flush().map_err(|err| {
+            report!(err).change_context(BppCliError::FailedToExport(
+                "Failed to write the export".to_string(),
+            ))
+        })
+    }
+}
+
+/// Front matter followed by the content as is. Strings are quoted as json,
+/// which is valid yaml
+fn markdown(note: &Note) -> String {
+    let quote = |value: &str| serde_json::Value::from(value).to_string();
+    format!(
+        "{FRONT_MATTER_DELIMITER}\n\
+        {VERSION_KEY}: {EXPORT_VERSION}\n\
+        id: {}\n\
+        title: {}\n\
+        encrypted: {}\n\
+        notebook: {}\n\
+        tags: {}\n\
+        created_ms: {}\n\
+        updated_ms: {}\n\
+        {FRONT_MATTER_DELIMITER}\n\
+        {}",
+        quote(&note.id),
+        quote(&note.title),
+        note.encrypted,
+        quote(&note.notebook),
+        serde_json::Value::from(note.tags.clone()),
+        note.created_ms,
+        note.updated_ms,
+        note.content,
+    )
+}
+
+/// `<id>.md`, with characters that are not safe in file names replaced.
+/// The id in the front matter is the one that counts, so a suffix keeps
+/// names unique when replacements collide
+fn file_name(id: &str, names: &mut HashSet<String>) -> String {
+    let stem: String = id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let mut name = format!("{stem}.md");
+    let mut suffix = 1;
+    while !names.insert(name.clone()) {
+        name = format!("{stem}-{suffix}.md");
+        suffix += 1;
+    }
+    name
+}
+
+fn to_json(value: &impl Serialize) -> Result<Vec<u8>, BppCliError> {
+    serde_json::to_vec(value).map_err(|err| {
+        report!(err).change_context(BppCliError::FailedToExport(
+            "Failed to serialize the export".to_string(),
+        ))
+    })
+}
+
+fn write_json_line(writer: &mut impl Write, value: &impl Serialize) -> Result<(), BppCliError> {
+    let mut line = to_json(value)?;
+    line.push(b'\n');
+    writer.write_all(&line).map_err(|err| {
+        report!(err).change_context(BppCliError::FailedToExport(
+            "Failed to write the export".to_string(),
+        ))
+    })
+}
+
+fn append_tar(
+    builder: &mut tar::Builder<Box<dyn Write>>,
+    path: &str,
+    data: &[u8],
+) -> Result<(), BppCliError> {
+    let mtime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(mtime);
+    builder.append_data(&mut header, path, data).map_err(|err| {
+        report!(err).change_context(BppCliError::FailedToExport(format!(
+            "Failed to add {path} to the archive"
+        )))
+    })
+}
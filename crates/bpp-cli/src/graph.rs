@@ -0,0 +1,211 @@
+use crate::error_def::BppCliError;
+use bpp_proto::bpp::{GraphEdge, GraphEdgeKind, GraphNode, GraphNodeKind};
+use error_stack::{report, Result};
+use serde::Serialize;
+use std::io::Write;
+use std::str::FromStr;
+
+/// Formats written by `bpp graph`:
+///  - dot: a Graphviz digraph, eg. `dot -Tsvg notes.dot > notes.svg`
+///  - graphml: for Gephi, yEd, Cytoscape...
+///  - json: `{"nodes": [...], "edges": [...]}`, as used by d3 and others
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    Dot,
+    GraphMl,
+    Json,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> std::result::Result<Self, Self::Err> {
+        match format {
+            "dot" => Ok(GraphFormat::Dot),
+            "graphml" => Ok(GraphFormat::GraphMl),
+            "json" => Ok(GraphFormat::Json),
+            other => Err(format!("unknown format {other:?}")),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct JsonGraph<'a> {
+    nodes: Vec<JsonNode<'a>>,
+    edges: Vec<JsonEdge<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonNode<'a> {
+    id: &'a str,
+    kind: &'static str,
+    title: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    notebook: &'a str,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    tags: &'a [String],
+}
+
+#[derive(Debug, Serialize)]
+struct JsonEdge<'a> {
+    source: &'a str,
+    target: &'a str,
+    kind: &'static str,
+}
+
+/// Write the graph received from the server in `format`
+pub fn write_graph(
+    format: GraphFormat,
+    nodes: &[GraphNode],
+    edges: &[GraphEdge],
+    mut writer: impl Write,
+) -> Result<(), BppCliError> {
+    let document = match format {
+        GraphFormat::Dot => dot(nodes, edges),
+        GraphFormat::GraphMl => graphml(nodes, edges),
+        GraphFormat::Json => {
+            let graph = JsonGraph {
+                nodes: nodes
+                    .iter()
+                    .map(|node| JsonNode {
+                        id: &node.id,
+                        kind: node_kind(node),
+                        title: &node.title,
+                        notebook: &node.notebook,
+                        tags: &node.tags,
+                    })
+                    .collect(),
+                edges: edges
+                    .iter()
+                    .map(|edge| JsonEdge {
+                        source: &edge.source,
+                        target: &edge.target,
+                        kind: edge_kind(edge),
+                    })
+                    .collect(),
+            };
+            let mut json = serde_json::to_string_pretty(&graph).map_err(|err| {
+                report!(err).change_context(BppCliError::FailedToExport(
+                    "Failed to serialize the graph".to_string(),
+                ))
+            })?;
+            json.push('\n');
+            json
+        }
+    };
+    writer
+        .write_all(document.as_bytes())
+        .and_then(|_| writer.flush())
+        .map_err(|err| {
+            report!(err).change_context(BppCliError::FailedToExport(
+                "Failed to write the graph".to_string(),
+            ))
+        })
+}
+
+/// Notes are ellipses labelled with their title, tags are boxes. Tag edges
+/// are dashed and undirected
+fn dot(nodes: &[GraphNode], edges: &[GraphEdge]) -> String {
+    let mut dot = String::from("digraph notes {\n");
+    for node in nodes {
+        let (label, shape) = if node.kind == GraphNodeKind::Tag as i32 {
+            (format!("#{}", node.title), ", shape=box")
+        } else {
+            (node.title.clone(), "")
+        };
+        dot.push_str(&format!(
+            "  {} [label={}{shape}];\n",
+            dot_quote(&node.id),
+            dot_quote(&label)
+        ));
+    }
+    for edge in edges {
+        let style = if edge.kind == GraphEdgeKind::Tag as i32 {
+            " [style=dashed, arrowhead=none]"
+        } else {
+            ""
+        };
+        dot.push_str(&format!(
+            "  {} -> {}{style};\n",
+            dot_quote(&edge.source),
+            dot_quote(&edge.target)
+        ));
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+fn graphml(nodes: &[GraphNode], edges: &[GraphEdge]) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n  \
+        <key id=\"kind\" for=\"node\" attr.name=\"kind\" attr.type=\"string\"/>\n  \
+        <key id=\"title\" for=\"node\" attr.name=\"title\" attr.type=\"string\"/>\n  \
+        <key id=\"notebook\" for=\"node\" attr.name=\"notebook\" attr.type=\"string\"/>\n  \
+        <key id=\"tags\" for=\"node\" attr.name=\"tags\" attr.type=\"string\"/>\n  \
+        <key id=\"edge_kind\" for=\"edge\" attr.name=\"kind\" attr.type=\"string\"/>\n  \
+        <graph id=\"notes\" edgedefault=\"directed\">\n",
+    );
+    for node in nodes {
+        xml.push_str(&format!(
+            "    <node id=\"{}\">\n      \
+            <data key=\"kind\">{}</data>\n      \
+            <data key=\"title\">{}</data>\n",
+            escape(&node.id),
+            node_kind(node),
+            escape(&node.title),
+        ));
+        if !node.notebook.is_empty() {
+            xml.push_str(&format!(
+                "      <data key=\"notebook\">{}</data>\n",
+                escape(&node.notebook)
+            ));
+        }
+        if !node.tags.is_empty() {
+            xml.push_str(&format!(
+                "      <data key=\"tags\">{}</data>\n",
+                escape(&node.tags.join(","))
+            ));
+        }
+        xml.push_str("    </node>\n");
+    }
+    for edge in edges {
+        xml.push_str(&format!(
+            "    <edge source=\"{}\" target=\"{}\">\n      \
+            <data key=\"edge_kind\">{}</data>\n    \
+            </edge>\n",
+            escape(&edge.source),
+            escape(&edge.target),
+            edge_kind(edge),
+        ));
+    }
+    xml.push_str("  </graph>\n</graphml>\n");
+    xml
+}
+
+fn node_kind(node: &GraphNode) -> &'static str {
+    match GraphNodeKind::from_i32(node.kind) {
+        Some(GraphNodeKind::Tag) => "tag",
+        _ => "note",
+    }
+}
+
+fn edge_kind(edge: &GraphEdge) -> &'static str {
+    match GraphEdgeKind::from_i32(edge.kind) {
+        Some(GraphEdgeKind::Tag) => "tag",
+        _ => "link",
+    }
+}
+
+/// A dot double quoted string
+fn dot_quote(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{escaped}\"")
+}
+
+fn escape(text: &str) -> String {
+    quick_xml::escape::escape(text).to_string()
+}
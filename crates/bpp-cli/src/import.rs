@@ -0,0 +1,1008 @@
+use crate::error_def::BppCliError;
+use crate::export::{FRONT_MATTER_DELIMITER, VERSION_KEY};
+use bpp_proto::bpp::Note;
+use bpp_proto::EXPORT_VERSION;
+use error_stack::{report, Result};
+use quick_xml::events::Event;
+use serde_yaml::{Mapping, Value};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use walkdir::{DirEntry, WalkDir};
+
+/// Joplin item types, see `read_joplin`
+const JOPLIN_NOTE: &str = "1";
+const JOPLIN_FOLDER: &str = "2";
+const JOPLIN_TAG: &str = "5";
+const JOPLIN_NOTE_TAG: &str = "6";
+/// Deepest folder nesting followed, guards against parent cycles
+const MAX_NOTEBOOK_DEPTH: usize = 32;
+/// ENML elements rendered on their own lines
+static BLOCK_TAGS: [&str; 16] = [
+    "blockquote",
+    "br",
+    "div",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "li",
+    "ol",
+    "p",
+    "pre",
+    "tr",
+    "ul",
+];
+
+/// Tools `bpp import` reads notes from:
+///  - markdown: a folder of `.md` files. The title is taken from the
+///    front matter, then the first `# heading`, then the file name
+///  - obsidian: a vault, the title is the file name as in Obsidian
+///  - enex: Evernote `.enex` exports, one notebook per file
+///  - joplin: a Joplin raw export directory or `.jex` archive
+///
+/// Folders become notebooks, front matter and `#hashtags` become tags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportSource {
+    Markdown,
+    Obsidian,
+    Enex,
+    Joplin,
+}
+
+impl FromStr for ImportSource {
+    type Err = String;
+
+    fn from_str(source: &str) -> std::result::Result<Self, Self::Err> {
+        match source {
+            "markdown" => Ok(ImportSource::Markdown),
+            "obsidian" => Ok(ImportSource::Obsidian),
+            "enex" => Ok(ImportSource::Enex),
+            "joplin" => Ok(ImportSource::Joplin),
+            other => Err(format!("unknown source {other:?}")),
+        }
+    }
+}
+
+/// A note read from `source`, or why it could not be read
+#[derive(Debug)]
+pub struct ImportItem {
+    pub source: String,
+    pub note: std::result::Result<Note, String>,
+}
+
+impl ImportItem {
+    fn failed(source: impl Into<String>, error: impl ToString) -> Self {
+        ImportItem {
+            source: source.into(),
+            note: Err(error.to_string()),
+        }
+    }
+}
+
+/// Read every note under `path`. Files that cannot be read are reported as
+/// failed items, only an unreadable `path` fails the whole import
+pub fn read_notes(source: ImportSource, path: &Path) -> Result<Vec<ImportItem>, BppCliError> {
+    if !path.exists() {
+        return Err(report!(BppCliError::InvalidParameters(format!(
+            "{} does not exist",
+            path.display()
+        ))));
+    }
+    match source {
+        ImportSource::Markdown | ImportSource::Obsidian => Ok(read_markdown(source, path)),
+        ImportSource::Enex => Ok(read_enex(path)),
+        ImportSource::Joplin => read_joplin(path),
+    }
+}
+
+/// Parse an RFC 3339 time, with or without a time zone, or a bare date
+pub fn parse_date(date: &str) -> std::result::Result<SystemTime, humantime::TimestampError> {
+    let date = date.trim();
+    if date.len() == 10 {
+        humantime::parse_rfc3339_weak(&format!("{date} 00:00:00"))
+    } else {
+        humantime::parse_rfc3339_weak(date)
+    }
+}
+
+fn millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as i64)
+}
+
+/// Files and directories starting with a dot, eg. `.obsidian` or `.trash`
+fn is_hidden(entry: &DirEntry) -> bool {
+    entry.depth() > 0 && entry.file_name().to_string_lossy().starts_with('.')
+}
+
+/// Files under `path` with `extension`, or `path` itself when it is a file
+fn files(path: &Path, extension: &str) -> Vec<std::result::Result<DirEntry, walkdir::Error>> {
+    WalkDir::new(path)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| !is_hidden(entry))
+        .filter(|entry| match entry {
+            Ok(entry) => {
+                entry.file_type().is_file()
+                    && entry
+                        .path()
+                        .extension()
+                        .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+            }
+            Err(_) => true,
+        })
+        .collect()
+}
+
+fn read_markdown(source: ImportSource, path: &Path) -> Vec<ImportItem> {
+    files(path, "md")
+        .into_iter()
+        .map(|entry| {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => return ImportItem::failed(path.display().to_string(), err),
+            };
+            let name = entry
+                .path()
+                .strip_prefix(path)
+                .ok()
+                .filter(|relative| !relative.as_os_str().is_empty())
+                .unwrap_or_else(|| entry.path())
+                .display()
+                .to_string();
+            ImportItem {
+                note: markdown_note(source, path, &entry),
+                source: name,
+            }
+        })
+        .collect()
+}
+
+fn markdown_note(
+    source: ImportSource,
+    root: &Path,
+    entry: &DirEntry,
+) -> std::result::Result<Note, String> {
+    let text = fs::read_to_string(entry.path()).map_err(|err| err.to_string())?;
+    let (front_matter, body) = split_front_matter(&text)?;
+    let field = |key: &str| front_matter.get(key).filter(|value| !value.is_null());
+
+    if let Some(version) = field(VERSION_KEY) {
+        match version.as_u64() {
+            Some(version) if version <= u64::from(EXPORT_VERSION) => {}
+            _ => return Err(format!("unsupported bpp export version {version:?}")),
+        }
+    }
+
+    let stem = entry
+        .path()
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut content = body.to_string();
+    let title = match field("title").and_then(Value::as_str) {
+        Some(title) => title.to_string(),
+        None if source == ImportSource::Markdown => match heading(body) {
+            Some((title, rest)) => {
+                content = rest.to_string();
+                title.to_string()
+            }
+            None => stem,
+        },
+        None => stem,
+    };
+
+    // Folders relative to the imported directory
+    let folder = entry
+        .path()
+        .parent()
+        .and_then(|parent| parent.strip_prefix(root).ok())
+        .map(|parent| {
+            parent
+                .components()
+                .map(|part| part.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/")
+        })
+        .unwrap_or_default();
+    let notebook = field("notebook")
+        .and_then(Value::as_str)
+        .map_or(folder, str::to_string);
+
+    let mut tags = field("tags").map(yaml_tags).unwrap_or_default();
+    tags.extend(hashtags(&content));
+
+    let metadata = entry.metadata().ok();
+    let file_time = |time: Option<SystemTime>| time.map_or(0, millis);
+    let created_ms = yaml_time(field("created_ms").or_else(|| field("created")))?
+        .or(yaml_time(field("date"))?)
+        .unwrap_or_else(|| {
+            file_time(
+                metadata
+                    .as_ref()
+                    .and_then(|metadata| metadata.created().or_else(|_| metadata.modified()).ok()),
+            )
+        });
+    let updated_ms = yaml_time(
+        field("updated_ms")
+            .or_else(|| field("updated"))
+            .or_else(|| field("modified")),
+    )?
+    .unwrap_or_else(|| {
+        file_time(
+            metadata
+                .as_ref()
+                .and_then(|metadata| metadata.modified().ok()),
+        )
+    });
+
+    Ok(Note {
+        id: field("id")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string(),
+        title,
+        content,
+        encrypted: field("encrypted").and_then(Value::as_bool).unwrap_or(false),
+        notebook,
+        tags,
+        created_ms,
+        updated_ms,
+        attachments: vec![],
+    })
+}
+
+/// The yaml between the leading `---` lines and what follows them. Text
+/// without front matter is all body
+fn split_front_matter(text: &str) -> std::result::Result<(Mapping, &str), String> {
+    let first_line = text.lines().next().unwrap_or_default();
+    if first_line.trim_end() != FRONT_MATTER_DELIMITER {
+        return Ok((Mapping::new(), text));
+    }
+    let yaml_start = first_line.len() + 1;
+    let mut offset = yaml_start;
+    for line in text[yaml_start.min(text.len())..].split_inclusive('\n') {
+        if line.trim_end() == FRONT_MATTER_DELIMITER {
+            let front_matter = match &text[yaml_start..offset] {
+                yaml if yaml.trim().is_empty() => Mapping::new(),
+                yaml => serde_yaml::from_str(yaml)
+                    .map_err(|err| format!("invalid front matter: {err}"))?,
+            };
+            return Ok((front_matter, &text[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+    Err("front matter is not closed by a --- line".to_string())
+}
+
+/// A leading `# title` line and the text after it
+fn heading(body: &str) -> Option<(&str, &str)> {
+    let body = body.trim_start_matches(['\n', '\r']);
+    let (line, rest) = body.split_once('\n').unwrap_or((body, ""));
+    let title = line.strip_prefix("# ")?.trim();
+    (!title.is_empty()).then_some((title, rest.trim_start_matches(['\n', '\r'])))
+}
+
+/// Tags given as a list or as a comma or space separated string
+fn yaml_tags(tags: &Value) -> Vec<String> {
+    match tags {
+        Value::Sequence(tags) => tags
+            .iter()
+            .filter_map(|tag| match tag {
+                Value::String(tag) => Some(tag.clone()),
+                Value::Number(tag) => Some(tag.to_string()),
+                _ => None,
+            })
+            .collect(),
+        Value::String(tags) => tags
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|tag| !tag.is_empty())
+            .map(str::to_string)
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Milliseconds since the epoch, or a date as accepted by `parse_date`
+fn yaml_time(time: Option<&Value>) -> std::result::Result<Option<i64>, String> {
+    match time {
+        None => Ok(None),
+        Some(Value::Number(millis)) => Ok(millis.as_i64()),
+        Some(Value::String(date)) => parse_date(date)
+            .map(|time| Some(millis(time)))
+            .map_err(|err| format!("invalid date {date:?}: {err}")),
+        Some(other) => Err(format!("invalid date {other:?}")),
+    }
+}
+
+/// `#tags` in `text`, outside of code blocks. As in Obsidian, tags may
+/// contain `/`, `-` and `_` but not only digits, so `#1` is not a tag
+fn hashtags(text: &str) -> Vec<String> {
+    let mut tags = vec![];
+    let mut in_code = false;
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            continue;
+        }
+        let mut previous = ' ';
+        for (start, c) in line.char_indices() {
+            if c == '#' && previous.is_whitespace() {
+                let tag: String = line[start + 1..]
+                    .chars()
+                    .take_while(|c| c.is_alphanumeric() || ['/', '-', '_'].contains(c))
+                    .collect();
+                if tag.chars().any(|c| !c.is_ascii_digit()) {
+                    tags.push(tag);
+                }
+            }
+            previous = c;
+        }
+    }
+    tags
+}
+
+fn read_enex(path: &Path) -> Vec<ImportItem> {
+    let mut items = vec![];
+    for entry in files(path, "enex") {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                items.push(ImportItem::failed(path.display().to_string(), err));
+                continue;
+            }
+        };
+        let name = entry.file_name().to_string_lossy().to_string();
+        let notebook = entry
+            .path()
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        match fs::read_to_string(entry.path()) {
+            Ok(xml) => items.extend(enex_notes(&name, &notebook, &xml)),
+            Err(err) => items.push(ImportItem::failed(name, err)),
+        }
+    }
+    items
+}
+
+/// Notes of an Evernote export:
+/// `<en-export><note><title/><content/><created/><updated/><tag/>*</note>`
+fn enex_notes(name: &str, notebook: &str, xml: &str) -> Vec<ImportItem> {
+    let mut items = vec![];
+    let mut reader = quick_xml::Reader::from_str(xml);
+    let mut element = String::new();
+    let mut fields: HashMap<String, String> = HashMap::new();
+    let mut tags = vec![];
+    let mut in_note = false;
+    loop {
+        let event = match reader.read_event() {
+            Ok(event) => event,
+            Err(err) => {
+                let position = reader.buffer_position();
+                items.push(ImportItem::failed(
+                    name,
+                    format!("invalid xml at byte {position}: {err}"),
+                ));
+                return items;
+            }
+        };
+        match event {
+            Event::Start(start) => {
+                element = String::from_utf8_lossy(start.name().as_ref()).to_string();
+                if element == "note" {
+                    in_note = true;
+                    fields.clear();
+                    tags.clear();
+                }
+            }
+            Event::Text(text) => {
+                let text = text
+                    .unescape()
+                    .map(|text| text.to_string())
+                    .unwrap_or_else(|_| String::from_utf8_lossy(&text).to_string());
+                if element == "tag" {
+                    tags.push(text);
+                } else {
+                    fields.entry(element.clone()).or_default().push_str(&text);
+                }
+            }
+            Event::CData(data) => {
+                let data = String::from_utf8_lossy(&data.into_inner()).to_string();
+                fields.entry(element.clone()).or_default().push_str(&data);
+            }
+            Event::End(end) => {
+                if end.name().as_ref() == b"note" {
+                    in_note = false;
+                    let number = items.len() + 1;
+                    let title = fields.get("title").cloned().unwrap_or_default();
+                    items.push(ImportItem {
+                        source: format!("{name} #{number} {title:?}"),
+                        note: enex_note(&fields, &tags, notebook),
+                    });
+                }
+                element.clear();
+            }
+            Event::Eof => {
+                if in_note {
+                    items.push(ImportItem::failed(name, "file ends inside a note"));
+                }
+                return items;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn enex_note(
+    fields: &HashMap<String, String>,
+    tags: &[String],
+    notebook: &str,
+) -> std::result::Result<Note, String> {
+    let time = |field: &str| match fields.get(field) {
+        Some(time) => enex_time(time).map_err(|err| format!("invalid {field} {time:?}: {err}")),
+        None => Ok(0),
+    };
+    Ok(Note {
+        id: String::new(),
+        title: fields.get("title").cloned().unwrap_or_default(),
+        content: enml_to_text(fields.get("content").map_or("", String::as_str)),
+        encrypted: false,
+        notebook: notebook.to_string(),
+        tags: tags.to_vec(),
+        created_ms: time("created")?,
+        updated_ms: time("updated")?,
+        attachments: vec![],
+    })
+}
+
+/// Evernote times look like `20230131T120000Z`
+fn enex_time(time: &str) -> std::result::Result<i64, humantime::TimestampError> {
+    let time = time.trim();
+    if time.len() < 15 || !time.is_ascii() {
+        return Err(humantime::TimestampError::InvalidFormat);
+    }
+    let rfc3339 = format!(
+        "{}-{}-{}T{}:{}:{}Z",
+        &time[0..4],
+        &time[4..6],
+        &time[6..8],
+        &time[9..11],
+        &time[11..13],
+        &time[13..15]
+    );
+    humantime::parse_rfc3339(&rfc3339).map(millis)
+}
+
+/// Plain text of an ENML (XHTML) document. Blocks become lines, list items
+/// are prefixed with `- ` and attachments are replaced by a placeholder
+fn enml_to_text(enml: &str) -> String {
+    let mut text = String::new();
+    let mut rest = enml;
+    while let Some(start) = rest.find('<') {
+        text.push_str(&decode_entities(&rest[..start]));
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let tag = rest[start + 1..end].trim();
+        let closing = tag.starts_with('/');
+        let name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '-')
+            .collect::<String>()
+            .to_ascii_lowercase();
+        if BLOCK_TAGS.contains(&name.as_str()) && !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        match name.as_str() {
+            "li" if !closing => text.push_str("- "),
+            "en-todo" => {
+                let checked = tag.contains("checked=\"true\"");
+                text.push_str(if checked { "[x] " } else { "[ ] " });
+            }
+            "en-media" => text.push_str("[attachment]"),
+            _ => {}
+        }
+        rest = &rest[end + 1..];
+    }
+    text.push_str(&decode_entities(rest));
+
+    // At most one empty line between paragraphs
+    let mut collapsed = String::new();
+    let mut blank_lines = 0;
+    for line in text.lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank_lines += 1;
+            if blank_lines > 1 {
+                continue;
+            }
+        } else {
+            blank_lines = 0;
+        }
+        collapsed.push_str(line);
+        collapsed.push('\n');
+    }
+    collapsed.trim().to_string()
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        let entity = rest[start + 1..]
+            .find(';')
+            .filter(|end| *end <= 10)
+            .map(|end| &rest[start + 1..start + 1 + end]);
+        let character = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                .and_then(char::from_u32),
+        });
+        match (entity, character) {
+            (Some(entity), Some(character)) => {
+                decoded.push(character);
+                rest = &rest[start + entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// A Joplin raw export is a directory with one file per item: the title,
+/// a blank line, the body, a blank line and `key: value` metadata lines.
+/// `type_` tells notes, folders, tags and links between notes and tags
+/// apart. A `.jex` file is a tar archive of the same files
+fn read_joplin(path: &Path) -> Result<Vec<ImportItem>, BppCliError> {
+    let mut files = vec![];
+    let mut items = vec![];
+    if path.is_file() {
+        let mut archive = File::open(path).map(tar::Archive::new).map_err(|err| {
+            report!(err).change_context(BppCliError::InvalidParameters(format!(
+                "Failed to open {}",
+                path.display()
+            )))
+        })?;
+        let entries = archive.entries().map_err(|err| {
+            report!(err).change_context(BppCliError::InvalidParameters(format!(
+                "{} is not a Joplin export",
+                path.display()
+            )))
+        })?;
+        for entry in entries {
+            let mut text = String::new();
+            let read = entry.and_then(|mut entry| {
+                let name = entry.path()?.display().to_string();
+                entry.read_to_string(&mut text)?;
+                Ok(name)
+            });
+            match read {
+                Ok(name) if name.ends_with(".md") => files.push((name, text)),
+                Ok(_) => {}
+                Err(err) => items.push(ImportItem::failed(path.display().to_string(), err)),
+            }
+        }
+    } else {
+        for entry in self::files(path, "md") {
+            let read = entry.map_err(|err| err.to_string()).and_then(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                fs::read_to_string(entry.path())
+                    .map(|text| (name, text))
+                    .map_err(|err| err.to_string())
+            });
+            match read {
+                Ok(file) => files.push(file),
+                Err(err) => items.push(ImportItem::failed(path.display().to_string(), err)),
+            }
+        }
+    }
+
+    let parsed: Vec<(String, JoplinItem)> = files
+        .into_iter()
+        .filter_map(|(name, text)| JoplinItem::parse(&text).map(|item| (name, item)))
+        .collect();
+    let of_type = |item_type: &'static str| {
+        parsed
+            .iter()
+            .filter(move |(_, item)| item.get("type_") == Some(item_type))
+    };
+    let folders: HashMap<&str, &JoplinItem> = of_type(JOPLIN_FOLDER)
+        .filter_map(|(_, item)| Some((item.get("id")?, item)))
+        .collect();
+    let tag_titles: HashMap<&str, &str> = of_type(JOPLIN_TAG)
+        .filter_map(|(_, item)| Some((item.get("id")?, item.title.as_str())))
+        .collect();
+    let mut note_tags: HashMap<&str, Vec<String>> = HashMap::new();
+    for (_, link) in of_type(JOPLIN_NOTE_TAG) {
+        if let (Some(note_id), Some(tag)) = (
+            link.get("note_id"),
+            link.get("tag_id").and_then(|id| tag_titles.get(id)),
+        ) {
+            note_tags.entry(note_id).or_default().push(tag.to_string());
+        }
+    }
+
+    for (name, item) in of_type(JOPLIN_NOTE) {
+        let id = item.get("id").unwrap_or_default();
+        let note = if item.get("encryption_applied") == Some("1") {
+            Err("encrypted by Joplin, disable encryption before exporting".to_string())
+        } else {
+            item.to_note(&folders, note_tags.remove(id).unwrap_or_default())
+        };
+        items.push(ImportItem {
+            source: format!("{name} {:?}", item.title),
+            note,
+        });
+    }
+    Ok(items)
+}
+
+#[derive(Debug)]
+struct JoplinItem {
+    title: String,
+    body: String,
+    metadata: HashMap<String, String>,
+}
+
+impl JoplinItem {
+    /// `None` for files that are not Joplin items
+    fn parse(text: &str) -> Option<Self> {
+        let lines: Vec<&str> = text.trim_end().lines().collect();
+        let is_metadata = |line: &str| {
+            line.split_once(':').is_some_and(|(key, _)| {
+                !key.is_empty() && key.chars().all(|c| c.is_ascii_lowercase() || c == '_')
+            })
+        };
+        let metadata_start = lines
+            .iter()
+            .rposition(|line| !is_metadata(line))
+            .map_or(0, |position| position + 1);
+        let metadata: HashMap<String, String> = lines[metadata_start..]
+            .iter()
+            .filter_map(|line| line.split_once(':'))
+            .map(|(key, value)| (key.to_string(), value.trim().to_string()))
+            .collect();
+        if !metadata.contains_key("type_") {
+            return None;
+        }
+
+        // Title and body are separated by a blank line, and the body from
+        // the metadata by another
+        let text = lines[..metadata_start].join("\n");
+        let (title, body) = text.split_once("\n\n").unwrap_or((&text, ""));
+        Some(JoplinItem {
+            title: title.trim().to_string(),
+            body: body.trim_end().to_string(),
+            metadata,
+        })
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.metadata
+            .get(key)
+            .map(String::as_str)
+            .filter(|value| !value.is_empty())
+    }
+
+    fn time(&self, keys: [&str; 2]) -> std::result::Result<i64, String> {
+        match keys.iter().find_map(|key| self.get(key)) {
+            Some(time) => parse_date(time)
+                .map(millis)
+                .map_err(|err| format!("invalid date {time:?}: {err}")),
+            None => Ok(0),
+        }
+    }
+
+    fn to_note(
+        &self,
+        folders: &HashMap<&str, &JoplinItem>,
+        tags: Vec<String>,
+    ) -> std::result::Result<Note, String> {
+        let mut notebook = vec![];
+        let mut parent = self.get("parent_id");
+        while let Some(folder) = parent.and_then(|id| folders.get(id)) {
+            if notebook.len() == MAX_NOTEBOOK_DEPTH {
+                break;
+            }
+            notebook.push(folder.title.as_str());
+            parent = folder.get("parent_id");
+        }
+        notebook.reverse();
+
+        Ok(Note {
+            id: String::new(),
+            title: self.title.clone(),
+            content: self.body.clone(),
+            encrypted: false,
+            notebook: notebook.join("/"),
+            tags,
+            created_ms: self.time(["user_created_time", "created_time"])?,
+            updated_ms: self.time(["user_updated_time", "updated_time"])?,
+            attachments: vec![],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, name: &str, text: &str) {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+
+    fn imported(source: ImportSource, path: &Path) -> Vec<(String, Note)> {
+        read_notes(source, path)
+            .unwrap()
+            .into_iter()
+            .map(|item| (item.source, item.note.unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn markdown_title_from_front_matter_heading_or_file_name() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            "a.md",
+            "---\ntitle: From front matter\n---\n# Heading\nbody",
+        );
+        write(dir.path(), "b.md", "\n# Heading\n\nbody #tag");
+        write(dir.path(), "work/c.md", "no heading");
+
+        let notes = imported(ImportSource::Markdown, dir.path());
+        let summary: Vec<_> = notes
+            .iter()
+            .map(|(source, note)| {
+                (
+                    source.as_str(),
+                    note.title.as_str(),
+                    note.content.as_str(),
+                    note.notebook.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("a.md", "From front matter", "# Heading\nbody", ""),
+                ("b.md", "Heading", "body #tag", ""),
+                ("work/c.md", "c", "no heading", "work"),
+            ]
+        );
+        assert_eq!(notes[1].1.tags, ["tag"]);
+    }
+
+    #[test]
+    fn obsidian_titles_are_file_names_and_hidden_folders_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "Daily.md", "# Heading\nbody");
+        write(dir.path(), ".obsidian/plugin.md", "skipped");
+
+        let notes = imported(ImportSource::Obsidian, dir.path());
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].1.title, "Daily");
+        assert_eq!(notes[0].1.content, "# Heading\nbody");
+    }
+
+    #[test]
+    fn front_matter_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            "note.md",
+            "---\n\
+             id: 0f8fad5b-d9cb-469f-a165-70867728950e\n\
+             notebook: work/projects\n\
+             tags: [rust, 2023]\n\
+             created: 2023-01-31\n\
+             updated_ms: 1675166400000\n\
+             encrypted: true\n\
+             ---\n\
+             body",
+        );
+
+        let (_, note) = imported(ImportSource::Markdown, dir.path()).remove(0);
+        assert_eq!(note.id, "0f8fad5b-d9cb-469f-a165-70867728950e");
+        assert_eq!(note.notebook, "work/projects");
+        assert_eq!(note.tags, ["rust", "2023"]);
+        assert_eq!(note.created_ms, 1_675_123_200_000);
+        assert_eq!(note.updated_ms, 1_675_166_400_000);
+        assert!(note.encrypted);
+        assert_eq!(note.content, "body");
+    }
+
+    #[test]
+    fn invalid_files_are_reported_and_the_rest_imported() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.md", "---\ntitle: not closed\n");
+        write(dir.path(), "b.md", "---\nbpp_export_version: 999\n---\n");
+        write(dir.path(), "c.md", "---\ncreated: yesterday\n---\n");
+        write(dir.path(), "d.md", "fine");
+
+        let items = read_notes(ImportSource::Markdown, dir.path()).unwrap();
+        let errors: Vec<_> = items
+            .iter()
+            .map(|item| item.note.as_ref().err().map(String::as_str))
+            .collect();
+        assert_eq!(errors.len(), 4);
+        assert_eq!(errors[0], Some("front matter is not closed by a --- line"));
+        assert_eq!(
+            errors[1],
+            Some("unsupported bpp export version Number(999)")
+        );
+        assert!(errors[2].unwrap().starts_with("invalid date \"yesterday\""));
+        assert_eq!(errors[3], None);
+    }
+
+    #[test]
+    fn missing_path_fails_the_import() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(read_notes(ImportSource::Markdown, &dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn split_front_matter_without_front_matter() {
+        let (front_matter, body) = split_front_matter("title: no\n---\nbody").unwrap();
+        assert!(front_matter.is_empty());
+        assert_eq!(body, "title: no\n---\nbody");
+
+        let (front_matter, body) = split_front_matter("---\n---\nbody").unwrap();
+        assert!(front_matter.is_empty());
+        assert_eq!(body, "body");
+    }
+
+    #[test]
+    fn yaml_tags_as_list_or_string() {
+        let tags = |yaml: &str| yaml_tags(&serde_yaml::from_str(yaml).unwrap());
+        assert_eq!(tags("[a, 1, {b: c}]"), ["a", "1"]);
+        assert_eq!(tags("a, b c"), ["a", "b", "c"]);
+        assert!(tags("true").is_empty());
+    }
+
+    #[test]
+    fn hashtags_outside_code_blocks() {
+        let text = "#start and #with/slash-dash_underscore, not a#b\n\
+                    #1 is a number, #2023a is not\n\
+                    ```\n#code\n```\n\
+                    #after";
+        assert_eq!(
+            hashtags(text),
+            ["start", "with/slash-dash_underscore", "2023a", "after"]
+        );
+    }
+
+    #[test]
+    fn enex_notes_with_tags_times_and_content() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<en-export>
+  <note>
+    <title>Groceries &amp; more</title>
+    <content><![CDATA[<en-note><div>Buy:</div><ul><li>milk</li><li>eggs &lt;12&gt;</li></ul><en-todo checked="true"/>done<br/><en-media hash="ab"/></en-note>]]></content>
+    <created>20230131T120000Z</created>
+    <updated>20230201T080000Z</updated>
+    <tag>home</tag>
+    <tag>shopping</tag>
+  </note>
+  <note>
+    <title>Bad time</title>
+    <created>yesterday</created>
+  </note>
+</en-export>"#;
+        let mut items = enex_notes("home.enex", "home", xml);
+        assert_eq!(items.len(), 2);
+
+        let item = items.remove(0);
+        assert_eq!(item.source, "home.enex #1 \"Groceries & more\"");
+        let note = item.note.unwrap();
+        assert_eq!(note.title, "Groceries & more");
+        assert_eq!(
+            note.content,
+            "Buy:\n- milk\n- eggs <12>\n[x] done\n[attachment]"
+        );
+        assert_eq!(note.notebook, "home");
+        assert_eq!(note.tags, ["home", "shopping"]);
+        assert_eq!(note.created_ms, 1_675_166_400_000);
+        assert_eq!(note.updated_ms, 1_675_238_400_000);
+
+        let error = items.remove(0).note.unwrap_err();
+        assert!(
+            error.starts_with("invalid created \"yesterday\""),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn enex_truncated_file() {
+        let items = enex_notes("cut.enex", "cut", "<en-export><note><title>cut");
+        assert_eq!(items.len(), 1);
+        assert_eq!(
+            items[0].note.as_ref().unwrap_err(),
+            "file ends inside a note"
+        );
+    }
+
+    #[test]
+    fn entities() {
+        assert_eq!(
+            decode_entities("a&nbsp;&#65;&#x42;&unknown; & b&amp;"),
+            "a AB&unknown; & b&"
+        );
+    }
+
+    #[test]
+    fn joplin_notes_with_folders_and_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "f1.md", "Work\n\nid: f1\nparent_id: \ntype_: 2");
+        write(
+            dir.path(),
+            "f2.md",
+            "Projects\n\nid: f2\nparent_id: f1\ntype_: 2",
+        );
+        write(
+            dir.path(),
+            "n1.md",
+            "Plan\n\nFirst line\n\nSecond: line\n\nid: n1\nparent_id: f2\n\
+             created_time: 2023-01-31T12:00:00.000Z\n\
+             user_updated_time: 2023-02-01T08:00:00.000Z\n\
+             updated_time: 2023-03-01T08:00:00.000Z\ntype_: 1",
+        );
+        write(
+            dir.path(),
+            "n2.md",
+            "Secret\n\nid: n2\nencryption_applied: 1\ntype_: 1",
+        );
+        write(dir.path(), "t1.md", "rust\n\nid: t1\ntype_: 5");
+        write(
+            dir.path(),
+            "nt.md",
+            "id: nt\nnote_id: n1\ntag_id: t1\ntype_: 6",
+        );
+        write(dir.path(), "readme.md", "Not a Joplin item");
+
+        let items = read_notes(ImportSource::Joplin, dir.path()).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].source, "n1.md \"Plan\"");
+        let note = items[0].note.as_ref().unwrap();
+        assert_eq!(note.title, "Plan");
+        assert_eq!(note.content, "First line\n\nSecond: line");
+        assert_eq!(note.notebook, "Work/Projects");
+        assert_eq!(note.tags, ["rust"]);
+        assert_eq!(note.created_ms, 1_675_166_400_000);
+        assert_eq!(note.updated_ms, 1_675_238_400_000);
+        assert!(items[1]
+            .note
+            .as_ref()
+            .unwrap_err()
+            .starts_with("encrypted by Joplin"));
+    }
+}
@@ -1,29 +1,146 @@
+mod attachment;
+mod e2e;
 mod error_def;
+mod export;
+mod graph;
+mod import;
+mod template;
 
+use crate::attachment::Download;
+use crate::e2e::NoteKey;
 use crate::error_def::BppCliError;
+use crate::export::{ExportFormat, Exporter};
+use crate::graph::GraphFormat;
+use crate::import::{ImportItem, ImportSource};
+use crate::template::TemplateVar;
 use bpp_proto::bpp::api_client::ApiClient;
-use bpp_proto::bpp::{AddRequest, RmRequest, SearchRequest};
-use error_stack::{report, Result};
-use std::fs;
+use bpp_proto::bpp::graph_response::Item;
+use bpp_proto::bpp::{
+    AddRequest, AttachmentsRequest, AuditAction, AuditEvent, AuditLogRequest, BacklinksRequest,
+    CreateTemplateRequest, DeleteTemplateRequest, ExportRequest, FetchRequest, GetTemplateRequest,
+    GraphRequest, ImportRequest, ImportStatus, LinksRequest, ListTemplatesRequest, Note, RmRequest,
+    SearchRequest, SearchResponse, Template, UpdateTemplateRequest,
+};
+use bpp_proto::{ACTOR_KEY, REQUEST_ID_KEY, RETRY_AFTER_KEY};
+use error_stack::{report, Report, Result};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::future::Future;
+use std::io::{self, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use structopt::StructOpt;
+use tonic::codegen::InterceptedService;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::Interceptor;
 use tonic::transport::Channel;
-use tonic::Request;
+use tonic::{Request, Response, Status};
+use tracing::{debug, warn};
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::fmt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 use uuid::Uuid;
 
+type BppClient = ApiClient<InterceptedService<Channel, ClientMetadata>>;
+
+/// Attempts made for an rpc the server asks to retry later
+const MAX_ATTEMPTS: u32 = 5;
+static BASE_BACKOFF: Duration = Duration::from_millis(500);
+static MAX_BACKOFF: Duration = Duration::from_secs(30);
+
 /// Command to interact with BreadPaper notes
 ///
 /// bpp add -title "this is a title" --content "this is a content"
 /// bpp add --edit
+/// bpp add --encrypt --title "secret" --content "only I can read this"
+/// bpp add --template standup [--var "Blockers=none"]
+///
+/// bpp template create standup --title "Standup {{date}}" --content "{{Yesterday}}"
+/// bpp template list
 ///
 /// bpp rm --id 1
 ///
-/// bpp search [--all] "is a"
+/// bpp show --id 1
+///
+/// bpp attach 1 ~/scan.pdf [--name invoice.pdf]
+/// bpp attachments 1
+/// bpp fetch 1 invoice.pdf [--output ~/invoice.pdf]
+///
+/// bpp search [--all] [--attachments] "is a"
+///
+/// bpp keygen
+///
+/// bpp export --format md --output ~/notes-export [--all] [--query "is a"]
+///
+/// bpp import ~/vault --from obsidian [--dry-run]
+///
+/// bpp graph --format dot [--notebook work] [--tag rust] | dot -Tsvg > notes.svg
+///
+/// bpp audit [--note-id 1] [--action rm] [--since 2h]
+///
+/// bpp -vv search "is a"
+///
+/// bpp --server notes.lan:8085 search "is a"
 #[derive(StructOpt, Debug)]
 pub struct BppCli {
+    #[structopt(
+        long,
+        short = "v",
+        parse(from_occurrences),
+        long_help = "Print diagnostics to stderr. Repeat for more details (-v, -vv, -vvv)"
+    )]
+    verbose: u8,
+
+    #[structopt(
+        long,
+        env = "BPP_SERVER",
+        default_value = "[::1]:8085",
+        long_help = "Address of the server, eg. notes.lan:8085 or http://[::1]:9000"
+    )]
+    server: String,
+
     #[structopt(subcommand)]
     subcommands: SubCommands,
 }
 
+/// Adds the id of this invocation to the metadata of every request, so
+/// server logs can be matched with the client ones, and the local user
+/// name, recorded as the actor in the audit log
+#[derive(Clone)]
+struct ClientMetadata {
+    request_id: MetadataValue<Ascii>,
+    actor: Option<MetadataValue<Ascii>>,
+}
+
+impl ClientMetadata {
+    fn new(request_id: &str) -> Self {
+        let actor = env::var("USER")
+            .or_else(|_| env::var("LOGNAME"))
+            .ok()
+            .and_then(|user| MetadataValue::try_from(user.as_str()).ok());
+        ClientMetadata {
+            request_id: MetadataValue::try_from(request_id)
+                .expect("uuids are valid metadata values"),
+            actor,
+        }
+    }
+}
+
+impl Interceptor for ClientMetadata {
+    fn call(&mut self, mut request: Request<()>) -> std::result::Result<Request<()>, Status> {
+        let metadata = request.metadata_mut();
+        metadata.insert(REQUEST_ID_KEY, self.request_id.clone());
+        if let Some(actor) = &self.actor {
+            metadata.insert(ACTOR_KEY, actor.clone());
+        }
+        Ok(request)
+    }
+}
+
 #[derive(StructOpt, Debug)]
 enum SubCommands {
     #[structopt(about = "Add new note")]
@@ -32,6 +149,26 @@ enum SubCommands {
     Rm(RmOpts),
     #[structopt(about = "Search for note")]
     Search(SearchOpts),
+    #[structopt(about = "Show a note with its links and the notes linking to it")]
+    Show(ShowOpts),
+    #[structopt(about = "Attach a file to a note")]
+    Attach(AttachOpts),
+    #[structopt(about = "List the files attached to a note")]
+    Attachments(AttachmentsOpts),
+    #[structopt(about = "Download a file attached to a note")]
+    Fetch(FetchOpts),
+    #[structopt(about = "Generate the key used for encrypted notes")]
+    Keygen(KeygenOpts),
+    #[structopt(about = "Export notes as markdown, json, jsonl or a tar archive")]
+    Export(ExportOpts),
+    #[structopt(about = "Import notes from markdown, Obsidian, Evernote or Joplin")]
+    Import(ImportOpts),
+    #[structopt(about = "Export the graph of links and tags between notes")]
+    Graph(GraphOpts),
+    #[structopt(about = "Show the audit log of note changes")]
+    Audit(AuditOpts),
+    #[structopt(about = "Manage the templates notes can be added from")]
+    Template(TemplateCommands),
 }
 
 #[derive(StructOpt, Debug)]
@@ -40,8 +177,8 @@ struct AddOpts {
     long,
     short = "t",
     long_help = "Note Title",
-    required_unless_one(&["edit"]),
-    conflicts_with_all(&["edit"]),
+    required_unless_one(&["edit", "template"]),
+    conflicts_with_all(&["edit", "template"]),
     )]
     title: Option<String>,
 
@@ -49,8 +186,8 @@ struct AddOpts {
     long,
     short = "c",
     long_help = "Note Content",
-    required_unless_one(&["edit"]),
-    conflicts_with_all(&["edit"]),
+    required_unless_one(&["edit", "template"]),
+    conflicts_with_all(&["edit", "template"]),
     )]
     content: Option<String>,
 
@@ -58,10 +195,89 @@ struct AddOpts {
     long,
     short = "e",
     long_help = "Edit using VIM",
-    required_unless_one(&["title", "content"]),
-    conflicts_with_all(&["title", "content"]),
+    required_unless_one(&["title", "content", "template"]),
+    conflicts_with_all(&["title", "content", "template"]),
     )]
     edit: bool,
+
+    #[structopt(
+    long,
+    short = "T",
+    long_help = "Start from this template, rendered then opened in VIM. \
+    Placeholders other than {{date}}, {{time}} and {{user}} are prompted for",
+    required_unless_one(&["title", "content", "edit"]),
+    conflicts_with_all(&["title", "content", "edit"]),
+    )]
+    template: Option<String>,
+
+    #[structopt(
+        long,
+        requires("template"),
+        number_of_values(1),
+        long_help = "Value of a placeholder of the template, as NAME=VALUE, \
+        so it is not prompted for. Can be repeated"
+    )]
+    var: Vec<TemplateVar>,
+
+    #[structopt(
+        long,
+        short = "x",
+        long_help = "Encrypt the content with the local key before sending it to the server. \
+        Only the title remains searchable"
+    )]
+    encrypt: bool,
+}
+
+#[derive(StructOpt, Debug)]
+enum TemplateCommands {
+    #[structopt(about = "List the templates")]
+    List,
+    #[structopt(about = "Show a template and the values it prompts for")]
+    Show(TemplateNameOpts),
+    #[structopt(about = "Create a template")]
+    Create(TemplateOpts),
+    #[structopt(about = "Change the title, content or description of a template")]
+    Update(TemplateOpts),
+    #[structopt(about = "Remove a template")]
+    Rm(TemplateNameOpts),
+}
+
+#[derive(StructOpt, Debug)]
+struct TemplateNameOpts {
+    #[structopt(long_help = "Name of the template")]
+    name: String,
+}
+
+#[derive(StructOpt, Debug)]
+struct TemplateOpts {
+    #[structopt(long_help = "Name of the template: lowercase letters, digits, - and _")]
+    name: String,
+
+    #[structopt(
+        long,
+        short = "t",
+        long_help = "Title of the notes added from the template"
+    )]
+    title: Option<String>,
+
+    #[structopt(
+        long,
+        short = "c",
+        long_help = "Content of the notes added from the template"
+    )]
+    content: Option<String>,
+
+    #[structopt(
+        long,
+        short = "f",
+        parse(from_os_str),
+        conflicts_with_all(&["title", "content"]),
+        long_help = "Read the title and content from this file, the first line is the title"
+    )]
+    file: Option<PathBuf>,
+
+    #[structopt(long, short = "d", long_help = "What the template is for")]
+    description: Option<String>,
 }
 
 #[derive(StructOpt, Debug)]
@@ -75,98 +291,381 @@ struct SearchOpts {
     #[structopt(long, short = "a", long_help = "Search for both title and content")]
     all: bool,
 
+    #[structopt(
+        long,
+        short = "f",
+        long_help = "Search the text of attached files too: plain text, markdown, html and pdf"
+    )]
+    attachments: bool,
+
     #[structopt()]
     query: String,
 }
 
+#[derive(StructOpt, Debug)]
+struct ShowOpts {
+    #[structopt(long, short = "i", long_help = "Id of the note to show")]
+    id: String,
+}
+
+#[derive(StructOpt, Debug)]
+struct AttachOpts {
+    #[structopt(long_help = "Id of the note to attach the file to")]
+    id: String,
+
+    #[structopt(parse(from_os_str), long_help = "File to attach")]
+    file: PathBuf,
+
+    #[structopt(
+        long,
+        short = "n",
+        long_help = "Name of the attachment, the file name by default. \
+        Replaces the attachment of the note with the same name"
+    )]
+    name: Option<String>,
+
+    #[structopt(
+        long,
+        short = "m",
+        long_help = "Media type of the file, guessed from its extension by default"
+    )]
+    media_type: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+struct AttachmentsOpts {
+    #[structopt(long_help = "Id of the note")]
+    id: String,
+}
+
+#[derive(StructOpt, Debug)]
+struct FetchOpts {
+    #[structopt(long_help = "Id of the note")]
+    id: String,
+
+    #[structopt(long_help = "Name or digest of the attachment")]
+    name: String,
+
+    #[structopt(
+        long,
+        short = "o",
+        parse(from_os_str),
+        long_help = "File to write, the name of the attachment in the current directory \
+        by default. Existing files are never overwritten"
+    )]
+    output: Option<PathBuf>,
+}
+
+#[derive(StructOpt, Debug)]
+struct KeygenOpts {
+    #[structopt(
+        long,
+        short = "f",
+        long_help = "Overwrite an existing key. Notes encrypted with it can no longer be read"
+    )]
+    force: bool,
+}
+
+#[derive(StructOpt, Debug)]
+struct ExportOpts {
+    #[structopt(
+        long,
+        short = "f",
+        default_value = "json",
+        possible_values(&["md", "json", "jsonl", "tar"]),
+        long_help = "md: one markdown file per note, with the id and title in a front matter. \
+        json: a single document, as written by `bpp-server export`. \
+        jsonl: one note per line. tar: the markdown files in an archive"
+    )]
+    format: ExportFormat,
+
+    #[structopt(
+        long,
+        short = "o",
+        parse(from_os_str),
+        required_if("format", "md"),
+        long_help = "File to write, stdout by default. Directory to write the files to for md"
+    )]
+    output: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        short = "q",
+        long_help = "Only export notes matching this query, as `bpp search` does"
+    )]
+    query: Option<String>,
+
+    #[structopt(long, short = "a", long_help = "Match the query against contents too")]
+    all: bool,
+
+    #[structopt(
+        long,
+        long_help = "Match the query against the text of attached files too"
+    )]
+    attachments: bool,
+}
+
+#[derive(StructOpt, Debug)]
+struct ImportOpts {
+    #[structopt(
+        parse(from_os_str),
+        long_help = "Directory or file to import: a markdown folder, an Obsidian vault, \
+        .enex files or a Joplin raw export directory or .jex archive"
+    )]
+    path: PathBuf,
+
+    #[structopt(
+        long,
+        short = "f",
+        default_value = "markdown",
+        possible_values(&["markdown", "obsidian", "enex", "joplin"]),
+        long_help = "Tool the notes come from. Folders become notebooks, front matter \
+        tags and #hashtags become tags"
+    )]
+    from: ImportSource,
+
+    #[structopt(
+        long,
+        long_help = "Report what would be imported without storing anything"
+    )]
+    dry_run: bool,
+}
+
+#[derive(StructOpt, Debug)]
+struct GraphOpts {
+    #[structopt(
+        long,
+        short = "f",
+        default_value = "dot",
+        possible_values(&["dot", "graphml", "json"]),
+        long_help = "dot: for Graphviz. graphml: for Gephi, yEd or Cytoscape. \
+        json: a list of nodes and a list of edges"
+    )]
+    format: GraphFormat,
+
+    #[structopt(
+        long,
+        short = "o",
+        parse(from_os_str),
+        long_help = "File to write, stdout by default"
+    )]
+    output: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        short = "n",
+        long_help = "Only include notes filed in this notebook or one below it"
+    )]
+    notebook: Option<String>,
+
+    #[structopt(long, short = "t", long_help = "Only include notes with this tag")]
+    tag: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+struct AuditOpts {
+    #[structopt(long, short = "i", long_help = "Only show events of this note")]
+    note_id: Option<String>,
+
+    #[structopt(
+        long,
+        short = "a",
+        possible_values(&["add", "rm", "import", "restore", "attach"]),
+        long_help = "Only show events of this action"
+    )]
+    action: Option<ActionFilter>,
+
+    #[structopt(long, short = "u", long_help = "Only show events of this user")]
+    actor: Option<String>,
+
+    #[structopt(
+        long,
+        short = "s",
+        long_help = "Only show events after this time, either a duration ago (\"2h\", \"3days\") \
+        or a date (\"2024-01-31\", \"2024-01-31T12:00:00Z\")"
+    )]
+    since: Option<TimeFilter>,
+
+    #[structopt(
+        long,
+        long_help = "Only show events before this time, in the same format as --since"
+    )]
+    until: Option<TimeFilter>,
+
+    #[structopt(
+        long,
+        short = "n",
+        default_value = "100",
+        long_help = "Show at most this many of the most recent events"
+    )]
+    limit: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ActionFilter(AuditAction);
+
+impl FromStr for ActionFilter {
+    type Err = String;
+
+    fn from_str(action: &str) -> std::result::Result<Self, Self::Err> {
+        match action {
+            "add" => Ok(ActionFilter(AuditAction::Add)),
+            "rm" => Ok(ActionFilter(AuditAction::Rm)),
+            "import" => Ok(ActionFilter(AuditAction::Import)),
+            "restore" => Ok(ActionFilter(AuditAction::Restore)),
+            "attach" => Ok(ActionFilter(AuditAction::Attach)),
+            other => Err(format!("unknown action {other:?}")),
+        }
+    }
+}
+
+/// Point in time given as a duration ago or a date, in milliseconds since
+/// the unix epoch
+#[derive(Debug, Clone, Copy)]
+struct TimeFilter(i64);
+
+impl FromStr for TimeFilter {
+    type Err = String;
+
+    fn from_str(time: &str) -> std::result::Result<Self, Self::Err> {
+        let time = match humantime::parse_duration(time) {
+            Ok(ago) => SystemTime::now()
+                .checked_sub(ago)
+                .ok_or_else(|| format!("{time:?} is too far in the past"))?,
+            // A bare date means its start
+            Err(_) => {
+                import::parse_date(time).map_err(|err| format!("invalid time {time:?}: {err}"))?
+            }
+        };
+        let millis = time
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| format!("{time:?} is before 1970"))?
+            .as_millis();
+        Ok(TimeFilter(millis as i64))
+    }
+}
+
 impl BppCli {
     pub async fn run(&self) -> Result<i32, BppCliError> {
-        let port = "8085";
-        let mut client = ApiClient::connect(format!("http://[::1]:{port}"))
+        if let SubCommands::Keygen(keygen_opts) = &self.subcommands {
+            return Self::handle_keygen(keygen_opts.force);
+        }
+
+        let server = match self.server.contains("://") {
+            true => self.server.clone(),
+            false => format!("http://{}", self.server),
+        };
+        let channel = Channel::from_shared(server.clone())
+            .map_err(|err| {
+                report!(err).change_context(BppCliError::FailedToConnect(format!(
+                    "Invalid server address {server}"
+                )))
+            })?
+            .connect()
             .await
             .map_err(|err| {
                 report!(err).change_context(BppCliError::FailedToConnect(format!(
-                    "Failed to connect to port {port}"
+                    "Failed to connect to {server}"
                 )))
             })?;
 
+        let request_id = Uuid::new_v4().to_string();
+        debug!(%request_id, "Connected to {server}");
+        let client = ApiClient::with_interceptor(channel, ClientMetadata::new(&request_id));
+
         match &self.subcommands {
             SubCommands::Add(add_opts) => {
-                let mut title_out = Some("".to_string());
-                let mut content_out = Some("".to_string());
-
-                if add_opts.edit {
-                    let tmp_file = format!("/tmp/note_{:}.bpp", Uuid::new_v4());
-                    let cmd = format!("vim {tmp_file:}");
-                    std::process::Command::new("/bin/sh")
-                        .arg("-c")
-                        .arg(&cmd)
-                        .spawn()
-                        .map_err(|err| report!(err).change_context(BppCliError::FailedToAddNote))?
-                        .wait()
-                        .map_err(|err| report!(err).change_context(BppCliError::FailedToAddNote))?;
-                    // Read tmp file
-                    let user_text = fs::read_to_string(tmp_file)
-                        .map_err(|err| report!(err).change_context(BppCliError::FailedToAddNote))?;
-
-                    if let Some((title, content)) = user_text.split_once('\n') {
-                        title_out = Some(title.to_string());
-                        content_out = Some(content.to_string());
-                    } else {
-                        println!(
-                            "Invalid text: More than one line required. (First line is the title)"
-                        )
-                    }
+                // Title and content are validated by the server
+                let (title, content) = if add_opts.edit {
+                    edit_note("")?
+                } else if let Some(name) = &add_opts.template {
+                    let template = Self::fetch_template(&client, name).await?;
+                    debug!(name, "Rendering template");
+                    edit_note(&template::render(&template, &add_opts.var)?)?
                 } else {
-                    title_out = add_opts.title.clone();
-                    content_out = add_opts.content.clone();
-                    println!("Add with opts {:?}", add_opts)
-                }
+                    debug!(title = ?add_opts.title, encrypt = add_opts.encrypt, "Adding note");
+                    (
+                        add_opts.title.clone().unwrap_or_default(),
+                        add_opts.content.clone().unwrap_or_default(),
+                    )
+                };
 
-                if title_out.is_some() && content_out.is_some() {
-                    Self::handle_add(&mut client, &title_out.unwrap(), &content_out.unwrap()).await
-                } else {
-                    Err(report!(BppCliError::InvalidParameters(
-                        "Title and Content cannot be empty".to_string()
-                    )))
-                }
+                Self::handle_add(&client, &title, &content, add_opts.encrypt).await
             }
-            SubCommands::Rm(rm_opts) => Self::handle_rm(&mut client, &rm_opts.id).await,
-            SubCommands::Search(search_opts) => {
-                Self::handle_search(&mut client, &search_opts.query, search_opts.all).await
+            SubCommands::Rm(rm_opts) => Self::handle_rm(&client, &rm_opts.id).await,
+            SubCommands::Search(search_opts) => Self::handle_search(&client, search_opts).await,
+            SubCommands::Show(show_opts) => Self::handle_show(&client, &show_opts.id).await,
+            SubCommands::Attach(attach_opts) => Self::handle_attach(&client, attach_opts).await,
+            SubCommands::Attachments(attachments_opts) => {
+                Self::handle_attachments(&client, &attachments_opts.id).await
             }
+            SubCommands::Fetch(fetch_opts) => Self::handle_fetch(&client, fetch_opts).await,
+            SubCommands::Export(export_opts) => Self::handle_export(&client, export_opts).await,
+            SubCommands::Import(import_opts) => Self::handle_import(&client, import_opts).await,
+            SubCommands::Graph(graph_opts) => Self::handle_graph(&client, graph_opts).await,
+            SubCommands::Audit(audit_opts) => Self::handle_audit(&client, audit_opts).await,
+            SubCommands::Template(command) => Self::handle_template(&client, command).await,
+            SubCommands::Keygen(_) => unreachable!("keygen does not require a connection"),
         }
     }
 
+    fn handle_keygen(force: bool) -> Result<i32, BppCliError> {
+        let path = NoteKey::generate(force)?;
+        println!("Key written to {:}", path.display());
+        Ok(0)
+    }
+
     async fn handle_add(
-        client: &mut ApiClient<Channel>,
-        title: &String,
-        content: &String,
+        client: &BppClient,
+        title: &str,
+        content: &str,
+        encrypt: bool,
     ) -> Result<i32, BppCliError> {
-        let request = Request::new(AddRequest {
-            title: title.clone(),
-            content: content.clone(),
-        });
+        // Encrypted contents are bound to the id of their note, so it is
+        // chosen here rather than by the server
+        let (id, content) = if encrypt {
+            let id = Uuid::new_v4().to_string();
+            let content = NoteKey::load()?.encrypt(&id, content)?;
+            (id, content)
+        } else {
+            (String::new(), content.to_string())
+        };
+        let request = AddRequest {
+            title: title.to_string(),
+            content,
+            encrypted: encrypt,
+            id,
+        };
 
-        let response = client.add(request).await;
+        let response = with_backoff(client, request, |mut client, request| async move {
+            client.add(request).await
+        })
+        .await;
 
         match response {
             Ok(resp) => {
-                if let Some(note) = resp.into_inner().note {
+                let resp = resp.into_inner();
+                if let Some(note) = resp.note {
                     println!("Note added! #{:}", note.id);
+                    for target in resp.broken_links {
+                        println!("Broken link: no note has the id or title [[{target}]]");
+                    }
                     Ok(0)
                 } else {
                     Ok(1)
                 }
             }
-            Err(err) => Err(report!(err).change_context(BppCliError::FailedToAddNote)),
+            Err(status) => Err(rpc_error(status)),
         }
     }
 
-    async fn handle_rm(client: &mut ApiClient<Channel>, id: &String) -> Result<i32, BppCliError> {
-        let request = Request::new(RmRequest { id: id.clone() });
+    async fn handle_rm(client: &BppClient, id: &str) -> Result<i32, BppCliError> {
+        let request = RmRequest { id: id.to_string() };
 
-        let response = client.rm(request).await;
+        let response = with_backoff(client, request, |mut client, request| async move {
+            client.rm(request).await
+        })
+        .await;
 
         match response {
             Ok(resp) => {
@@ -177,28 +676,49 @@ impl BppCli {
                     Ok(1)
                 }
             }
-            Err(err) => Err(report!(err).change_context(BppCliError::FailedToRmNote)),
+            Err(status) => Err(rpc_error(status)),
         }
     }
 
-    async fn handle_search(
-        client: &mut ApiClient<Channel>,
-        query: &String,
-        all: bool,
-    ) -> Result<i32, BppCliError> {
-        let request = Request::new(SearchRequest {
-            query: query.clone(),
-            all,
-        });
+    async fn handle_search(client: &BppClient, opts: &SearchOpts) -> Result<i32, BppCliError> {
+        let request = SearchRequest {
+            query: opts.query.clone(),
+            all: opts.all,
+            attachments: opts.attachments,
+        };
 
-        let response = client.search(request).await;
+        let response = with_backoff(client, request, |mut client, request| async move {
+            client.search(request).await
+        })
+        .await;
 
         match response {
             Ok(resp) => {
-                let notes = resp.into_inner().notes;
-                if notes.len() > 0 {
+                let SearchResponse { notes, matches } = resp.into_inner();
+                if !notes.is_empty() {
+                    // Only require a key when there is something to decrypt
+                    let key = if notes.iter().any(|note| note.encrypted) {
+                        NoteKey::load().ok()
+                    } else {
+                        None
+                    };
                     for note in notes {
-                        println!("{note}");
+                        match (&key, note.encrypted) {
+                            (_, false) => println!("{note}"),
+                            // One note encrypted with another key must not
+                            // hide the other results
+                            (Some(key), true) => match key.decrypt_note(&note) {
+                                Ok(note) => println!("{note}"),
+                                Err(err) => {
+                                    debug!(id = %note.id, "{:?}", err);
+                                    println!("{:}: <encrypted: cannot decrypt>", note.title)
+                                }
+                            },
+                            (None, true) => println!("{:}: <encrypted>", note.title),
+                        }
+                        if let Some(found) = matches.iter().find(|found| found.note_id == note.id) {
+                            println!("Matched in {:}", found.attachments.join(", "));
+                        }
                         println!("--------");
                     }
                 } else {
@@ -206,18 +726,652 @@ impl BppCli {
                 }
                 Ok(0)
             }
-            Err(err) => Err(report!(err).change_context(BppCliError::FailedToRmNote)),
+            Err(status) => Err(rpc_error(status)),
         }
     }
+
+    async fn handle_show(client: &BppClient, id: &str) -> Result<i32, BppCliError> {
+        let request = LinksRequest { id: id.to_string() };
+        let response = with_backoff(client, request, |mut client, request| async move {
+            client.links(request).await
+        })
+        .await;
+        let links = response.map_err(rpc_error)?.into_inner();
+
+        let request = BacklinksRequest { id: id.to_string() };
+        let response = with_backoff(client, request, |mut client, request| async move {
+            client.backlinks(request).await
+        })
+        .await;
+        let backlinks = response.map_err(rpc_error)?.into_inner().notes;
+
+        let note = match links.note {
+            Some(note) if note.encrypted => match NoteKey::load() {
+                Ok(key) => match key.decrypt_note(&note) {
+                    Ok(note) => note,
+                    // Encrypted with another key, the links are still shown
+                    Err(err) => {
+                        debug!(id = %note.id, "{:?}", err);
+                        Note {
+                            content: "<encrypted: cannot decrypt>".to_string(),
+                            ..note
+                        }
+                    }
+                },
+                Err(_) => Note {
+                    content: "<encrypted>".to_string(),
+                    ..note
+                },
+            },
+            Some(note) => note,
+            None => return Ok(1),
+        };
+        println!("{:} #{:}", note.title, note.id);
+        println!("{:}", note.content);
+
+        if !links.links.is_empty() {
+            println!("--------\nLinks:");
+            for link in links.links {
+                if link.broken {
+                    println!("  [[{:}]] (broken)", link.target);
+                } else {
+                    println!(
+                        "  [[{:}]] -> {:} #{:}",
+                        link.target, link.note_title, link.note_id
+                    );
+                }
+            }
+        }
+        if !backlinks.is_empty() {
+            println!("--------\nLinked from:");
+            for note in backlinks {
+                println!("  {:} #{:}", note.title, note.id);
+            }
+        }
+        if !note.attachments.is_empty() {
+            println!("--------\nAttachments:");
+            for attachment in &note.attachments {
+                println!(
+                    "  {:} ({:})",
+                    attachment.name,
+                    attachment::format_size(attachment.size)
+                );
+            }
+        }
+        Ok(0)
+    }
+
+    async fn handle_attach(client: &BppClient, opts: &AttachOpts) -> Result<i32, BppCliError> {
+        let requests = attachment::attach_requests(
+            &opts.id,
+            &opts.file,
+            opts.name.as_deref(),
+            opts.media_type.as_deref(),
+        )?;
+        debug!(chunks = requests.len(), "Read file to attach");
+
+        let response = with_backoff(client, requests, |mut client, requests| async move {
+            client.attach(tokio_stream::iter(requests)).await
+        })
+        .await;
+        let response = response.map_err(rpc_error)?.into_inner();
+        let attachment = response.attachment.unwrap_or_default();
+        println!(
+            "Attached {:} ({:}) to #{:}",
+            attachment.name,
+            attachment::format_size(attachment.size),
+            opts.id
+        );
+        if response.deduplicated {
+            println!("The same data was already stored, it is not stored twice");
+        }
+        Ok(0)
+    }
+
+    async fn handle_attachments(client: &BppClient, id: &str) -> Result<i32, BppCliError> {
+        let request = AttachmentsRequest {
+            note_id: id.to_string(),
+        };
+        let response = with_backoff(client, request, |mut client, request| async move {
+            client.attachments(request).await
+        })
+        .await;
+        let attachments = response.map_err(rpc_error)?.into_inner().attachments;
+        if attachments.is_empty() {
+            println!("No attachments");
+        }
+        for attachment in attachments {
+            println!(
+                "{:}\t{:}\t{:}\t{:}",
+                attachment.name,
+                attachment::format_size(attachment.size),
+                attachment.media_type,
+                attachment.digest
+            );
+        }
+        Ok(0)
+    }
+
+    /// The file is only kept when its digest matches the attachment
+    async fn handle_fetch(client: &BppClient, opts: &FetchOpts) -> Result<i32, BppCliError> {
+        let request = FetchRequest {
+            note_id: opts.id.clone(),
+            name: opts.name.clone(),
+        };
+        let response = with_backoff(client, request, |mut client, request| async move {
+            client.fetch(request).await
+        })
+        .await;
+        let mut stream = response.map_err(rpc_error)?.into_inner();
+
+        let first = stream.message().await.map_err(rpc_error)?;
+        let (attachment, data) = match first {
+            Some(message) => (message.attachment.unwrap_or_default(), message.data),
+            None => {
+                return Err(report!(BppCliError::FailedToTransfer(
+                    "The server sent no data".to_string()
+                )))
+            }
+        };
+        // Only the file name, names holding a path are rejected by the
+        // server anyway
+        let path = match &opts.output {
+            Some(output) => output.clone(),
+            None => Path::new(&attachment.name)
+                .file_name()
+                .map(PathBuf::from)
+                .ok_or_else(|| {
+                    report!(BppCliError::FailedToTransfer(format!(
+                        "{:?} is not a file name, use --output",
+                        attachment.name
+                    )))
+                })?,
+        };
+        let mut download = Download::create(&path)?;
+        download.write(&data)?;
+        while let Some(message) = stream.message().await.map_err(rpc_error)? {
+            download.write(&message.data)?;
+        }
+        download.finish(&attachment)?;
+
+        println!(
+            "Fetched {:} ({:}) to {:}",
+            attachment.name,
+            attachment::format_size(attachment.size),
+            path.display()
+        );
+        Ok(0)
+    }
+
+    async fn handle_export(client: &BppClient, opts: &ExportOpts) -> Result<i32, BppCliError> {
+        let request = ExportRequest {
+            filter: Some(SearchRequest {
+                query: opts.query.clone().unwrap_or_default(),
+                all: opts.all,
+                attachments: opts.attachments,
+            }),
+        };
+
+        let response = with_backoff(client, request, |mut client, request| async move {
+            client.export(request).await
+        })
+        .await;
+        let mut stream = response.map_err(rpc_error)?.into_inner();
+
+        // Only created once the server accepted the export
+        let mut exporter = Exporter::new(opts.format, opts.output.as_deref())?;
+        let mut count = 0;
+        while let Some(message) = stream.message().await.map_err(rpc_error)? {
+            if let Some(note) = message.note {
+                exporter.write(note)?;
+                count += 1;
+            }
+        }
+        exporter.finish()?;
+        debug!(count, "Export complete");
+
+        if let Some(output) = &opts.output {
+            println!("Exported {count} notes to {:}", output.display());
+        }
+        Ok(0)
+    }
+
+    /// Files that cannot be read or notes the server rejects are reported
+    /// and skipped, the exit code is 1 when there are any
+    async fn handle_import(client: &BppClient, opts: &ImportOpts) -> Result<i32, BppCliError> {
+        let items = import::read_notes(opts.from, &opts.path)?;
+        // Errors of the files that could not be read, `None` for the notes
+        // sent to the server, so they are reported in file order
+        let mut read_errors = vec![];
+        let mut requests = vec![];
+        for ImportItem { source, note } in items {
+            match note {
+                Ok(note) => {
+                    requests.push(ImportRequest {
+                        note: Some(note),
+                        source,
+                        dry_run: opts.dry_run,
+                    });
+                    read_errors.push(None);
+                }
+                Err(error) => read_errors.push(Some((source, error))),
+            }
+        }
+        debug!(
+            notes = requests.len(),
+            unreadable = read_errors.len() - requests.len(),
+            "Read notes to import"
+        );
+
+        let results = if requests.is_empty() {
+            vec![]
+        } else {
+            let response = with_backoff(client, requests, |mut client, requests| async move {
+                client.import(tokio_stream::iter(requests)).await
+            })
+            .await;
+            response.map_err(rpc_error)?.into_inner().results
+        };
+
+        let (mut imported, mut duplicates, mut failed) = (0, 0, 0);
+        let mut results = results.into_iter();
+        for read_error in read_errors {
+            let result = match read_error {
+                Some((source, error)) => {
+                    failed += 1;
+                    println!("Failed {source}: {error}");
+                    continue;
+                }
+                None => results.next().unwrap_or_default(),
+            };
+            match ImportStatus::from_i32(result.status) {
+                Some(ImportStatus::Imported) => {
+                    imported += 1;
+                    if opts.dry_run {
+                        println!("Would import {:}", result.source);
+                    } else {
+                        println!("Imported {:} as #{:}", result.source, result.note_id);
+                    }
+                }
+                Some(ImportStatus::Duplicate) => {
+                    duplicates += 1;
+                    println!(
+                        "Skipped {:}, duplicate of #{:}",
+                        result.source, result.note_id
+                    );
+                }
+                _ => {
+                    failed += 1;
+                    println!("Failed {:}: {:}", result.source, result.error);
+                }
+            }
+        }
+
+        let verb = if opts.dry_run {
+            "Would import"
+        } else {
+            "Imported"
+        };
+        println!("{verb} {imported} notes, skipped {duplicates} duplicates, {failed} failed");
+        Ok(if failed > 0 { 1 } else { 0 })
+    }
+
+    /// Notes are nodes, and so are their tags. Edges are the links between
+    /// notes and the tags of every note
+    async fn handle_graph(client: &BppClient, opts: &GraphOpts) -> Result<i32, BppCliError> {
+        let request = GraphRequest {
+            notebook: opts.notebook.clone().unwrap_or_default(),
+            tag: opts.tag.clone().unwrap_or_default(),
+        };
+
+        let response = with_backoff(client, request, |mut client, request| async move {
+            client.graph(request).await
+        })
+        .await;
+        let mut stream = response.map_err(rpc_error)?.into_inner();
+
+        let (mut nodes, mut edges) = (vec![], vec![]);
+        while let Some(message) = stream.message().await.map_err(rpc_error)? {
+            match message.item {
+                Some(Item::Node(node)) => nodes.push(node),
+                Some(Item::Edge(edge)) => edges.push(edge),
+                None => {}
+            }
+        }
+        debug!(nodes = nodes.len(), edges = edges.len(), "Graph received");
+
+        match &opts.output {
+            Some(path) => {
+                let file = fs::File::create(path).map_err(|err| {
+                    report!(err).change_context(BppCliError::FailedToExport(format!(
+                        "Failed to create {}",
+                        path.display()
+                    )))
+                })?;
+                graph::write_graph(opts.format, &nodes, &edges, io::BufWriter::new(file))?;
+                println!(
+                    "Exported {} nodes and {} edges to {:}",
+                    nodes.len(),
+                    edges.len(),
+                    path.display()
+                );
+            }
+            None => graph::write_graph(opts.format, &nodes, &edges, io::stdout().lock())?,
+        }
+        Ok(0)
+    }
+
+    async fn handle_audit(client: &BppClient, opts: &AuditOpts) -> Result<i32, BppCliError> {
+        let request = AuditLogRequest {
+            note_id: opts.note_id.clone().unwrap_or_default(),
+            action: opts
+                .action
+                .map_or(AuditAction::Unspecified, |action| action.0) as i32,
+            actor: opts.actor.clone().unwrap_or_default(),
+            since_ms: opts.since.map_or(0, |since| since.0),
+            until_ms: opts.until.map_or(0, |until| until.0),
+            limit: opts.limit,
+        };
+
+        let response = with_backoff(client, request, |mut client, request| async move {
+            client.audit_log(request).await
+        })
+        .await;
+
+        match response {
+            Ok(resp) => {
+                let events = resp.into_inner().events;
+                if events.is_empty() {
+                    println!("No audit events found!");
+                }
+                for event in events {
+                    println!("{:}", format_event(&event));
+                }
+                Ok(0)
+            }
+            Err(status) => Err(rpc_error(status)),
+        }
+    }
+
+    async fn fetch_template(client: &BppClient, name: &str) -> Result<Template, BppCliError> {
+        let request = GetTemplateRequest {
+            name: name.to_string(),
+        };
+        let response = with_backoff(client, request, |mut client, request| async move {
+            client.get_template(request).await
+        })
+        .await;
+        Ok(response
+            .map_err(rpc_error)?
+            .into_inner()
+            .template
+            .unwrap_or_default())
+    }
+
+    async fn handle_template(
+        client: &BppClient,
+        command: &TemplateCommands,
+    ) -> Result<i32, BppCliError> {
+        match command {
+            TemplateCommands::List => {
+                let response = with_backoff(
+                    client,
+                    ListTemplatesRequest {},
+                    |mut client, request| async move { client.list_templates(request).await },
+                )
+                .await;
+                let templates = response.map_err(rpc_error)?.into_inner().templates;
+                if templates.is_empty() {
+                    println!("No templates");
+                }
+                for template in templates {
+                    println!("{:}\t{:}", template.name, template.description);
+                }
+            }
+            TemplateCommands::Show(opts) => {
+                let template = Self::fetch_template(client, &opts.name).await?;
+                if !template.description.is_empty() {
+                    println!("{:}\n--------", template.description);
+                }
+                println!("{:}\n{:}", template.title, template.content);
+                let prompts: Vec<String> =
+                    template::placeholders(&format!("{:}\n{:}", template.title, template.content))
+                        .into_iter()
+                        .filter(|placeholder| !template::is_builtin(placeholder))
+                        .collect();
+                if !prompts.is_empty() {
+                    println!("--------\nPrompts for: {:}", prompts.join(", "));
+                }
+            }
+            TemplateCommands::Create(opts) => {
+                let (title, content) = template_text(opts)?;
+                let request = CreateTemplateRequest {
+                    template: Some(Template {
+                        name: opts.name.clone(),
+                        description: opts.description.clone().unwrap_or_default(),
+                        title: title.unwrap_or_default(),
+                        content: content.unwrap_or_default(),
+                        ..Default::default()
+                    }),
+                };
+                let response = with_backoff(client, request, |mut client, request| async move {
+                    client.create_template(request).await
+                })
+                .await;
+                let template = response.map_err(rpc_error)?.into_inner().template;
+                println!("Template created! {:}", template.unwrap_or_default().name);
+            }
+            TemplateCommands::Update(opts) => {
+                // Only the given fields change
+                let (title, content) = template_text(opts)?;
+                let current = Self::fetch_template(client, &opts.name).await?;
+                let request = UpdateTemplateRequest {
+                    template: Some(Template {
+                        description: opts.description.clone().unwrap_or(current.description),
+                        title: title.unwrap_or(current.title),
+                        content: content.unwrap_or(current.content),
+                        ..current
+                    }),
+                };
+                let response = with_backoff(client, request, |mut client, request| async move {
+                    client.update_template(request).await
+                })
+                .await;
+                let template = response.map_err(rpc_error)?.into_inner().template;
+                println!("Template updated! {:}", template.unwrap_or_default().name);
+            }
+            TemplateCommands::Rm(opts) => {
+                let request = DeleteTemplateRequest {
+                    name: opts.name.clone(),
+                };
+                let response = with_backoff(client, request, |mut client, request| async move {
+                    client.delete_template(request).await
+                })
+                .await;
+                let template = response.map_err(rpc_error)?.into_inner().template;
+                println!("Template removed! {:}", template.unwrap_or_default().name);
+            }
+        }
+        Ok(0)
+    }
+}
+
+/// Title and content given to `bpp template create` or `update`, read
+/// from `--file` when given
+fn template_text(opts: &TemplateOpts) -> Result<(Option<String>, Option<String>), BppCliError> {
+    let path = match &opts.file {
+        Some(path) => path,
+        None => return Ok((opts.title.clone(), opts.content.clone())),
+    };
+    let text = fs::read_to_string(path).map_err(|err| {
+        report!(err).change_context(BppCliError::InvalidParameters(format!(
+            "Could not read {}",
+            path.display()
+        )))
+    })?;
+    Ok(match text.split_once('\n') {
+        Some((title, content)) => (Some(title.to_string()), Some(content.to_string())),
+        None => (Some(text), Some(String::new())),
+    })
+}
+
+/// Open `text` in VIM, returning the title and content of the note: the
+/// first line and the rest of the saved text.
+///
+/// The file may hold the plaintext of a note about to be encrypted, so it
+/// is only readable by the user, in a directory of its own removed on
+/// return, errors included
+fn edit_note(text: &str) -> Result<(String, String), BppCliError> {
+    let to_add_error = |err: io::Error| report!(err).change_context(BppCliError::FailedToAddNote);
+    let tmp_dir = tempfile::Builder::new()
+        .prefix("bpp-")
+        .tempdir()
+        .and_then(|dir| {
+            fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o700)).map(|_| dir)
+        })
+        .map_err(to_add_error)?;
+    let tmp_file = tmp_dir.path().join("note.bpp");
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp_file)
+        .and_then(|mut file| file.write_all(text.as_bytes()))
+        .map_err(to_add_error)?;
+
+    process::Command::new("vim")
+        .arg(&tmp_file)
+        .spawn()
+        .and_then(|mut vim| vim.wait())
+        .map_err(to_add_error)?;
+    let user_text = fs::read_to_string(&tmp_file).map_err(to_add_error)?;
+
+    // The first line is the title
+    Ok(match user_text.split_once('\n') {
+        Some((title, content)) => (title.to_string(), content.to_string()),
+        None => (user_text, String::new()),
+    })
+}
+
+/// One line per event: time, action, note, then who made the change
+fn format_event(event: &AuditEvent) -> String {
+    let time = UNIX_EPOCH + Duration::from_millis(event.timestamp_ms.max(0) as u64);
+    let action = match AuditAction::from_i32(event.action) {
+        Some(AuditAction::Add) => "add",
+        Some(AuditAction::Rm) => "rm",
+        Some(AuditAction::Import) => "import",
+        Some(AuditAction::Restore) => "restore",
+        Some(AuditAction::Attach) => "attach",
+        _ => "?",
+    };
+    // Restores apply to every note
+    let note = match event.note_id.as_str() {
+        "" => String::new(),
+        id => format!(" #{id} {:?}", event.note_title),
+    };
+    let detail = match event.detail.as_str() {
+        "" => String::new(),
+        detail => format!(" [{detail}]"),
+    };
+    let or_unknown = |value: &str| {
+        if value.is_empty() {
+            "-".to_string()
+        } else {
+            value.to_string()
+        }
+    };
+    format!(
+        "{:} {action:<7}{note}{detail} by {:} from {:} (request {:})",
+        humantime::format_rfc3339_millis(time),
+        or_unknown(&event.actor),
+        or_unknown(&event.peer),
+        or_unknown(&event.request_id),
+    )
+}
+
+/// Call `rpc` until it succeeds, fails without the server asking for a
+/// retry, or `MAX_ATTEMPTS` is reached
+async fn with_backoff<Req, Res, F, Fut>(
+    client: &BppClient,
+    request: Req,
+    rpc: F,
+) -> std::result::Result<Response<Res>, Status>
+where
+    Req: Clone,
+    F: Fn(BppClient, Req) -> Fut,
+    Fut: Future<Output = std::result::Result<Response<Res>, Status>>,
+{
+    let mut attempt = 0;
+    loop {
+        match rpc(client.clone(), request.clone()).await {
+            Err(status) if attempt + 1 < MAX_ATTEMPTS => match retry_delay(&status, attempt) {
+                Some(delay) => {
+                    warn!(
+                        code = ?status.code(),
+                        "Server asked to retry later, retrying in {:.1}s",
+                        delay.as_secs_f64()
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                None => return Err(status),
+            },
+            result => return result,
+        }
+    }
+}
+
+/// Delay before retrying a failed rpc, when the server asked for a retry
+/// with `retry-after` metadata. The delay doubles on every attempt and
+/// never goes below what the server asked for
+fn retry_delay(status: &Status, attempt: u32) -> Option<Duration> {
+    let requested = status
+        .metadata()
+        .get(RETRY_AFTER_KEY)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .map(Duration::from_secs)?;
+    let backoff = BASE_BACKOFF * 2u32.pow(attempt);
+    Some(requested.max(backoff).min(MAX_BACKOFF))
+}
+
+/// The status itself is only attached as text, its details are binary
+fn rpc_error(status: Status) -> Report<BppCliError> {
+    report!(BppCliError::from(&status)).attach_printable(format!(
+        "status: {:?}, message: {:?}",
+        status.code(),
+        status.message()
+    ))
 }
 
 #[tokio::main]
 async fn main() {
     let cli = BppCli::from_args();
+    init_logging(cli.verbose);
     match cli.run().await {
-        Ok(_) => (),
+        Ok(code) => process::exit(code),
         Err(err) => {
-            println!("{:?}", err);
+            eprintln!("{:?}", err);
+            process::exit(1)
         }
     }
 }
+
+/// Diagnostics go to stderr so they never mix with the command output.
+/// Logs of dependencies (hyper, h2...) are only shown with -vvv
+fn init_logging(verbose: u8) {
+    let (level, dependencies) = match verbose {
+        0 => (LevelFilter::WARN, LevelFilter::WARN),
+        1 => (LevelFilter::INFO, LevelFilter::WARN),
+        2 => (LevelFilter::DEBUG, LevelFilter::WARN),
+        _ => (LevelFilter::TRACE, LevelFilter::TRACE),
+    };
+    tracing_subscriber::registry()
+        .with(
+            Targets::new()
+                .with_target(env!("CARGO_CRATE_NAME"), level)
+                .with_default(dependencies),
+        )
+        .with(fmt::layer().with_writer(std::io::stderr))
+        .init();
+}
@@ -0,0 +1,212 @@
+use crate::error_def::BppCliError;
+use bpp_proto::bpp::Template;
+use error_stack::{report, Result};
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+/// Placeholders filled in without asking: `{{date}}` and `{{time}}` in
+/// UTC, `{{user}}` the local user name
+static BUILTINS: [&str; 3] = ["date", "time", "user"];
+
+/// Value given with `bpp add --var NAME=VALUE` for the placeholder
+/// `{{NAME}}`, so it is not prompted for
+#[derive(Debug, Clone)]
+pub struct TemplateVar {
+    name: String,
+    value: String,
+}
+
+impl FromStr for TemplateVar {
+    type Err = String;
+
+    fn from_str(var: &str) -> std::result::Result<Self, Self::Err> {
+        match var.split_once('=') {
+            Some((name, value)) if !name.trim().is_empty() => Ok(TemplateVar {
+                name: name.trim().to_string(),
+                value: value.to_string(),
+            }),
+            _ => Err(format!("{var:?} is not NAME=VALUE")),
+        }
+    }
+}
+
+/// The placeholders of `text`, trimmed, in order of first appearance
+pub fn placeholders(text: &str) -> Vec<String> {
+    let mut found: Vec<String> = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start + 2..].find("}}") {
+            Some(end) => start + 2 + end,
+            None => break,
+        };
+        let placeholder = rest[start + 2..end].trim().to_string();
+        if !placeholder.is_empty() && !found.contains(&placeholder) {
+            found.push(placeholder);
+        }
+        rest = &rest[end + 2..];
+    }
+    found
+}
+
+/// The title and content of `template` as the first line and the rest of
+/// the text opened in the editor. Placeholders that are neither builtins
+/// nor given in `vars` are prompted for, their text being the prompt, eg.
+/// `{{What did you do yesterday?}}`. Each is asked once
+pub fn render(template: &Template, vars: &[TemplateVar]) -> Result<String, BppCliError> {
+    let text = format!("{:}\n{:}", template.title, template.content);
+    let now = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+    let stdin = io::stdin();
+    let mut values = vec![];
+    for placeholder in placeholders(&text) {
+        let given = vars
+            .iter()
+            .rev()
+            .find(|var| var.name == placeholder)
+            .map(|var| var.value.clone());
+        let value = match (given, placeholder.as_str()) {
+            (Some(value), _) => value,
+            (None, "date") => now[..10].to_string(),
+            (None, "time") => now[11..16].to_string(),
+            (None, "user") => env::var("USER")
+                .or_else(|_| env::var("LOGNAME"))
+                .unwrap_or_default(),
+            (None, prompt) => {
+                print!("{prompt}: ");
+                let mut answer = String::new();
+                io::stdout()
+                    .flush()
+                    .and_then(|_| stdin.lock().read_line(&mut answer))
+                    .map_err(|err| {
+                        report!(err).change_context(BppCliError::InvalidParameters(format!(
+                            "Could not read a value for {{{{{prompt}}}}}"
+                        )))
+                    })?;
+                answer.trim_end_matches(['\r', '\n']).to_string()
+            }
+        };
+        values.push((placeholder, value));
+    }
+    Ok(replace_placeholders(&text, &values))
+}
+
+/// Whether `placeholder` is filled in without asking
+pub fn is_builtin(placeholder: &str) -> bool {
+    BUILTINS.contains(&placeholder)
+}
+
+fn replace_placeholders(text: &str, values: &[(String, String)]) -> String {
+    let mut rendered = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start + 2..].find("}}") {
+            Some(end) => start + 2 + end,
+            None => break,
+        };
+        rendered.push_str(&rest[..start]);
+        let placeholder = rest[start + 2..end].trim();
+        match values.iter().find(|(name, _)| name == placeholder) {
+            Some((_, value)) => rendered.push_str(value),
+            None => rendered.push_str(&rest[start..end + 2]),
+        }
+        rest = &rest[end + 2..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(title: &str, content: &str) -> Template {
+        Template {
+            title: title.to_string(),
+            content: content.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn var(var: &str) -> TemplateVar {
+        var.parse().unwrap()
+    }
+
+    #[test]
+    fn placeholders_are_trimmed_and_listed_once() {
+        assert_eq!(
+            placeholders("{{ a }} {{b}} {{a}} {{  }} {{What did you do?}}"),
+            ["a", "b", "What did you do?"]
+        );
+    }
+
+    #[test]
+    fn unterminated_placeholder_is_text() {
+        assert_eq!(placeholders("{{a}} then {{b"), ["a"]);
+        let values = [("a".to_string(), "1".to_string())];
+        assert_eq!(
+            replace_placeholders("{{a}} then {{b", &values),
+            "1 then {{b"
+        );
+    }
+
+    #[test]
+    fn every_occurrence_is_replaced() {
+        let values = [("a".to_string(), "1".to_string())];
+        assert_eq!(
+            replace_placeholders("{{a}}, {{ a }} and {{unknown}}", &values),
+            "1, 1 and {{unknown}}"
+        );
+    }
+
+    #[test]
+    fn vars_fill_placeholders_and_the_last_one_wins() {
+        let rendered = render(
+            &template("Standup {{ team }}", "{{Yesterday}}\n{{team}}"),
+            &[
+                var("team=core"),
+                var("Yesterday=reviews"),
+                var(" team =platform"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(rendered, "Standup platform\nreviews\nplatform");
+    }
+
+    #[test]
+    fn builtins_are_not_prompted_for() {
+        let rendered = render(&template("{{date}} {{time}}", "{{user}}"), &[]).unwrap();
+        let (title, user) = rendered.split_once('\n').unwrap();
+        let (date, time) = title.split_once(' ').unwrap();
+
+        let is_digits = |text: &str| text.chars().all(|c| c.is_ascii_digit());
+        let date_parts: Vec<_> = date.split('-').collect();
+        assert_eq!(date_parts.len(), 3, "{date}");
+        assert!(date_parts.iter().all(|part| is_digits(part)), "{date}");
+        let time_parts: Vec<_> = time.split(':').collect();
+        assert_eq!(time_parts.len(), 2, "{time}");
+        assert!(time_parts.iter().all(|part| is_digits(part)), "{time}");
+
+        let expected = env::var("USER")
+            .or_else(|_| env::var("LOGNAME"))
+            .unwrap_or_default();
+        assert_eq!(user, expected);
+        assert!(BUILTINS.iter().all(|builtin| is_builtin(builtin)));
+        assert!(!is_builtin("Yesterday"));
+    }
+
+    #[test]
+    fn vars_override_builtins() {
+        let rendered = render(&template("{{date}}", ""), &[var("date=someday")]).unwrap();
+        assert_eq!(rendered, "someday\n");
+    }
+
+    #[test]
+    fn var_parsing() {
+        let parsed = var(" name = a=b ");
+        assert_eq!(parsed.name, "name");
+        assert_eq!(parsed.value, " a=b ");
+        assert!("=value".parse::<TemplateVar>().is_err());
+        assert!("novalue".parse::<TemplateVar>().is_err());
+    }
+}
@@ -1,8 +1,11 @@
+use std::env;
 use std::path::PathBuf;
 
 fn main() {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").expect("OUT_DIR is set by cargo"));
     tonic_build::configure()
-        .out_dir(&PathBuf::from("src"))
+        .out_dir(PathBuf::from("src"))
+        .file_descriptor_set_path(out_dir.join("bpp_descriptor.bin"))
         .compile(&["protos/api.proto"], &["protos"])
         .expect("Failed to compile protobuf")
 }
@@ -7,6 +7,89 @@ pub struct Note {
     pub title: ::prost::alloc::string::String,
     #[prost(string, tag = "3")]
     pub content: ::prost::alloc::string::String,
+    /// When set, `content` is ciphertext produced by the client and
+    /// opaque to the server
+    #[prost(bool, tag = "4")]
+    pub encrypted: bool,
+    /// Folder the note was filed in, "/" separated. Empty for none
+    #[prost(string, tag = "5")]
+    pub notebook: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "6")]
+    pub tags: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// Unix time in milliseconds
+    #[prost(int64, tag = "7")]
+    pub created_ms: i64,
+    #[prost(int64, tag = "8")]
+    pub updated_ms: i64,
+    /// Files attached with `Attach`, in the order they were attached
+    #[prost(message, repeated, tag = "9")]
+    pub attachments: ::prost::alloc::vec::Vec<Attachment>,
+}
+/// A file attached to a note. Its data is stored once per digest, however
+/// many notes it is attached to
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Attachment {
+    /// Unique among the attachments of a note
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    /// Hex encoded sha256 of the data
+    #[prost(string, tag = "2")]
+    pub digest: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "3")]
+    pub size: u64,
+    /// As sent by the client, eg. image/png
+    #[prost(string, tag = "4")]
+    pub media_type: ::prost::alloc::string::String,
+    /// Unix time in milliseconds
+    #[prost(int64, tag = "5")]
+    pub created_ms: i64,
+}
+/// A note mutation, as recorded by the server. Events are kept after the
+/// note is removed
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AuditEvent {
+    /// Unix time in milliseconds
+    #[prost(int64, tag = "1")]
+    pub timestamp_ms: i64,
+    #[prost(enumeration = "AuditAction", tag = "2")]
+    pub action: i32,
+    #[prost(string, tag = "3")]
+    pub note_id: ::prost::alloc::string::String,
+    /// Title of the note when the event happened
+    #[prost(string, tag = "4")]
+    pub note_title: ::prost::alloc::string::String,
+    /// User name sent by the client, it is not authenticated
+    #[prost(string, tag = "5")]
+    pub actor: ::prost::alloc::string::String,
+    /// Address of the client
+    #[prost(string, tag = "6")]
+    pub peer: ::prost::alloc::string::String,
+    #[prost(string, tag = "7")]
+    pub request_id: ::prost::alloc::string::String,
+    /// Name of the attachment for attach events, the backup and the number of
+    /// notes restored for restore events
+    #[prost(string, tag = "8")]
+    pub detail: ::prost::alloc::string::String,
+}
+/// A `\[[target]\]` or `\[[target|label]\]` link in the content of a note.
+/// The target is the id or the title of another note, titles are compared
+/// ignoring case
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Link {
+    /// As written between the brackets, without the label
+    #[prost(string, tag = "1")]
+    pub target: ::prost::alloc::string::String,
+    /// Note the target resolves to, empty when the link is broken
+    #[prost(string, tag = "2")]
+    pub note_id: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub note_title: ::prost::alloc::string::String,
+    /// No note has this id or title
+    #[prost(bool, tag = "4")]
+    pub broken: bool,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -15,12 +98,21 @@ pub struct AddRequest {
     pub title: ::prost::alloc::string::String,
     #[prost(string, tag = "2")]
     pub content: ::prost::alloc::string::String,
+    #[prost(bool, tag = "3")]
+    pub encrypted: bool,
+    /// Id to give the note, generated by the server when empty. Clients set
+    /// it to bind the ciphertext of an encrypted note to its id
+    #[prost(string, tag = "4")]
+    pub id: ::prost::alloc::string::String,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct AddResponse {
     #[prost(message, optional, tag = "1")]
     pub note: ::core::option::Option<Note>,
+    /// Targets of the links of the note that resolve to no note
+    #[prost(string, repeated, tag = "2")]
+    pub broken_links: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -41,12 +133,421 @@ pub struct SearchRequest {
     pub query: ::prost::alloc::string::String,
     #[prost(bool, tag = "2")]
     pub all: bool,
+    /// Match the query against the text extracted from attachments too
+    #[prost(bool, tag = "3")]
+    pub attachments: bool,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct SearchResponse {
     #[prost(message, repeated, tag = "1")]
     pub notes: ::prost::alloc::vec::Vec<Note>,
+    /// Attachments whose text matched the query, for the notes that have any
+    #[prost(message, repeated, tag = "2")]
+    pub matches: ::prost::alloc::vec::Vec<SearchMatch>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SearchMatch {
+    #[prost(string, tag = "1")]
+    pub note_id: ::prost::alloc::string::String,
+    /// Attachment names, in the order they were attached
+    #[prost(string, repeated, tag = "2")]
+    pub attachments: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// Exports the notes `Search` would return for `filter`, every note when
+/// it is unset
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ExportRequest {
+    #[prost(message, optional, tag = "1")]
+    pub filter: ::core::option::Option<SearchRequest>,
+}
+/// One message per note. Encrypted notes are exported as stored
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ExportResponse {
+    #[prost(message, optional, tag = "1")]
+    pub note: ::core::option::Option<Note>,
+}
+/// One message per note. The id is kept when no other note has it, the
+/// timestamps default to the time of the import
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ImportRequest {
+    #[prost(message, optional, tag = "1")]
+    pub note: ::core::option::Option<Note>,
+    /// Where the note comes from, eg. a file name, echoed in the results
+    #[prost(string, tag = "2")]
+    pub source: ::prost::alloc::string::String,
+    /// Report what would be imported without storing anything. Only read
+    /// from the first message
+    #[prost(bool, tag = "3")]
+    pub dry_run: bool,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ImportResult {
+    #[prost(string, tag = "1")]
+    pub source: ::prost::alloc::string::String,
+    #[prost(enumeration = "ImportStatus", tag = "2")]
+    pub status: i32,
+    /// Id of the imported note, empty for a dry run, or of the note it
+    /// duplicates
+    #[prost(string, tag = "3")]
+    pub note_id: ::prost::alloc::string::String,
+    /// Why the note failed validation
+    #[prost(string, tag = "4")]
+    pub error: ::prost::alloc::string::String,
+}
+/// Results are in the order of the requests
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ImportResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub results: ::prost::alloc::vec::Vec<ImportResult>,
+    #[prost(bool, tag = "2")]
+    pub dry_run: bool,
+}
+/// Unset filters match every event
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AuditLogRequest {
+    #[prost(string, tag = "1")]
+    pub note_id: ::prost::alloc::string::String,
+    #[prost(enumeration = "AuditAction", tag = "2")]
+    pub action: i32,
+    #[prost(string, tag = "3")]
+    pub actor: ::prost::alloc::string::String,
+    /// Unix time in milliseconds, inclusive. 0 for no bound
+    #[prost(int64, tag = "4")]
+    pub since_ms: i64,
+    #[prost(int64, tag = "5")]
+    pub until_ms: i64,
+    /// Number of most recent matching events returned. 0 for the default
+    #[prost(uint32, tag = "6")]
+    pub limit: u32,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AuditLogResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub events: ::prost::alloc::vec::Vec<AuditEvent>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LinksRequest {
+    #[prost(string, tag = "1")]
+    pub id: ::prost::alloc::string::String,
+}
+/// Links are in the order they appear in. Encrypted notes have none
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LinksResponse {
+    #[prost(message, optional, tag = "1")]
+    pub note: ::core::option::Option<Note>,
+    #[prost(message, repeated, tag = "2")]
+    pub links: ::prost::alloc::vec::Vec<Link>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BacklinksRequest {
+    #[prost(string, tag = "1")]
+    pub id: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BacklinksResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub notes: ::prost::alloc::vec::Vec<Note>,
+}
+/// Unset filters match every note
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GraphRequest {
+    /// Only notes filed in this notebook or one below it
+    #[prost(string, tag = "1")]
+    pub notebook: ::prost::alloc::string::String,
+    /// Only notes with this tag, compared ignoring case and a leading `#`
+    #[prost(string, tag = "2")]
+    pub tag: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GraphNode {
+    #[prost(string, tag = "1")]
+    pub id: ::prost::alloc::string::String,
+    #[prost(enumeration = "GraphNodeKind", tag = "2")]
+    pub kind: i32,
+    #[prost(string, tag = "3")]
+    pub title: ::prost::alloc::string::String,
+    /// Empty for tags
+    #[prost(string, tag = "4")]
+    pub notebook: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "5")]
+    pub tags: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// Edges only join nodes of the graph. A note linking several times to
+/// another gives a single edge
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GraphEdge {
+    #[prost(string, tag = "1")]
+    pub source: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub target: ::prost::alloc::string::String,
+    #[prost(enumeration = "GraphEdgeKind", tag = "3")]
+    pub kind: i32,
+}
+/// Every node is sent before the first edge
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GraphResponse {
+    #[prost(oneof = "graph_response::Item", tags = "1, 2")]
+    pub item: ::core::option::Option<graph_response::Item>,
+}
+/// Nested message and enum types in `GraphResponse`.
+pub mod graph_response {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum Item {
+        #[prost(message, tag = "1")]
+        Node(super::GraphNode),
+        #[prost(message, tag = "2")]
+        Edge(super::GraphEdge),
+    }
+}
+/// The file is sent in chunks of `data`. The other fields are only read
+/// from the first message
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AttachRequest {
+    #[prost(string, tag = "1")]
+    pub note_id: ::prost::alloc::string::String,
+    /// Replaces the attachment of the note with the same name, if any
+    #[prost(string, tag = "2")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub media_type: ::prost::alloc::string::String,
+    #[prost(bytes = "vec", tag = "4")]
+    pub data: ::prost::alloc::vec::Vec<u8>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AttachResponse {
+    #[prost(message, optional, tag = "1")]
+    pub note: ::core::option::Option<Note>,
+    #[prost(message, optional, tag = "2")]
+    pub attachment: ::core::option::Option<Attachment>,
+    /// The data was already stored for this or another note
+    #[prost(bool, tag = "3")]
+    pub deduplicated: bool,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AttachmentsRequest {
+    #[prost(string, tag = "1")]
+    pub note_id: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AttachmentsResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub attachments: ::prost::alloc::vec::Vec<Attachment>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct FetchRequest {
+    #[prost(string, tag = "1")]
+    pub note_id: ::prost::alloc::string::String,
+    /// Name or digest of the attachment
+    #[prost(string, tag = "2")]
+    pub name: ::prost::alloc::string::String,
+}
+/// The first message carries the attachment, every message a chunk of its
+/// data
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct FetchResponse {
+    #[prost(message, optional, tag = "1")]
+    pub attachment: ::core::option::Option<Attachment>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub data: ::prost::alloc::vec::Vec<u8>,
+}
+/// A skeleton for notes, stored by the server and rendered by the client.
+/// `{{date}}`, `{{time}}` and `{{user}}` in the title or the content are
+/// replaced when rendering, any other `{{...}}` is asked to the user
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Template {
+    /// Lowercase letters, digits, `-` and `_`
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub description: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub title: ::prost::alloc::string::String,
+    #[prost(string, tag = "4")]
+    pub content: ::prost::alloc::string::String,
+    /// Set by the server
+    #[prost(int64, tag = "5")]
+    pub created_ms: i64,
+    #[prost(int64, tag = "6")]
+    pub updated_ms: i64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CreateTemplateRequest {
+    #[prost(message, optional, tag = "1")]
+    pub template: ::core::option::Option<Template>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CreateTemplateResponse {
+    #[prost(message, optional, tag = "1")]
+    pub template: ::core::option::Option<Template>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GetTemplateRequest {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GetTemplateResponse {
+    #[prost(message, optional, tag = "1")]
+    pub template: ::core::option::Option<Template>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListTemplatesRequest {}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListTemplatesResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub templates: ::prost::alloc::vec::Vec<Template>,
+}
+/// Replaces the description, title and content of the template
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct UpdateTemplateRequest {
+    #[prost(message, optional, tag = "1")]
+    pub template: ::core::option::Option<Template>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct UpdateTemplateResponse {
+    #[prost(message, optional, tag = "1")]
+    pub template: ::core::option::Option<Template>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DeleteTemplateRequest {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DeleteTemplateResponse {
+    #[prost(message, optional, tag = "1")]
+    pub template: ::core::option::Option<Template>,
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum AuditAction {
+    Unspecified = 0,
+    Add = 1,
+    Rm = 2,
+    /// Added by an import, from a client or `bpp-server import`
+    Import = 3,
+    /// Notes replaced by `bpp-server restore`, not tied to a note
+    Restore = 4,
+    Attach = 5,
+}
+impl AuditAction {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            AuditAction::Unspecified => "AUDIT_ACTION_UNSPECIFIED",
+            AuditAction::Add => "AUDIT_ACTION_ADD",
+            AuditAction::Rm => "AUDIT_ACTION_RM",
+            AuditAction::Import => "AUDIT_ACTION_IMPORT",
+            AuditAction::Restore => "AUDIT_ACTION_RESTORE",
+            AuditAction::Attach => "AUDIT_ACTION_ATTACH",
+        }
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum ImportStatus {
+    Unspecified = 0,
+    Imported = 1,
+    /// Same title and content as an existing note, or an earlier one of the
+    /// import
+    Duplicate = 2,
+    Failed = 3,
+}
+impl ImportStatus {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            ImportStatus::Unspecified => "IMPORT_STATUS_UNSPECIFIED",
+            ImportStatus::Imported => "IMPORT_STATUS_IMPORTED",
+            ImportStatus::Duplicate => "IMPORT_STATUS_DUPLICATE",
+            ImportStatus::Failed => "IMPORT_STATUS_FAILED",
+        }
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum GraphNodeKind {
+    Unspecified = 0,
+    Note = 1,
+    /// A tag of at least one of the notes. Its id is the tag prefixed with
+    /// `#`, its title the tag
+    Tag = 2,
+}
+impl GraphNodeKind {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            GraphNodeKind::Unspecified => "GRAPH_NODE_KIND_UNSPECIFIED",
+            GraphNodeKind::Note => "GRAPH_NODE_KIND_NOTE",
+            GraphNodeKind::Tag => "GRAPH_NODE_KIND_TAG",
+        }
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum GraphEdgeKind {
+    Unspecified = 0,
+    /// A `\[[...]\]` link from the source note to the target note
+    Link = 1,
+    /// The source note has the target tag
+    Tag = 2,
+}
+impl GraphEdgeKind {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            GraphEdgeKind::Unspecified => "GRAPH_EDGE_KIND_UNSPECIFIED",
+            GraphEdgeKind::Link => "GRAPH_EDGE_KIND_LINK",
+            GraphEdgeKind::Tag => "GRAPH_EDGE_KIND_TAG",
+        }
+    }
 }
 /// Generated client implementations.
 pub mod api_client {
@@ -153,7 +654,7 @@ pub mod api_client {
             let path = http::uri::PathAndQuery::from_static("/bpp.Api/Rm");
             self.inner.unary(request.into_request(), path, codec).await
         }
-        /// search(all: bool, input: String) -> Vec<Note>
+        /// search(all: bool, attachments: bool, input: String) -> Vec<Note>
         pub async fn search(
             &mut self,
             request: impl tonic::IntoRequest<super::SearchRequest>,
@@ -171,55 +672,408 @@ pub mod api_client {
             let path = http::uri::PathAndQuery::from_static("/bpp.Api/Search");
             self.inner.unary(request.into_request(), path, codec).await
         }
-    }
-}
-/// Generated server implementations.
-pub mod api_server {
-    #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
-    use tonic::codegen::*;
-    /// Generated trait containing gRPC methods that should be implemented for use with ApiServer.
-    #[async_trait]
-    pub trait Api: Send + Sync + 'static {
-        /// add(title: String, content: String) -> Result<Note, Error>
-        async fn add(
-            &self,
-            request: tonic::Request<super::AddRequest>,
-        ) -> Result<tonic::Response<super::AddResponse>, tonic::Status>;
-        /// rm(id: String) -> result<Option<Note>, Error>
-        async fn rm(
-            &self,
-            request: tonic::Request<super::RmRequest>,
-        ) -> Result<tonic::Response<super::RmResponse>, tonic::Status>;
-        /// search(all: bool, input: String) -> Vec<Note>
-        async fn search(
-            &self,
-            request: tonic::Request<super::SearchRequest>,
-        ) -> Result<tonic::Response<super::SearchResponse>, tonic::Status>;
-    }
-    #[derive(Debug)]
-    pub struct ApiServer<T: Api> {
-        inner: _Inner<T>,
-        accept_compression_encodings: EnabledCompressionEncodings,
-        send_compression_encodings: EnabledCompressionEncodings,
-    }
-    struct _Inner<T>(Arc<T>);
-    impl<T: Api> ApiServer<T> {
-        pub fn new(inner: T) -> Self {
-            Self::from_arc(Arc::new(inner))
+        /// export(filter: SearchRequest) -> stream of Note
+        pub async fn export(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ExportRequest>,
+        ) -> Result<
+            tonic::Response<tonic::codec::Streaming<super::ExportResponse>>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/bpp.Api/Export");
+            self.inner.server_streaming(request.into_request(), path, codec).await
         }
-        pub fn from_arc(inner: Arc<T>) -> Self {
-            let inner = _Inner(inner);
-            Self {
-                inner,
-                accept_compression_encodings: Default::default(),
-                send_compression_encodings: Default::default(),
-            }
+        /// import(stream of Note) -> outcome of every note
+        pub async fn import(
+            &mut self,
+            request: impl tonic::IntoStreamingRequest<Message = super::ImportRequest>,
+        ) -> Result<tonic::Response<super::ImportResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/bpp.Api/Import");
+            self.inner
+                .client_streaming(request.into_streaming_request(), path, codec)
+                .await
         }
-        pub fn with_interceptor<F>(
-            inner: T,
-            interceptor: F,
-        ) -> InterceptedService<Self, F>
-        where
+        /// audit_log(filters) -> Vec<AuditEvent>, oldest first
+        pub async fn audit_log(
+            &mut self,
+            request: impl tonic::IntoRequest<super::AuditLogRequest>,
+        ) -> Result<tonic::Response<super::AuditLogResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/bpp.Api/AuditLog");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// links(id) -> (Note, Vec<Link>), the links in the content of the note
+        pub async fn links(
+            &mut self,
+            request: impl tonic::IntoRequest<super::LinksRequest>,
+        ) -> Result<tonic::Response<super::LinksResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/bpp.Api/Links");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// backlinks(id) -> Vec<Note>, the notes linking to the note
+        pub async fn backlinks(
+            &mut self,
+            request: impl tonic::IntoRequest<super::BacklinksRequest>,
+        ) -> Result<tonic::Response<super::BacklinksResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/bpp.Api/Backlinks");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// graph(filters) -> stream of nodes, then edges
+        pub async fn graph(
+            &mut self,
+            request: impl tonic::IntoRequest<super::GraphRequest>,
+        ) -> Result<
+            tonic::Response<tonic::codec::Streaming<super::GraphResponse>>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/bpp.Api/Graph");
+            self.inner.server_streaming(request.into_request(), path, codec).await
+        }
+        /// attach(note_id, name, stream of data) -> Attachment
+        pub async fn attach(
+            &mut self,
+            request: impl tonic::IntoStreamingRequest<Message = super::AttachRequest>,
+        ) -> Result<tonic::Response<super::AttachResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/bpp.Api/Attach");
+            self.inner
+                .client_streaming(request.into_streaming_request(), path, codec)
+                .await
+        }
+        /// attachments(note_id) -> Vec<Attachment>
+        pub async fn attachments(
+            &mut self,
+            request: impl tonic::IntoRequest<super::AttachmentsRequest>,
+        ) -> Result<tonic::Response<super::AttachmentsResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/bpp.Api/Attachments");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// fetch(note_id, name) -> Attachment, then stream of data
+        pub async fn fetch(
+            &mut self,
+            request: impl tonic::IntoRequest<super::FetchRequest>,
+        ) -> Result<
+            tonic::Response<tonic::codec::Streaming<super::FetchResponse>>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/bpp.Api/Fetch");
+            self.inner.server_streaming(request.into_request(), path, codec).await
+        }
+        /// create_template(Template) -> Template, fails if the name is taken
+        pub async fn create_template(
+            &mut self,
+            request: impl tonic::IntoRequest<super::CreateTemplateRequest>,
+        ) -> Result<tonic::Response<super::CreateTemplateResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/bpp.Api/CreateTemplate");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// get_template(name) -> Template
+        pub async fn get_template(
+            &mut self,
+            request: impl tonic::IntoRequest<super::GetTemplateRequest>,
+        ) -> Result<tonic::Response<super::GetTemplateResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/bpp.Api/GetTemplate");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// list_templates() -> Vec<Template>, by name
+        pub async fn list_templates(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ListTemplatesRequest>,
+        ) -> Result<tonic::Response<super::ListTemplatesResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/bpp.Api/ListTemplates");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// update_template(Template) -> Template, fails if there is none by the name
+        pub async fn update_template(
+            &mut self,
+            request: impl tonic::IntoRequest<super::UpdateTemplateRequest>,
+        ) -> Result<tonic::Response<super::UpdateTemplateResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/bpp.Api/UpdateTemplate");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// delete_template(name) -> Template
+        pub async fn delete_template(
+            &mut self,
+            request: impl tonic::IntoRequest<super::DeleteTemplateRequest>,
+        ) -> Result<tonic::Response<super::DeleteTemplateResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/bpp.Api/DeleteTemplate");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+    }
+}
+/// Generated server implementations.
+pub mod api_server {
+    #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
+    use tonic::codegen::*;
+    /// Generated trait containing gRPC methods that should be implemented for use with ApiServer.
+    #[async_trait]
+    pub trait Api: Send + Sync + 'static {
+        /// add(title: String, content: String) -> Result<Note, Error>
+        async fn add(
+            &self,
+            request: tonic::Request<super::AddRequest>,
+        ) -> Result<tonic::Response<super::AddResponse>, tonic::Status>;
+        /// rm(id: String) -> result<Option<Note>, Error>
+        async fn rm(
+            &self,
+            request: tonic::Request<super::RmRequest>,
+        ) -> Result<tonic::Response<super::RmResponse>, tonic::Status>;
+        /// search(all: bool, attachments: bool, input: String) -> Vec<Note>
+        async fn search(
+            &self,
+            request: tonic::Request<super::SearchRequest>,
+        ) -> Result<tonic::Response<super::SearchResponse>, tonic::Status>;
+        /// Server streaming response type for the Export method.
+        type ExportStream: futures_core::Stream<
+                Item = Result<super::ExportResponse, tonic::Status>,
+            >
+            + Send
+            + 'static;
+        /// export(filter: SearchRequest) -> stream of Note
+        async fn export(
+            &self,
+            request: tonic::Request<super::ExportRequest>,
+        ) -> Result<tonic::Response<Self::ExportStream>, tonic::Status>;
+        /// import(stream of Note) -> outcome of every note
+        async fn import(
+            &self,
+            request: tonic::Request<tonic::Streaming<super::ImportRequest>>,
+        ) -> Result<tonic::Response<super::ImportResponse>, tonic::Status>;
+        /// audit_log(filters) -> Vec<AuditEvent>, oldest first
+        async fn audit_log(
+            &self,
+            request: tonic::Request<super::AuditLogRequest>,
+        ) -> Result<tonic::Response<super::AuditLogResponse>, tonic::Status>;
+        /// links(id) -> (Note, Vec<Link>), the links in the content of the note
+        async fn links(
+            &self,
+            request: tonic::Request<super::LinksRequest>,
+        ) -> Result<tonic::Response<super::LinksResponse>, tonic::Status>;
+        /// backlinks(id) -> Vec<Note>, the notes linking to the note
+        async fn backlinks(
+            &self,
+            request: tonic::Request<super::BacklinksRequest>,
+        ) -> Result<tonic::Response<super::BacklinksResponse>, tonic::Status>;
+        /// Server streaming response type for the Graph method.
+        type GraphStream: futures_core::Stream<
+                Item = Result<super::GraphResponse, tonic::Status>,
+            >
+            + Send
+            + 'static;
+        /// graph(filters) -> stream of nodes, then edges
+        async fn graph(
+            &self,
+            request: tonic::Request<super::GraphRequest>,
+        ) -> Result<tonic::Response<Self::GraphStream>, tonic::Status>;
+        /// attach(note_id, name, stream of data) -> Attachment
+        async fn attach(
+            &self,
+            request: tonic::Request<tonic::Streaming<super::AttachRequest>>,
+        ) -> Result<tonic::Response<super::AttachResponse>, tonic::Status>;
+        /// attachments(note_id) -> Vec<Attachment>
+        async fn attachments(
+            &self,
+            request: tonic::Request<super::AttachmentsRequest>,
+        ) -> Result<tonic::Response<super::AttachmentsResponse>, tonic::Status>;
+        /// Server streaming response type for the Fetch method.
+        type FetchStream: futures_core::Stream<
+                Item = Result<super::FetchResponse, tonic::Status>,
+            >
+            + Send
+            + 'static;
+        /// fetch(note_id, name) -> Attachment, then stream of data
+        async fn fetch(
+            &self,
+            request: tonic::Request<super::FetchRequest>,
+        ) -> Result<tonic::Response<Self::FetchStream>, tonic::Status>;
+        /// create_template(Template) -> Template, fails if the name is taken
+        async fn create_template(
+            &self,
+            request: tonic::Request<super::CreateTemplateRequest>,
+        ) -> Result<tonic::Response<super::CreateTemplateResponse>, tonic::Status>;
+        /// get_template(name) -> Template
+        async fn get_template(
+            &self,
+            request: tonic::Request<super::GetTemplateRequest>,
+        ) -> Result<tonic::Response<super::GetTemplateResponse>, tonic::Status>;
+        /// list_templates() -> Vec<Template>, by name
+        async fn list_templates(
+            &self,
+            request: tonic::Request<super::ListTemplatesRequest>,
+        ) -> Result<tonic::Response<super::ListTemplatesResponse>, tonic::Status>;
+        /// update_template(Template) -> Template, fails if there is none by the name
+        async fn update_template(
+            &self,
+            request: tonic::Request<super::UpdateTemplateRequest>,
+        ) -> Result<tonic::Response<super::UpdateTemplateResponse>, tonic::Status>;
+        /// delete_template(name) -> Template
+        async fn delete_template(
+            &self,
+            request: tonic::Request<super::DeleteTemplateRequest>,
+        ) -> Result<tonic::Response<super::DeleteTemplateResponse>, tonic::Status>;
+    }
+    #[derive(Debug)]
+    pub struct ApiServer<T: Api> {
+        inner: _Inner<T>,
+        accept_compression_encodings: EnabledCompressionEncodings,
+        send_compression_encodings: EnabledCompressionEncodings,
+    }
+    struct _Inner<T>(Arc<T>);
+    impl<T: Api> ApiServer<T> {
+        pub fn new(inner: T) -> Self {
+            Self::from_arc(Arc::new(inner))
+        }
+        pub fn from_arc(inner: Arc<T>) -> Self {
+            let inner = _Inner(inner);
+            Self {
+                inner,
+                accept_compression_encodings: Default::default(),
+                send_compression_encodings: Default::default(),
+            }
+        }
+        pub fn with_interceptor<F>(
+            inner: T,
+            interceptor: F,
+        ) -> InterceptedService<Self, F>
+        where
             F: tonic::service::Interceptor,
         {
             InterceptedService::new(Self::new(inner), interceptor)
@@ -363,6 +1217,543 @@ pub mod api_server {
                     };
                     Box::pin(fut)
                 }
+                "/bpp.Api/Export" => {
+                    #[allow(non_camel_case_types)]
+                    struct ExportSvc<T: Api>(pub Arc<T>);
+                    impl<
+                        T: Api,
+                    > tonic::server::ServerStreamingService<super::ExportRequest>
+                    for ExportSvc<T> {
+                        type Response = super::ExportResponse;
+                        type ResponseStream = T::ExportStream;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::ResponseStream>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ExportRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).export(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = ExportSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.server_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/bpp.Api/Import" => {
+                    #[allow(non_camel_case_types)]
+                    struct ImportSvc<T: Api>(pub Arc<T>);
+                    impl<
+                        T: Api,
+                    > tonic::server::ClientStreamingService<super::ImportRequest>
+                    for ImportSvc<T> {
+                        type Response = super::ImportResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<
+                                tonic::Streaming<super::ImportRequest>,
+                            >,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).import(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = ImportSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.client_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/bpp.Api/AuditLog" => {
+                    #[allow(non_camel_case_types)]
+                    struct AuditLogSvc<T: Api>(pub Arc<T>);
+                    impl<T: Api> tonic::server::UnaryService<super::AuditLogRequest>
+                    for AuditLogSvc<T> {
+                        type Response = super::AuditLogResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::AuditLogRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).audit_log(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = AuditLogSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/bpp.Api/Links" => {
+                    #[allow(non_camel_case_types)]
+                    struct LinksSvc<T: Api>(pub Arc<T>);
+                    impl<T: Api> tonic::server::UnaryService<super::LinksRequest>
+                    for LinksSvc<T> {
+                        type Response = super::LinksResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::LinksRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).links(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = LinksSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/bpp.Api/Backlinks" => {
+                    #[allow(non_camel_case_types)]
+                    struct BacklinksSvc<T: Api>(pub Arc<T>);
+                    impl<T: Api> tonic::server::UnaryService<super::BacklinksRequest>
+                    for BacklinksSvc<T> {
+                        type Response = super::BacklinksResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::BacklinksRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).backlinks(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = BacklinksSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/bpp.Api/Graph" => {
+                    #[allow(non_camel_case_types)]
+                    struct GraphSvc<T: Api>(pub Arc<T>);
+                    impl<
+                        T: Api,
+                    > tonic::server::ServerStreamingService<super::GraphRequest>
+                    for GraphSvc<T> {
+                        type Response = super::GraphResponse;
+                        type ResponseStream = T::GraphStream;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::ResponseStream>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::GraphRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).graph(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = GraphSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.server_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/bpp.Api/Attach" => {
+                    #[allow(non_camel_case_types)]
+                    struct AttachSvc<T: Api>(pub Arc<T>);
+                    impl<
+                        T: Api,
+                    > tonic::server::ClientStreamingService<super::AttachRequest>
+                    for AttachSvc<T> {
+                        type Response = super::AttachResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<
+                                tonic::Streaming<super::AttachRequest>,
+                            >,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).attach(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = AttachSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.client_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/bpp.Api/Attachments" => {
+                    #[allow(non_camel_case_types)]
+                    struct AttachmentsSvc<T: Api>(pub Arc<T>);
+                    impl<T: Api> tonic::server::UnaryService<super::AttachmentsRequest>
+                    for AttachmentsSvc<T> {
+                        type Response = super::AttachmentsResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::AttachmentsRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).attachments(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = AttachmentsSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/bpp.Api/Fetch" => {
+                    #[allow(non_camel_case_types)]
+                    struct FetchSvc<T: Api>(pub Arc<T>);
+                    impl<
+                        T: Api,
+                    > tonic::server::ServerStreamingService<super::FetchRequest>
+                    for FetchSvc<T> {
+                        type Response = super::FetchResponse;
+                        type ResponseStream = T::FetchStream;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::ResponseStream>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::FetchRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).fetch(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = FetchSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.server_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/bpp.Api/CreateTemplate" => {
+                    #[allow(non_camel_case_types)]
+                    struct CreateTemplateSvc<T: Api>(pub Arc<T>);
+                    impl<
+                        T: Api,
+                    > tonic::server::UnaryService<super::CreateTemplateRequest>
+                    for CreateTemplateSvc<T> {
+                        type Response = super::CreateTemplateResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::CreateTemplateRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move {
+                                (*inner).create_template(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = CreateTemplateSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/bpp.Api/GetTemplate" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetTemplateSvc<T: Api>(pub Arc<T>);
+                    impl<T: Api> tonic::server::UnaryService<super::GetTemplateRequest>
+                    for GetTemplateSvc<T> {
+                        type Response = super::GetTemplateResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::GetTemplateRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move {
+                                (*inner).get_template(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = GetTemplateSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/bpp.Api/ListTemplates" => {
+                    #[allow(non_camel_case_types)]
+                    struct ListTemplatesSvc<T: Api>(pub Arc<T>);
+                    impl<T: Api> tonic::server::UnaryService<super::ListTemplatesRequest>
+                    for ListTemplatesSvc<T> {
+                        type Response = super::ListTemplatesResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ListTemplatesRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move {
+                                (*inner).list_templates(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = ListTemplatesSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/bpp.Api/UpdateTemplate" => {
+                    #[allow(non_camel_case_types)]
+                    struct UpdateTemplateSvc<T: Api>(pub Arc<T>);
+                    impl<
+                        T: Api,
+                    > tonic::server::UnaryService<super::UpdateTemplateRequest>
+                    for UpdateTemplateSvc<T> {
+                        type Response = super::UpdateTemplateResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::UpdateTemplateRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move {
+                                (*inner).update_template(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = UpdateTemplateSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/bpp.Api/DeleteTemplate" => {
+                    #[allow(non_camel_case_types)]
+                    struct DeleteTemplateSvc<T: Api>(pub Arc<T>);
+                    impl<
+                        T: Api,
+                    > tonic::server::UnaryService<super::DeleteTemplateRequest>
+                    for DeleteTemplateSvc<T> {
+                        type Response = super::DeleteTemplateResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::DeleteTemplateRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move {
+                                (*inner).delete_template(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = DeleteTemplateSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
                 _ => {
                     Box::pin(async move {
                         Ok(
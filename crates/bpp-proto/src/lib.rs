@@ -3,6 +3,39 @@ use std::fmt::{Display, Formatter};
 
 pub mod bpp;
 
+/// Metadata key carrying the id of a request. Set by the client so that
+/// client and server logs can be matched
+pub static REQUEST_ID_KEY: &str = "x-request-id";
+
+/// Metadata key carrying the name of the user running the client. It is
+/// recorded in the audit log as is, nothing authenticates it
+pub static ACTOR_KEY: &str = "x-bpp-actor";
+
+/// Metadata key set on failed rpcs, with the machine readable cause of the
+/// error, eg. `TEMPLATE_NOT_FOUND`
+pub static ERROR_REASON_KEY: &str = "x-bpp-error-reason";
+
+/// Metadata key naming the kind of resource a failed rpc was about: `note`,
+/// `attachment` or `template`
+pub static RESOURCE_TYPE_KEY: &str = "x-bpp-resource-type";
+
+/// Binary metadata key carrying the id or name of the resource a failed rpc
+/// was about. Binary since template names are not limited to ascii
+pub static RESOURCE_NAME_KEY: &str = "x-bpp-resource-name-bin";
+
+/// Metadata key set on rate limited and unavailable responses, with the
+/// delay before retrying in whole seconds
+pub static RETRY_AFTER_KEY: &str = "retry-after";
+
+/// Version of the export formats written by `bpp export` and
+/// `bpp-server export`. Increased whenever a change would prevent an older
+/// export from being imported as is
+pub const EXPORT_VERSION: u32 = 1;
+
+/// Encoded descriptors of the `bpp` package, served by the reflection service
+pub static FILE_DESCRIPTOR_SET: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/bpp_descriptor.bin"));
+
 impl Display for Note {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_str(format!("{:}: {:}", self.title, self.content).as_str())
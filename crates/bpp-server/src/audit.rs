@@ -0,0 +1,277 @@
+use crate::dao::{unix_millis, StoredNote};
+use crate::error_def::BppStoreError;
+use crate::keys::Keyring;
+use bpp_proto::bpp::{AuditAction, AuditEvent, AuditLogRequest};
+use bpp_proto::{ACTOR_KEY, REQUEST_ID_KEY};
+use error_stack::{IntoReport, Result, ResultExt};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tonic::Request;
+use tracing::warn;
+
+pub static AUDIT_FILE: &str = "audit.jsonl";
+/// Events returned by a query that does not set a limit
+pub const DEFAULT_LIMIT: usize = 100;
+pub const MAX_LIMIT: usize = 10_000;
+
+/// Kind of mutation recorded, kept apart from the protobuf enum so the
+/// file stays readable
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditKind {
+    Add,
+    Rm,
+    Import,
+    Restore,
+    Attach,
+}
+
+impl From<AuditKind> for AuditAction {
+    fn from(kind: AuditKind) -> Self {
+        match kind {
+            AuditKind::Add => AuditAction::Add,
+            AuditKind::Rm => AuditAction::Rm,
+            AuditKind::Import => AuditAction::Import,
+            AuditKind::Restore => AuditAction::Restore,
+            AuditKind::Attach => AuditAction::Attach,
+        }
+    }
+}
+
+/// Who sent an rpc, see `Actor::from_request`
+#[derive(Debug, Clone, Default)]
+pub struct Actor {
+    pub name: String,
+    pub peer: String,
+    pub request_id: String,
+}
+
+impl Actor {
+    /// The name and request id are sent by the client as metadata, the
+    /// peer is the address of the connection
+    pub fn from_request<T>(request: &Request<T>) -> Self {
+        let metadata = |key| {
+            request
+                .metadata()
+                .get(key)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_string()
+        };
+        Actor {
+            name: metadata(ACTOR_KEY),
+            peer: request
+                .remote_addr()
+                .map(|addr| addr.ip().to_string())
+                .unwrap_or_default(),
+            request_id: metadata(REQUEST_ID_KEY),
+        }
+    }
+
+    /// The user running a `bpp-server` subcommand
+    pub fn local() -> Self {
+        Actor {
+            name: env::var("USER")
+                .or_else(|_| env::var("LOGNAME"))
+                .unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+}
+
+/// One line of the audit file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub timestamp_ms: i64,
+    pub action: AuditKind,
+    pub note_id: String,
+    pub note_title: String,
+    pub actor: String,
+    pub peer: String,
+    pub request_id: String,
+    /// What the action applied to beyond the note, see `AuditEvent`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub detail: String,
+}
+
+impl AuditRecord {
+    pub fn new(action: AuditKind, note: &StoredNote, actor: &Actor) -> Self {
+        AuditRecord {
+            timestamp_ms: unix_millis(),
+            action,
+            note_id: note.id.clone(),
+            note_title: note.title.clone(),
+            actor: actor.name.clone(),
+            peer: actor.peer.clone(),
+            request_id: actor.request_id.clone(),
+            detail: String::new(),
+        }
+    }
+
+    /// An action on the whole store rather than a note, eg. a restore
+    pub fn store(action: AuditKind, actor: &Actor) -> Self {
+        AuditRecord {
+            timestamp_ms: unix_millis(),
+            action,
+            note_id: String::new(),
+            note_title: String::new(),
+            actor: actor.name.clone(),
+            peer: actor.peer.clone(),
+            request_id: actor.request_id.clone(),
+            detail: String::new(),
+        }
+    }
+
+    pub fn with_detail(mut self, detail: &str) -> Self {
+        self.detail = detail.to_string();
+        self
+    }
+
+    fn matches(&self, request: &AuditLogRequest) -> bool {
+        (request.note_id.is_empty() || request.note_id == self.note_id)
+            && (request.action == AuditAction::Unspecified as i32
+                || request.action == AuditAction::from(self.action) as i32)
+            && (request.actor.is_empty() || request.actor == self.actor)
+            && (request.since_ms == 0 || self.timestamp_ms >= request.since_ms)
+            && (request.until_ms == 0 || self.timestamp_ms <= request.until_ms)
+    }
+}
+
+impl From<AuditRecord> for AuditEvent {
+    fn from(record: AuditRecord) -> Self {
+        AuditEvent {
+            timestamp_ms: record.timestamp_ms,
+            action: AuditAction::from(record.action) as i32,
+            note_id: record.note_id,
+            note_title: record.note_title,
+            actor: record.actor,
+            peer: record.peer,
+            request_id: record.request_id,
+            detail: record.detail,
+        }
+    }
+}
+
+/// Append only log of note mutations, one json record per line in
+/// `audit.jsonl` next to the notes. It is never rewritten, so events
+/// outlive the notes they are about. Note titles are sealed when the store
+/// is encrypted
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    file: Mutex<File>,
+    keys: Option<Arc<Keyring>>,
+}
+
+impl AuditLog {
+    /// The audit log of `data_dir`, see `NoteDao::keys` for `keys`
+    pub fn open(data_dir: &Path, keys: Option<Arc<Keyring>>) -> Result<Self, BppStoreError> {
+        let path = data_dir.join(AUDIT_FILE);
+        let open = || -> std::io::Result<File> {
+            fs::create_dir_all(data_dir)?;
+            let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+            // Terminate a line left incomplete by a crash, so it does not
+            // swallow the next record
+            let contents = fs::read(&path)?;
+            if contents.last().is_some_and(|last| *last != b'\n') {
+                file.write_all(b"\n")?;
+            }
+            Ok(file)
+        };
+        let file = open().into_report().change_context_lazy(|| {
+            BppStoreError::FailedToOpenStore(format!("Could not open {}", path.display()))
+        })?;
+        Ok(AuditLog {
+            path,
+            file: Mutex::new(file),
+            keys,
+        })
+    }
+
+    /// Append `record` and sync it to disk
+    pub fn record(&self, record: &AuditRecord) -> Result<(), BppStoreError> {
+        self.record_all(std::slice::from_ref(record))
+    }
+
+    /// Append `records` and sync them to disk once
+    pub fn record_all(&self, records: &[AuditRecord]) -> Result<(), BppStoreError> {
+        let mut lines = vec![];
+        for record in records {
+            let mut record = record.clone();
+            if let Some(keys) = &self.keys {
+                record.note_title = keys.seal(record.note_title.as_bytes(), &title_aad(&record))?;
+            }
+            serde_json::to_writer(&mut lines, &record)
+                .into_report()
+                .change_context(BppStoreError::FailedToWriteStore(
+                    "Could not serialize audit record".to_string(),
+                ))?;
+            lines.push(b'\n');
+        }
+
+        let mut file = self.lock();
+        file.write_all(&lines)
+            .and_then(|_| file.sync_data())
+            .into_report()
+            .change_context_lazy(|| {
+                BppStoreError::FailedToWriteStore(format!(
+                    "Could not write {}",
+                    self.path.display()
+                ))
+            })
+    }
+
+    /// The most recent records matching `request`, oldest first. At most
+    /// `request.limit` records are returned, `DEFAULT_LIMIT` if unset
+    pub fn query(&self, request: &AuditLogRequest) -> Result<Vec<AuditRecord>, BppStoreError> {
+        let limit = match request.limit as usize {
+            0 => DEFAULT_LIMIT,
+            limit => limit.min(MAX_LIMIT),
+        };
+
+        // Hold the lock so no record is read while half written
+        let _file = self.lock();
+        let reader = File::open(&self.path)
+            .map(BufReader::new)
+            .into_report()
+            .change_context_lazy(|| {
+                BppStoreError::FailedToOpenStore(format!("Could not read {}", self.path.display()))
+            })?;
+
+        let mut records = vec![];
+        for (number, line) in reader.lines().enumerate() {
+            let line = line.into_report().change_context_lazy(|| {
+                BppStoreError::FailedToOpenStore(format!("Could not read {}", self.path.display()))
+            })?;
+            match serde_json::from_str::<AuditRecord>(&line) {
+                Ok(record) if record.matches(request) => records.push(record),
+                Ok(_) => {}
+                // A crash can leave the last line incomplete
+                Err(err) => warn!("Skipping line {} of {AUDIT_FILE}: {err}", number + 1),
+            }
+        }
+
+        let skip = records.len().saturating_sub(limit);
+        let mut records = records.split_off(skip);
+        if let Some(keys) = &self.keys {
+            for record in &mut records {
+                record.note_title = keys.open_str(&record.note_title, &title_aad(record))?;
+            }
+        }
+        Ok(records)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, File> {
+        self.file.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Authenticated with the sealed title of `record`, so it cannot be moved
+/// to another record
+fn title_aad(record: &AuditRecord) -> String {
+    format!("audit {} {}", record.timestamp_ms, record.note_id)
+}
@@ -0,0 +1,601 @@
+use crate::audit::AUDIT_FILE;
+use crate::blobs::{is_digest, BLOBS_DIR};
+use crate::config::Config;
+use crate::dao::{unix_millis, NoteDao, StoredNote};
+use crate::error_def::BppStoreError;
+use crate::extract::TEXTS_DIR;
+use crate::keys::KEYS_FILE;
+use crate::templates::TEMPLATES_FILE;
+use error_stack::{report, IntoReport, Result, ResultExt};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use humantime::Rfc3339Timestamp;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+
+/// Version of the backup file format. Version 1 backups only hold notes
+pub const BACKUP_VERSION: u32 = 2;
+
+static FILE_PREFIX: &str = "bpp-backup-";
+static FILE_EXTENSION: &str = ".json.gz";
+
+/// First line of a backup
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackupHeader {
+    pub version: u32,
+    /// `STORE_VERSION` of the server that took the backup
+    pub store_version: u32,
+    pub created_ms: i64,
+    pub note_count: usize,
+    /// Hex encoded sha256 of the notes, as written after the header
+    pub sha256: String,
+    /// Size of the notes json. The notes take the rest of version 1 backups
+    #[serde(default)]
+    pub notes_size: u64,
+    /// Files of the data directory, written after the notes in this order
+    #[serde(default)]
+    pub files: Vec<BackupFile>,
+}
+
+/// A file of the data directory in a backup. Its data is followed by its
+/// sha256, 32 raw bytes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackupFile {
+    /// Relative to the data directory, `/` separated
+    pub path: String,
+    pub size: u64,
+}
+
+/// A backup read back by `read`, checksums verified
+#[derive(Debug)]
+pub struct Backup {
+    pub path: PathBuf,
+    pub header: BackupHeader,
+    pub notes: Vec<StoredNote>,
+}
+
+/// Outcome of `NoteDao::restore`
+#[derive(Debug)]
+pub struct Restored {
+    pub notes: usize,
+    /// Files of the backup written to the data directory, see `restore_files`
+    pub files: usize,
+    /// Digests of attachments the restored notes refer to that are neither
+    /// in the backup nor in the data directory
+    pub missing_blobs: Vec<String>,
+}
+
+/// Files of the data directory to back up, linked or copied into a
+/// directory of their own so they are neither removed nor modified while
+/// the backup is written. The directory is removed on drop
+#[derive(Debug)]
+pub struct Snapshot {
+    dir: PathBuf,
+    files: Vec<(BackupFile, PathBuf)>,
+}
+
+impl Snapshot {
+    pub fn new(dir: PathBuf) -> Result<Self, BppStoreError> {
+        fs::create_dir_all(&dir)
+            .into_report()
+            .change_context_lazy(|| {
+                BppStoreError::FailedToWriteStore(format!("Could not create {}", dir.display()))
+            })?;
+        Ok(Snapshot { dir, files: vec![] })
+    }
+
+    /// Add `path` of `data_dir`, a file that is replaced but never modified
+    /// in place. It is hard linked, copied where links are not supported
+    pub fn link(&mut self, data_dir: &Path, path: &Path) -> Result<(), BppStoreError> {
+        self.add(data_dir, path, |target| {
+            fs::hard_link(path, target).or_else(|_| fs::copy(path, target).map(drop))
+        })
+    }
+
+    /// Add `path` of `data_dir`, copied as it may be appended to
+    pub fn copy(&mut self, data_dir: &Path, path: &Path) -> Result<(), BppStoreError> {
+        self.add(data_dir, path, |target| fs::copy(path, target).map(drop))
+    }
+
+    fn add(
+        &mut self,
+        data_dir: &Path,
+        path: &Path,
+        add: impl FnOnce(&Path) -> io::Result<()>,
+    ) -> Result<(), BppStoreError> {
+        let relative = path
+            .strip_prefix(data_dir)
+            .ok()
+            .and_then(|relative| {
+                relative
+                    .components()
+                    .map(|component| component.as_os_str().to_str())
+                    .collect::<Option<Vec<_>>>()
+            })
+            .map(|components| components.join("/"))
+            .ok_or_else(|| {
+                report!(BppStoreError::FailedToWriteStore(format!(
+                    "{} is not in {}",
+                    path.display(),
+                    data_dir.display()
+                )))
+            })?;
+        let target = self.dir.join(self.files.len().to_string());
+        let size = add(&target)
+            .and_then(|_| fs::metadata(&target))
+            .into_report()
+            .change_context_lazy(|| {
+                BppStoreError::FailedToWriteStore(format!("Could not back up {}", path.display()))
+            })?
+            .len();
+        self.files.push((
+            BackupFile {
+                path: relative,
+                size,
+            },
+            target,
+        ));
+        Ok(())
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_dir_all(&self.dir) {
+            warn!("Could not remove {}: {err}", self.dir.display());
+        }
+    }
+}
+
+/// Write a gzip compressed `BackupHeader` line to `writer`, followed by the
+/// notes as a json array and the files of `snapshot`. Returns the header
+pub fn encode(
+    notes: &[StoredNote],
+    snapshot: &Snapshot,
+    created_ms: i64,
+    writer: impl Write,
+) -> Result<BackupHeader, BppStoreError> {
+    let notes_json = serde_json::to_vec(notes).into_report().change_context(
+        BppStoreError::FailedToWriteStore("Could not serialize notes".to_string()),
+    )?;
+    let header = BackupHeader {
+        version: BACKUP_VERSION,
+        store_version: crate::dao::STORE_VERSION,
+        created_ms,
+        note_count: notes.len(),
+        sha256: sha256(&notes_json),
+        notes_size: notes_json.len() as u64,
+        files: snapshot
+            .files
+            .iter()
+            .map(|(file, _)| file.clone())
+            .collect(),
+    };
+    let mut header_json = serde_json::to_vec(&header).into_report().change_context(
+        BppStoreError::FailedToWriteStore("Could not serialize the backup header".to_string()),
+    )?;
+    header_json.push(b'\n');
+
+    let mut encoder = GzEncoder::new(writer, Compression::default());
+    let mut write = || -> io::Result<()> {
+        encoder.write_all(&header_json)?;
+        encoder.write_all(&notes_json)?;
+        for (file, path) in &snapshot.files {
+            let mut hashing = HashingWriter::new(&mut encoder);
+            let copied = io::copy(&mut fs::File::open(path)?, &mut hashing)?;
+            if copied != file.size {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("{} changed while backed up", file.path),
+                ));
+            }
+            let digest = hashing.hasher.finalize();
+            encoder.write_all(&digest)?;
+        }
+        encoder.try_finish()
+    };
+    write()
+        .map(|_| header)
+        .into_report()
+        .change_context(BppStoreError::FailedToWriteStore(
+            "Could not write the backup".to_string(),
+        ))
+}
+
+/// Read and decompress a backup, checking its version and the checksums of
+/// the notes and files
+pub fn read(path: &Path) -> Result<Backup, BppStoreError> {
+    let mut reader = BufReader::new(open(path)?);
+    let header = read_header_line(path, &mut reader)?;
+    let notes = read_notes(path, &header, &mut reader)?;
+    for file in &header.files {
+        check_path(path, file)?;
+        copy_file(path, file, &mut reader, io::sink())?;
+    }
+    let mut rest = [0; 1];
+    let trailing = reader
+        .read(&mut rest)
+        .into_report()
+        .change_context_lazy(|| invalid(path, "could not be decompressed"))?;
+    if trailing != 0 {
+        return Err(report!(invalid(path, "has data after its last file")));
+    }
+    Ok(Backup {
+        path: path.to_path_buf(),
+        header,
+        notes,
+    })
+}
+
+/// The data of the file `name` of `backup`, its checksum verified. None
+/// when the backup does not hold it
+pub fn read_file(backup: &Backup, name: &str) -> Result<Option<Vec<u8>>, BppStoreError> {
+    let path = backup.path.as_path();
+    let mut reader = BufReader::new(open(path)?);
+    let header = read_header_line(path, &mut reader)?;
+    read_notes(path, &header, &mut reader)?;
+
+    for file in &header.files {
+        if file.path == name {
+            let mut data = vec![];
+            copy_file(path, file, &mut reader, &mut data)?;
+            return Ok(Some(data));
+        }
+        copy_file(path, file, &mut reader, io::sink())?;
+    }
+    Ok(None)
+}
+
+/// Write the files of `backup` to `data_dir`. Attachments and their texts
+/// are only written when missing, being content addressed. The templates
+/// are replaced. The audit log is only written when there is none, so no
+/// event recorded since the backup is lost. The keys are not written, they
+/// are merged into the keys of the store by `NoteDao::restore`. Returns
+/// the number of files written
+pub fn restore_files(backup: &Backup, data_dir: &Path) -> Result<usize, BppStoreError> {
+    let path = backup.path.as_path();
+    let mut reader = BufReader::new(open(path)?);
+    let header = read_header_line(path, &mut reader)?;
+    read_notes(path, &header, &mut reader)?;
+
+    let mut written = 0;
+    for file in &header.files {
+        let target = data_dir.join(check_path(path, file)?);
+        if file.path == KEYS_FILE || (target.exists() && file.path != TEMPLATES_FILE) {
+            copy_file(path, file, &mut reader, io::sink())?;
+            continue;
+        }
+
+        let not_written =
+            || BppStoreError::FailedToWriteStore(format!("Could not write {}", target.display()));
+        if let Some(dir) = target.parent() {
+            fs::create_dir_all(dir)
+                .into_report()
+                .change_context_lazy(not_written)?;
+        }
+        let tmp_path = target.with_extension("restore");
+        let mut writer = fs::File::create(&tmp_path)
+            .map(BufWriter::new)
+            .into_report()
+            .change_context_lazy(not_written)?;
+        let copied = copy_file(path, file, &mut reader, &mut writer).and_then(|_| {
+            writer
+                .into_inner()
+                .map_err(io::IntoInnerError::into_error)
+                .and_then(|file| file.sync_all())
+                .and_then(|_| fs::rename(&tmp_path, &target))
+                .into_report()
+                .change_context_lazy(not_written)
+        });
+        if let Err(err) = copied {
+            fs::remove_file(&tmp_path).ok();
+            return Err(err);
+        }
+        written += 1;
+    }
+    Ok(written)
+}
+
+/// The path of `file` relative to the data directory. Only the files a
+/// backup is taken of are accepted, so a crafted backup cannot write
+/// anywhere else
+fn check_path<'a>(path: &Path, file: &'a BackupFile) -> Result<&'a Path, BppStoreError> {
+    let valid = match file.path.split('/').collect::<Vec<_>>().as_slice() {
+        [name] => *name == TEMPLATES_FILE || *name == AUDIT_FILE || *name == KEYS_FILE,
+        [dir, name] => *dir == TEXTS_DIR && name.strip_suffix(".txt").is_some_and(is_digest),
+        [dir, prefix, digest] => {
+            *dir == BLOBS_DIR && is_digest(digest) && digest.get(..2) == Some(*prefix)
+        }
+        _ => false,
+    };
+    if !valid {
+        return Err(report!(invalid(
+            path,
+            &format!("holds {}, not a file of a data directory", file.path)
+        )));
+    }
+    Ok(Path::new(&file.path))
+}
+
+/// Copy the data of `file` from `reader` to `writer`, checking it against
+/// its sha256. Blobs are also checked against their digest
+fn copy_file(
+    path: &Path,
+    file: &BackupFile,
+    reader: &mut impl Read,
+    writer: impl Write,
+) -> Result<(), BppStoreError> {
+    let mut hashing = HashingWriter::new(writer);
+    let mut expected = [0; 32];
+    let copied = io::copy(&mut reader.by_ref().take(file.size), &mut hashing)
+        .and_then(|copied| reader.read_exact(&mut expected).map(|_| copied))
+        .into_report()
+        .change_context_lazy(|| invalid(path, &format!("could not be read at {}", file.path)))?;
+    let digest = hashing.hasher.finalize();
+    let is_blob = file.path.starts_with(&format!("{BLOBS_DIR}/"));
+    if copied != file.size
+        || digest[..] != expected
+        || (is_blob && !file.path.ends_with(&format!("{digest:x}")))
+    {
+        return Err(report!(invalid(
+            path,
+            &format!("{} does not match its checksum", file.path)
+        )));
+    }
+    Ok(())
+}
+
+/// Writes to `inner`, hashing what was written
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        HashingWriter {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn read_notes(
+    path: &Path,
+    header: &BackupHeader,
+    reader: &mut impl Read,
+) -> Result<Vec<StoredNote>, BppStoreError> {
+    let mut notes_json = vec![];
+    if header.version < 2 {
+        reader.read_to_end(&mut notes_json)
+    } else {
+        reader
+            .by_ref()
+            .take(header.notes_size)
+            .read_to_end(&mut notes_json)
+    }
+    .into_report()
+    .change_context_lazy(|| invalid(path, "could not be decompressed"))?;
+    if sha256(&notes_json) != header.sha256 {
+        return Err(report!(invalid(path, "does not match its checksum")));
+    }
+
+    let notes: Vec<StoredNote> = serde_json::from_slice(&notes_json)
+        .into_report()
+        .change_context_lazy(|| invalid(path, "notes are corrupted"))?;
+    if notes.len() != header.note_count {
+        return Err(report!(invalid(
+            path,
+            &format!(
+                "holds {} notes, the header announces {}",
+                notes.len(),
+                header.note_count
+            )
+        )));
+    }
+    Ok(notes)
+}
+
+fn read_header_line(path: &Path, reader: &mut impl BufRead) -> Result<BackupHeader, BppStoreError> {
+    let mut line = vec![];
+    reader
+        .read_until(b'\n', &mut line)
+        .into_report()
+        .change_context_lazy(|| invalid(path, "could not be decompressed"))?;
+    if !line.ends_with(b"\n") {
+        return Err(report!(invalid(path, "has no header")));
+    }
+    parse_header(path, &line[..line.len() - 1])
+}
+
+/// Only decompress the header of a backup. The checksum is not verified
+pub fn read_header(path: &Path) -> Result<BackupHeader, BppStoreError> {
+    read_header_line(path, &mut BufReader::new(open(path)?))
+}
+
+/// Backups of `dir`, oldest first. Files that are not backups are ignored
+/// and unreadable backups are skipped with a warning
+pub fn list(dir: &Path) -> Result<Vec<(PathBuf, BackupHeader)>, BppStoreError> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let entries = fs::read_dir(dir).into_report().change_context_lazy(|| {
+        BppStoreError::FailedToOpenStore(format!("Could not list {}", dir.display()))
+    })?;
+
+    let mut backups = vec![];
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.starts_with(FILE_PREFIX) || !name.ends_with(FILE_EXTENSION) {
+            continue;
+        }
+        match read_header(&path) {
+            Ok(header) => backups.push((path, header)),
+            Err(err) => warn!("Skipping {}\n{:?}", path.display(), err),
+        }
+    }
+    backups.sort_by(|(a_path, a), (b_path, b)| {
+        a.created_ms
+            .cmp(&b.created_ms)
+            .then_with(|| a_path.cmp(b_path))
+    });
+    Ok(backups)
+}
+
+/// The most recent backup of `dir` taken at or before `time_ms`
+pub fn latest_before(
+    dir: &Path,
+    time_ms: i64,
+) -> Result<Option<(PathBuf, BackupHeader)>, BppStoreError> {
+    Ok(list(dir)?
+        .into_iter()
+        .rev()
+        .find(|(_, header)| header.created_ms <= time_ms))
+}
+
+/// Remove all but the `keep` most recent backups of `dir`.
+/// Returns the removed files
+pub fn prune(dir: &Path, keep: usize) -> Result<Vec<PathBuf>, BppStoreError> {
+    let backups = list(dir)?;
+    let excess = backups.len().saturating_sub(keep);
+    let mut removed = vec![];
+    for (path, _) in backups.into_iter().take(excess) {
+        fs::remove_file(&path)
+            .into_report()
+            .change_context_lazy(|| {
+                BppStoreError::FailedToWriteStore(format!("Could not remove {}", path.display()))
+            })?;
+        removed.push(path);
+    }
+    Ok(removed)
+}
+
+/// `bpp-backup-<utc time>.json.gz`, eg. bpp-backup-20230131T104500.123Z.json.gz
+/// so names sort in the order backups were taken
+pub fn file_name(created_ms: i64) -> String {
+    let time: String = timestamp(created_ms)
+        .to_string()
+        .chars()
+        .filter(|c| *c != '-' && *c != ':')
+        .collect();
+    format!("{FILE_PREFIX}{time}{FILE_EXTENSION}")
+}
+
+/// Back up the store every `backup.interval_secs` into `backup.dir` and
+/// prune it down to `backup.keep` backups. The first backup is taken one
+/// interval after start. The settings are read from `config` before each
+/// backup, a new interval counting from the previous backup. Failures are
+/// logged and retried at the next interval
+pub async fn schedule(dao: Arc<NoteDao>, mut config: watch::Receiver<Arc<Config>>) {
+    let mut last = Instant::now();
+    loop {
+        let (interval, dir, keep) = {
+            let config = config.borrow();
+            (
+                config.backup.interval(),
+                config.backup_dir(),
+                config.backup.keep,
+            )
+        };
+        tokio::select! {
+            _ = due(interval.map(|interval| last + interval)) => {}
+            changed = config.changed() => match changed {
+                Ok(()) => continue,
+                // The server is stopping
+                Err(_) => return,
+            },
+        }
+
+        last = Instant::now();
+        let dao = dao.clone();
+        let dir = dir.clone();
+        let backup = tokio::task::spawn_blocking(move || {
+            let path = dir.join(file_name(unix_millis()));
+            let header = dao.backup(&path)?;
+            let removed = prune(&dir, keep)?;
+            Ok::<_, error_stack::Report<BppStoreError>>((path, header, removed))
+        })
+        .await;
+
+        match backup {
+            Ok(Ok((path, header, removed))) => {
+                info!(
+                    notes = header.note_count,
+                    pruned = removed.len(),
+                    "Backed up the store to {}",
+                    path.display()
+                );
+            }
+            Ok(Err(err)) => error!("Scheduled backup failed\n{:?}", err),
+            Err(err) => error!("Scheduled backup failed: {err}"),
+        }
+    }
+}
+
+/// Completes at `deadline`, never when scheduled backups are disabled
+async fn due(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Unix time in milliseconds as an rfc3339 utc time
+pub fn timestamp(time_ms: i64) -> Rfc3339Timestamp {
+    humantime::format_rfc3339_millis(UNIX_EPOCH + Duration::from_millis(time_ms.max(0) as u64))
+}
+
+fn open(path: &Path) -> Result<GzDecoder<fs::File>, BppStoreError> {
+    fs::File::open(path)
+        .map(GzDecoder::new)
+        .into_report()
+        .change_context_lazy(|| {
+            BppStoreError::FailedToOpenStore(format!("Could not open {}", path.display()))
+        })
+}
+
+fn parse_header(path: &Path, line: &[u8]) -> Result<BackupHeader, BppStoreError> {
+    let header: BackupHeader = serde_json::from_slice(line)
+        .into_report()
+        .change_context_lazy(|| invalid(path, "is not a bpp backup"))?;
+    if header.version > BACKUP_VERSION {
+        return Err(report!(invalid(
+            path,
+            &format!(
+                "is at version {}, newer than this server supports",
+                header.version
+            )
+        )));
+    }
+    Ok(header)
+}
+
+fn invalid(path: &Path, reason: &str) -> BppStoreError {
+    BppStoreError::InvalidBackup(format!("{} {reason}", path.display()))
+}
+
+fn sha256(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
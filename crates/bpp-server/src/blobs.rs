@@ -0,0 +1,237 @@
+use crate::dao::DataDirLock;
+use crate::error_def::BppStoreError;
+use error_stack::{report, IntoReport, Result, ResultExt};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use uuid::Uuid;
+
+pub static BLOBS_DIR: &str = "blobs";
+/// Uploads in progress, under `BLOBS_DIR`
+pub static TMP_DIR: &str = "tmp";
+
+/// Content addressed files under `<data_dir>/blobs`, stored at
+/// `<first 2 characters of the digest>/<digest>` where the digest is the
+/// hex encoded sha256 of the data. Identical data is stored once.
+///
+/// Which blobs are in use is known from the notes, see `NoteDao`
+#[derive(Debug)]
+pub struct BlobStore {
+    dir: PathBuf,
+    /// Digests of the blobs committed but not attached to a note yet, with
+    /// the number of uploads of each. `BlobStore::remove` keeps them
+    pinned: Mutex<HashMap<String, usize>>,
+}
+
+impl BlobStore {
+    pub fn new(data_dir: &Path) -> Self {
+        BlobStore {
+            dir: data_dir.join(BLOBS_DIR),
+            pinned: Mutex::default(),
+        }
+    }
+
+    /// Create the directories of the store, discarding uploads a previous
+    /// process did not complete. Only the holder of `DataDirLock` may do
+    /// so, the uploads may otherwise be those of a running server
+    pub fn init(&self, _lock: &DataDirLock) -> Result<(), BppStoreError> {
+        let tmp_dir = self.dir.join(TMP_DIR);
+        if tmp_dir.exists() {
+            fs::remove_dir_all(&tmp_dir)
+                .into_report()
+                .change_context_lazy(|| {
+                    BppStoreError::FailedToOpenStore(format!(
+                        "Could not clean up {}",
+                        tmp_dir.display()
+                    ))
+                })?;
+        }
+        fs::create_dir_all(&tmp_dir)
+            .into_report()
+            .change_context_lazy(|| {
+                BppStoreError::FailedToOpenStore(format!("Could not create {}", tmp_dir.display()))
+            })
+    }
+
+    /// Start an upload. Nothing is stored before `BlobStore::commit`
+    pub fn writer(&self) -> Result<BlobWriter, BppStoreError> {
+        let path = self.tmp_path();
+        let file = fs::File::create(&path)
+            .into_report()
+            .change_context_lazy(|| {
+                BppStoreError::FailedToWriteStore(format!("Could not create {}", path.display()))
+            })?;
+        Ok(BlobWriter {
+            file,
+            path,
+            hasher: Sha256::new(),
+            size: 0,
+        })
+    }
+
+    /// Move a complete upload to its place. The blob is kept until the
+    /// returned `CommittedBlob` is dropped, so it can be attached to a note
+    /// without holding a lock during the commit
+    pub fn commit(&self, writer: BlobWriter) -> Result<CommittedBlob<'_>, BppStoreError> {
+        let digest = format!("{:x}", writer.hasher.clone().finalize());
+        *self.pinned().entry(digest.clone()).or_default() += 1;
+        let mut committed = CommittedBlob {
+            digest,
+            size: writer.size,
+            deduplicated: false,
+            store: self,
+        };
+
+        writer
+            .file
+            .sync_all()
+            .into_report()
+            .change_context_lazy(|| {
+                BppStoreError::FailedToWriteStore(format!(
+                    "Could not write {}",
+                    writer.path.display()
+                ))
+            })?;
+        let path = self.path(&committed.digest)?;
+        if path.exists() {
+            committed.deduplicated = true;
+            return Ok(committed);
+        }
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .into_report()
+                .change_context_lazy(|| {
+                    BppStoreError::FailedToWriteStore(format!("Could not create {}", dir.display()))
+                })?;
+        }
+        fs::rename(&writer.path, &path)
+            .into_report()
+            .change_context_lazy(|| {
+                BppStoreError::FailedToWriteStore(format!("Could not write {}", path.display()))
+            })?;
+        Ok(committed)
+    }
+
+    /// The blob with `digest`, which must exist
+    pub fn open_blob(&self, digest: &str) -> Result<fs::File, BppStoreError> {
+        let path = self.path(digest)?;
+        fs::File::open(&path).into_report().change_context_lazy(|| {
+            BppStoreError::FailedToOpenStore(format!("Could not open {}", path.display()))
+        })
+    }
+
+    /// Delete the blob with `digest`, once no note refers to it. Blobs
+    /// about to be attached are kept. Returns whether the blob was deleted
+    pub fn remove(&self, digest: &str) -> Result<bool, BppStoreError> {
+        let Ok(path) = self.path(digest) else {
+            return Ok(false);
+        };
+        // Held while deleting, so a commit of the same data stores it again
+        // rather than finding the blob about to be deleted
+        let pinned = self.pinned();
+        if pinned.contains_key(digest) {
+            return Ok(false);
+        }
+        match fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(report!(err)
+                .change_context(BppStoreError::FailedToWriteStore(format!(
+                    "Could not remove {}",
+                    path.display()
+                )))),
+            _ => Ok(true),
+        }
+    }
+
+    /// Where the blob with `digest` is stored. Fails unless `digest` is a
+    /// hex encoded sha256, so that it cannot name a path outside the store
+    pub fn path(&self, digest: &str) -> Result<PathBuf, BppStoreError> {
+        if !is_digest(digest) {
+            return Err(report!(BppStoreError::FailedToOpenStore(format!(
+                "{digest:?} is not a blob digest"
+            ))));
+        }
+        Ok(self.dir.join(&digest[..2]).join(digest))
+    }
+
+    /// A new path under the directory of uploads in progress, discarded
+    /// with them at the next start
+    pub fn tmp_path(&self) -> PathBuf {
+        self.dir.join(TMP_DIR).join(Uuid::new_v4().to_string())
+    }
+
+    fn pinned(&self) -> MutexGuard<'_, HashMap<String, usize>> {
+        self.pinned.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// A blob stored by `BlobStore::commit`, kept until dropped
+#[derive(Debug)]
+pub struct CommittedBlob<'a> {
+    pub digest: String,
+    pub size: u64,
+    /// Whether a blob with this digest already existed
+    pub deduplicated: bool,
+    store: &'a BlobStore,
+}
+
+impl Drop for CommittedBlob<'_> {
+    fn drop(&mut self) {
+        let mut pinned = self.store.pinned();
+        if let Some(count) = pinned.get_mut(&self.digest) {
+            *count -= 1;
+            if *count == 0 {
+                pinned.remove(&self.digest);
+            }
+        }
+    }
+}
+
+/// An upload in progress, removed unless committed
+#[derive(Debug)]
+pub struct BlobWriter {
+    file: fs::File,
+    path: PathBuf,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl BlobWriter {
+    pub fn write(&mut self, data: &[u8]) -> Result<(), BppStoreError> {
+        self.file
+            .write_all(data)
+            .into_report()
+            .change_context_lazy(|| {
+                BppStoreError::FailedToWriteStore(format!(
+                    "Could not write {}",
+                    self.path.display()
+                ))
+            })?;
+        self.hasher.update(data);
+        self.size += data.len() as u64;
+        Ok(())
+    }
+
+    /// Bytes written so far
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl Drop for BlobWriter {
+    fn drop(&mut self) {
+        // Gone already once committed, unless the blob was a duplicate
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+/// Hex encoded sha256, the only names `BlobStore` accepts
+pub fn is_digest(digest: &str) -> bool {
+    digest.len() == 64
+        && digest
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
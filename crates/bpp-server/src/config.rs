@@ -1,90 +1,742 @@
 use crate::error_def::BppConfigError;
-use config::{Config as ConfigBase, Environment, File};
-use error_stack::{report, Result};
-use serde::Deserialize;
+use config::{Config as ConfigBase, Environment, File, FileFormat, FileSourceFile, Map};
+use error_stack::{report, Context, IntoReport, Result, ResultExt};
+use serde::{Deserialize, Serialize, Serializer};
 use std::env;
+use std::fmt;
+use std::fmt::Formatter;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 /// Enables building a Struct from config attributes
-pub trait FromConfig: Sized {
-    fn from_config(config: &Config) -> Result<Self, BppConfigError>;
+pub trait FromConfig<E: Context = BppConfigError>: Sized {
+    fn from_config(config: &Config) -> Result<Self, E>;
 }
 
-#[derive(Debug, Deserialize, Default)]
-#[serde(default)]
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
+    pub backup: BackupConfig,
     pub database: DatabaseConfig,
+    pub extraction: ExtractionConfig,
+    pub limits: LimitsConfig,
     pub logging: LoggingConfig,
+    pub metrics: MetricsConfig,
+    pub rate_limit: RateLimitConfig,
     pub service: ServiceConfig,
 }
 
-static DEFAULT_CONFIG_FILE: &str = "~/bpp_server.config";
+/// Values given on the command line. They take precedence over the
+/// environment and the config file
+#[derive(Debug, Default, Clone)]
+pub struct ConfigOverrides {
+    pub config_file: Option<PathBuf>,
+    pub listen: Option<String>,
+    pub data_dir: Option<PathBuf>,
+    pub log_level: Option<String>,
+}
+
+/// Config file looked up under the XDG config directories
+static XDG_CONFIG_FILE: &str = "bpp/server.yaml";
+/// Config file used before the XDG lookup existed, kept for compatibility
+static LEGACY_CONFIG_FILE: &str = "~/bpp_server.config";
+/// Extensions the config crate can infer a format from. Files with any
+/// other extension are read as yaml
+static KNOWN_EXTENSIONS: [&str; 7] = ["yaml", "yml", "json", "json5", "toml", "ini", "ron"];
+/// Default backup directory, under `database.data_dir`
+static BACKUP_DIR: &str = "backups";
+static ENV_PREFIX: &str = "BPP";
+static ENV_SEPARATOR: &str = "__";
+/// Top level sections that can be overwritten from the environment
+static ENV_SECTIONS: [&str; 8] = [
+    "backup",
+    "database",
+    "extraction",
+    "limits",
+    "logging",
+    "metrics",
+    "rate_limit",
+    "service",
+];
 
 impl Config {
     /// Load config options from a yaml file and overload
-    ///  options with environment variable starting with `BPP_`
+    ///  options with environment variable starting with `BPP_`,
+    ///  using `__` to separate nested keys
     ///  Example:
-    ///     BPP_logging_level -> logging.level
+    ///     BPP_LOGGING__LEVEL -> logging.level
+    ///     BPP_DATABASE__PASSWORD_FILE -> database.password_file
+    ///  The single `_` of earlier versions is still accepted after the
+    ///  section, eg. BPP_LOGGING_LEVEL
     ///
-    /// If BPP_SERVER_CONFIG is defined, it will be used
-    /// instead of the default config path
-    pub fn load() -> Result<Self, BppConfigError> {
-        let config_file = env::var("BPP_SERVER_CONFIG").unwrap_or(DEFAULT_CONFIG_FILE.to_string());
-
-        let config = ConfigBase::builder()
-            .add_source(File::with_name(config_file.as_str()).required(true))
-            .add_source(Environment::with_prefix("BPP").separator("_"))
-            .build();
-
-        config
-            .map(|c| Config::deserialize(c).unwrap())
-            .map_err(|err| {
-                report!(err).change_context(BppConfigError::FailedToLoadConfigOptions(
-                    "Config could not be loaded".to_string(),
-                ))
+    /// Values set in `overrides` take precedence over both. The order is:
+    ///  command line > environment > config file > defaults
+    ///
+    /// See `Config::config_file` for how the file is located.
+    ///
+    /// Unknown keys are rejected, secrets are resolved
+    /// (see `DatabaseConfig`) and values are validated before the
+    /// config is returned
+    pub fn load(overrides: &ConfigOverrides) -> Result<Self, BppConfigError> {
+        let config_file = Self::config_file(overrides.config_file.as_deref())?;
+
+        let mut builder = ConfigBase::builder()
+            .add_source(Self::file_source(&config_file))
+            .add_source(
+                Environment::with_prefix(ENV_PREFIX)
+                    .prefix_separator("_")
+                    .separator(ENV_SEPARATOR)
+                    .source(Some(Self::config_env(env::vars()))),
+            );
+        let cli_values = [
+            ("service.listen", overrides.listen.clone()),
+            (
+                "database.data_dir",
+                overrides
+                    .data_dir
+                    .as_ref()
+                    .map(|dir| dir.to_string_lossy().to_string()),
+            ),
+            ("logging.level", overrides.log_level.clone()),
+        ];
+        for (key, value) in cli_values {
+            if let Some(value) = value {
+                builder = builder
+                    .set_override(key, value)
+                    .into_report()
+                    .change_context_lazy(|| BppConfigError::InvalidConfigValue(key.to_string()))?;
+            }
+        }
+
+        let base = builder.build().into_report().change_context_lazy(|| {
+            BppConfigError::FailedToLoadConfigOptions(format!(
+                "Config could not be loaded from {}",
+                config_file.display()
+            ))
+        })?;
+
+        let mut config: Config = serde_path_to_error::deserialize(base).map_err(|err| {
+            let key = err.path().to_string();
+            let err = err.into_inner();
+            report!(BppConfigError::InvalidConfigValue(format!("{key}: {err}")))
+        })?;
+        config.database.resolve_secrets()?;
+        config.database.data_dir = expand_home(&config.database.data_dir.to_string_lossy());
+        config.backup.dir = Some(match &config.backup.dir {
+            Some(dir) => expand_home(&dir.to_string_lossy()),
+            None => config.database.data_dir.join(BACKUP_DIR),
+        });
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Locate the config file. `explicit` (--config) is used if given,
+    /// then BPP_SERVER_CONFIG, otherwise the first existing file out of:
+    ///  - $XDG_CONFIG_HOME/bpp/server.yaml (defaults to ~/.config)
+    ///  - ~/bpp_server.config
+    ///  - $XDG_CONFIG_DIRS/bpp/server.yaml (defaults to /etc/xdg)
+    ///
+    /// A leading `~` is expanded to the home directory
+    pub fn config_file(explicit: Option<&Path>) -> Result<PathBuf, BppConfigError> {
+        if let Some(config_file) = explicit {
+            return Ok(expand_home(&config_file.to_string_lossy()));
+        }
+        if let Ok(config_file) = env::var("BPP_SERVER_CONFIG") {
+            return Ok(expand_home(&config_file));
+        }
+
+        let config_home = env::var("XDG_CONFIG_HOME")
+            .ok()
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| expand_home("~/.config"));
+        let config_dirs = env::var("XDG_CONFIG_DIRS")
+            .ok()
+            .filter(|dirs| !dirs.is_empty())
+            .unwrap_or_else(|| "/etc/xdg".to_string());
+
+        let mut candidates = vec![
+            config_home.join(XDG_CONFIG_FILE),
+            expand_home(LEGACY_CONFIG_FILE),
+        ];
+        candidates.extend(
+            config_dirs
+                .split(':')
+                .filter(|dir| !dir.is_empty())
+                .map(|dir| PathBuf::from(dir).join(XDG_CONFIG_FILE)),
+        );
+
+        candidates
+            .iter()
+            .find(|path| path.is_file())
+            .cloned()
+            .ok_or_else(|| {
+                let searched: Vec<String> = candidates
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect();
+                report!(BppConfigError::FailedToLoadConfigOptions(format!(
+                    "No config file found. Set BPP_SERVER_CONFIG or create one of: {}",
+                    searched.join(", ")
+                )))
             })
     }
+
+    fn file_source(path: &Path) -> File<FileSourceFile, FileFormat> {
+        let known_format = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| KNOWN_EXTENSIONS.contains(&ext));
+
+        if path.is_file() && !known_format {
+            File::from(path).format(FileFormat::Yaml)
+        } else {
+            File::with_name(path.to_string_lossy().as_ref())
+        }
+    }
+
+    /// Directory backups are written to, see `BackupConfig`
+    pub fn backup_dir(&self) -> PathBuf {
+        self.backup
+            .dir
+            .clone()
+            .unwrap_or_else(|| self.database.data_dir.join(BACKUP_DIR))
+    }
+
+    fn validate(&self) -> Result<(), BppConfigError> {
+        self.backup.validate()?;
+        self.extraction.validate()?;
+        self.limits.validate()?;
+        self.logging.validate()?;
+        self.metrics.validate()?;
+        self.rate_limit.validate()?;
+        self.service.validate()
+    }
+
+    /// Environment variables meant for the config sections. Other `BPP_`
+    /// variables (eg. BPP_SERVER_CONFIG) are not config keys and would
+    /// otherwise be rejected as unknown fields. Variables separating the
+    /// section with a single `_` are renamed to use `ENV_SEPARATOR`
+    fn config_env(vars: impl IntoIterator<Item = (String, String)>) -> Map<String, String> {
+        let prefix = ENV_PREFIX.to_lowercase();
+        vars.into_iter()
+            .filter_map(|(key, value)| {
+                let key = key.to_lowercase();
+                ENV_SECTIONS.iter().find_map(|section| {
+                    let rest = key.strip_prefix(&format!("{prefix}_{section}"))?;
+                    let field = rest
+                        .strip_prefix(ENV_SEPARATOR)
+                        .or_else(|| rest.strip_prefix('_'))
+                        .filter(|field| !field.is_empty())?;
+                    let key = format!("{prefix}_{section}{ENV_SEPARATOR}{field}");
+                    Some((key, value.clone()))
+                })
+            })
+            .collect()
+    }
+}
+
+/// Backups are written to `dir`, which defaults to <data_dir>/backups.
+/// While serving, a backup is taken every `interval_secs` and only the
+/// `keep` most recent backups of `dir` are kept. Scheduled backups are
+/// disabled when `interval_secs` is 0
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackupConfig {
+    pub dir: Option<PathBuf>,
+    pub interval_secs: u64,
+    pub keep: usize,
+}
+
+impl BackupConfig {
+    fn validate(&self) -> Result<(), BppConfigError> {
+        if self
+            .dir
+            .as_ref()
+            .is_some_and(|dir| dir.as_os_str().is_empty())
+        {
+            return Err(report!(BppConfigError::InvalidConfigValue(
+                "backup.dir: cannot be empty".to_string()
+            )));
+        }
+        if self.keep == 0 {
+            return Err(report!(BppConfigError::InvalidConfigValue(
+                "backup.keep: must be greater than 0".to_string()
+            )));
+        }
+        Ok(())
+    }
+
+    /// None when scheduled backups are disabled
+    pub fn interval(&self) -> Option<Duration> {
+        (self.interval_secs > 0).then(|| Duration::from_secs(self.interval_secs))
+    }
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        BackupConfig {
+            dir: None,
+            interval_secs: 0,
+            keep: 7,
+        }
+    }
+}
+
+/// Text of plain text, markdown, html and pdf attachments is extracted in
+/// the background and indexed for search, unless `enabled` is false. Only
+/// the first `max_text_bytes` of the text of an attachment are indexed
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExtractionConfig {
+    pub enabled: bool,
+    pub max_text_bytes: usize,
+}
+
+impl ExtractionConfig {
+    fn validate(&self) -> Result<(), BppConfigError> {
+        if self.max_text_bytes == 0 {
+            return Err(report!(BppConfigError::InvalidConfigValue(
+                "extraction.max_text_bytes: must be greater than 0".to_string()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Default for ExtractionConfig {
+    fn default() -> Self {
+        ExtractionConfig {
+            enabled: true,
+            max_text_bytes: 1024 * 1024,
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(default)]
+/// A config value that must never end up in logs. Both `Debug` and
+/// `Display` are redacted, use `Secret::expose` to access the value
+#[derive(Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str("<redacted>")
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+/// `connection_str` and the password are not used, notes are stored as
+/// files, but still accepted so older configs load. The password can be
+/// provided either as:
+///  - `password_str`: the password itself, or a reference to an environment
+///    variable in the form `${VAR_NAME}`
+///  - `password_file`: a file containing the password
+///
+/// There is no default password.
+///
+/// `passphrase_file` holds the passphrase note titles and contents are
+/// encrypted with at rest, see `Keyring`. Notes are not encrypted without
+/// it.
+///
+/// Notes are stored under `data_dir`, which defaults to
+/// $XDG_DATA_HOME/bpp (~/.local/share/bpp). Stores written by an older
+/// version are upgraded on start when `auto_migrate` is set, otherwise
+/// the server refuses to start until `bpp-server migrate` is run
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct DatabaseConfig {
     pub connection_str: String,
-    pub password_str: String,
+    pub password_str: Option<Secret>,
+    pub password_file: Option<PathBuf>,
+    pub passphrase_file: Option<PathBuf>,
+    pub data_dir: PathBuf,
+    pub auto_migrate: bool,
 }
 
 impl Default for DatabaseConfig {
     fn default() -> Self {
+        let data_home = env::var("XDG_DATA_HOME")
+            .ok()
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| expand_home("~/.local/share"));
+
         DatabaseConfig {
-            connection_str: "user@url.ca:8080".to_string(),
-            password_str: "123olivera4".to_string(),
+            connection_str: String::new(),
+            password_str: None,
+            password_file: None,
+            passphrase_file: None,
+            data_dir: data_home.join("bpp"),
+            auto_migrate: true,
+        }
+    }
+}
+
+impl DatabaseConfig {
+    /// Replace env references and file paths with the secrets they point to.
+    /// After this call `password_str` holds the effective password
+    fn resolve_secrets(&mut self) -> Result<(), BppConfigError> {
+        match (&self.password_str, &self.password_file) {
+            (Some(_), Some(_)) => Err(report!(BppConfigError::FailedToResolveSecret(
+                "Only one of database.password_str and database.password_file can be set"
+                    .to_string()
+            ))),
+            (Some(password), None) => {
+                self.password_str = Some(resolve_env_reference(password)?);
+                Ok(())
+            }
+            (None, Some(path)) => {
+                let password =
+                    fs::read_to_string(path)
+                        .into_report()
+                        .change_context_lazy(|| {
+                            BppConfigError::FailedToResolveSecret(format!(
+                                "Failed to read database.password_file {}",
+                                path.display()
+                            ))
+                        })?;
+                self.password_str = Some(Secret(password.trim_end().to_string()));
+                Ok(())
+            }
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// Replace a leading `~` with the home directory
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix('~'), env::var("HOME")) {
+        (Some(rest), Ok(home)) if rest.is_empty() || rest.starts_with('/') => {
+            PathBuf::from(format!("{home}{rest}"))
+        }
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Resolve a `${VAR_NAME}` reference. Values that are not a reference are
+/// returned as they are
+fn resolve_env_reference(value: &Secret) -> Result<Secret, BppConfigError> {
+    match value
+        .expose()
+        .strip_prefix("${")
+        .and_then(|v| v.strip_suffix('}'))
+    {
+        Some(var_name) => env::var(var_name)
+            .map(Secret)
+            .into_report()
+            .change_context_lazy(|| {
+                BppConfigError::FailedToResolveSecret(format!(
+                    "Environment variable {var_name} is not set"
+                ))
+            }),
+        None => Ok(value.clone()),
+    }
+}
+
+/// Limits on the notes accepted from clients. Titles are counted in
+/// characters after normalization, contents in bytes as sent, that is
+/// encrypted and encoded for encrypted notes.
+///
+/// `max_notes` and `max_storage_bytes` are quotas on the whole store,
+/// storage being the size of every title, content and attached file
+/// combined, files attached more than once counting once.
+/// `max_attachment_bytes` is the size of a single attached file.
+/// `max_tags` and `max_tag_chars` bound the tags of imported notes
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub max_title_chars: usize,
+    pub max_content_bytes: usize,
+    pub max_notes: usize,
+    pub max_storage_bytes: usize,
+    pub max_attachment_bytes: usize,
+    pub max_tags: usize,
+    pub max_tag_chars: usize,
+}
+
+impl LimitsConfig {
+    fn validate(&self) -> Result<(), BppConfigError> {
+        for (key, value) in [
+            ("limits.max_title_chars", self.max_title_chars),
+            ("limits.max_content_bytes", self.max_content_bytes),
+            ("limits.max_notes", self.max_notes),
+            ("limits.max_storage_bytes", self.max_storage_bytes),
+            ("limits.max_attachment_bytes", self.max_attachment_bytes),
+            ("limits.max_tags", self.max_tags),
+            ("limits.max_tag_chars", self.max_tag_chars),
+        ] {
+            if value == 0 {
+                return Err(report!(BppConfigError::InvalidConfigValue(format!(
+                    "{key}: must be greater than 0"
+                ))));
+            }
         }
+        Ok(())
     }
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(default)]
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_title_chars: 200,
+            max_content_bytes: 1024 * 1024,
+            max_notes: 100_000,
+            max_storage_bytes: 1024 * 1024 * 1024,
+            max_attachment_bytes: 25 * 1024 * 1024,
+            max_tags: 50,
+            max_tag_chars: 64,
+        }
+    }
+}
+
+/// Rpcs are rate limited per client ip with a token bucket refilled with
+/// `requests_per_second` tokens, holding up to `burst` tokens.
+/// Disabled when `requests_per_second` is 0
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub requests_per_second: u32,
+    pub burst: u32,
+}
+
+impl RateLimitConfig {
+    fn validate(&self) -> Result<(), BppConfigError> {
+        if self.requests_per_second > 0 && self.burst == 0 {
+            return Err(report!(BppConfigError::InvalidConfigValue(
+                "rate_limit.burst: must be greater than 0".to_string()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            requests_per_second: 20,
+            burst: 40,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct LoggingConfig {
     pub level: String,
+    pub format: LogFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Accepted values for `logging.level`, case insensitive
+static LOG_LEVELS: [&str; 5] = ["TRACE", "DEBUG", "INFO", "WARN", "ERROR"];
+
+impl LoggingConfig {
+    fn validate(&self) -> Result<(), BppConfigError> {
+        if LOG_LEVELS
+            .iter()
+            .any(|level| level.eq_ignore_ascii_case(&self.level))
+        {
+            Ok(())
+        } else {
+            Err(report!(BppConfigError::InvalidConfigValue(format!(
+                "logging.level: `{}` is not one of {}",
+                self.level,
+                LOG_LEVELS.join(", ")
+            ))))
+        }
+    }
 }
 
 impl Default for LoggingConfig {
     fn default() -> Self {
         LoggingConfig {
             level: "INFO".to_string(),
+            format: LogFormat::Text,
         }
     }
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(default)]
+/// Prometheus metrics are served over http at `/metrics` on `listen`,
+/// eg. 127.0.0.1:9090. Disabled when `listen` is not set
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub listen: Option<String>,
+}
+
+impl MetricsConfig {
+    fn validate(&self) -> Result<(), BppConfigError> {
+        self.listen_addr().map(|_| ())
+    }
+
+    pub fn listen_addr(&self) -> Result<Option<SocketAddr>, BppConfigError> {
+        self.listen
+            .as_ref()
+            .map(|listen| {
+                listen.parse().into_report().change_context_lazy(|| {
+                    BppConfigError::InvalidConfigValue(format!(
+                        "metrics.listen: `{listen}` is not a valid socket address"
+                    ))
+                })
+            })
+            .transpose()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct ServiceConfig {
     pub name: String,
+    /// Address the gRPC server listens on
+    pub listen: String,
+    /// Seconds rpcs in flight are given to complete on shutdown
+    pub shutdown_deadline_secs: u64,
+}
+
+impl ServiceConfig {
+    fn validate(&self) -> Result<(), BppConfigError> {
+        if self.name.trim().is_empty() {
+            return Err(report!(BppConfigError::InvalidConfigValue(
+                "service.name: cannot be empty".to_string()
+            )));
+        }
+        self.listen_addr().map(|_| ())
+    }
+
+    pub fn listen_addr(&self) -> Result<SocketAddr, BppConfigError> {
+        self.listen.parse().into_report().change_context_lazy(|| {
+            BppConfigError::InvalidConfigValue(format!(
+                "service.listen: `{}` is not a valid socket address",
+                self.listen
+            ))
+        })
+    }
+
+    pub fn shutdown_deadline(&self) -> Duration {
+        Duration::from_secs(self.shutdown_deadline_secs)
+    }
 }
 
 impl Default for ServiceConfig {
     fn default() -> Self {
         ServiceConfig {
             name: "NoteService".to_string(),
+            listen: "[::1]:8085".to_string(),
+            shutdown_deadline_secs: 30,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Load `yaml` as the config file, from a directory removed on drop
+    fn load(yaml: &str) -> (tempfile::TempDir, Result<Config, BppConfigError>) {
+        let dir = tempfile::tempdir().unwrap();
+        let config_file = dir.path().join("server.yaml");
+        fs::write(&config_file, yaml).unwrap();
+        let overrides = ConfigOverrides {
+            config_file: Some(config_file),
+            data_dir: Some(dir.path().join("data")),
+            ..Default::default()
+        };
+        let config = Config::load(&overrides);
+        (dir, config)
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let (_dir, config) = load("logging:\n  levle: INFO\n");
+        let err = format!("{:?}", config.unwrap_err());
+        assert!(err.contains("levle"), "{err}");
+
+        let (_dir, config) = load("loging:\n  level: INFO\n");
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn env_references_are_expanded() {
+        env::set_var("BPP_CONFIG_TEST_PASSWORD", "hunter2");
+        let (_dir, config) = load("database:\n  password_str: ${BPP_CONFIG_TEST_PASSWORD}\n");
+        let password = config.unwrap().database.password_str.unwrap();
+        assert_eq!(password.expose(), "hunter2");
+
+        let (_dir, config) = load("database:\n  password_str: ${BPP_CONFIG_TEST_UNSET}\n");
+        let err = format!("{:?}", config.unwrap_err());
+        assert!(err.contains("BPP_CONFIG_TEST_UNSET is not set"), "{err}");
+
+        let (_dir, config) = load("database:\n  password_str: plain\n");
+        let password = config.unwrap().database.password_str.unwrap();
+        assert_eq!(password.expose(), "plain");
+    }
+
+    #[test]
+    fn secrets_are_redacted() {
+        let (_dir, config) = load("database:\n  password_str: hunter2\n");
+        let config = config.unwrap();
+        let printed = [
+            serde_json::to_string(&config).unwrap(),
+            format!("{config:?}"),
+            format!("{}", config.database.password_str.as_ref().unwrap()),
+        ];
+        for printed in printed {
+            assert!(!printed.contains("hunter2"), "{printed}");
+            assert!(printed.contains("<redacted>"), "{printed}");
+        }
+    }
+
+    #[test]
+    fn env_accepts_both_separators() {
+        let vars = [
+            ("BPP_LOGGING__LEVEL", "info"),
+            ("BPP_RATE_LIMIT_BURST", "5"),
+            ("BPP_DATABASE_PASSWORD_FILE", "/run/secret"),
+            ("BPP_SERVER_CONFIG", "server.yaml"),
+            ("BPP_LOGGING_", "ignored"),
+            ("HOME", "/root"),
+        ]
+        .map(|(key, value)| (key.to_string(), value.to_string()));
+        let env = Config::config_env(vars);
+        let mut keys: Vec<_> = env
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                ("bpp_database__password_file", "/run/secret"),
+                ("bpp_logging__level", "info"),
+                ("bpp_rate_limit__burst", "5"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,1467 @@
+use crate::audit::AUDIT_FILE;
+use crate::backup::{self, Backup, BackupHeader, Restored, Snapshot};
+use crate::blobs::{is_digest, BlobStore, BlobWriter};
+use crate::config::{Config, FromConfig, LimitsConfig};
+use crate::error_def::BppStoreError;
+use crate::extract::{TextFormat, TextStore};
+use crate::index::SearchIndex;
+use crate::keys::{self, Keyring, Passphrase, KEYS_FILE};
+use crate::links::LinkGraph;
+use crate::metrics;
+use crate::migrations;
+use crate::templates::TEMPLATES_FILE;
+use bpp_proto::bpp::{Attachment, Note};
+use bpp_proto::EXPORT_VERSION;
+use error_stack::{report, IntoReport, Result, ResultExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufWriter, IntoInnerError, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Version of the on-disk format of the notes file, see `migrations`
+pub const STORE_VERSION: u32 = 3;
+
+static NOTES_FILE: &str = "notes.json";
+static INDEX_FILE: &str = "index.json";
+static PROBE_FILE: &str = ".probe";
+static LOCK_FILE: &str = ".lock";
+
+/// A note as persisted on disk. Kept apart from the protobuf `Note` so the
+/// storage format can evolve independently from the api
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredNote {
+    pub id: String,
+    pub title: String,
+    pub content: String,
+    #[serde(default)]
+    pub encrypted: bool,
+    #[serde(default)]
+    pub notebook: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Unix time in milliseconds, 0 for notes stored before timestamps
+    /// were recorded
+    #[serde(default)]
+    pub created_ms: i64,
+    #[serde(default)]
+    pub updated_ms: i64,
+    #[serde(default)]
+    pub attachments: Vec<StoredAttachment>,
+}
+
+/// A file attached to a note, its data is in the `BlobStore`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredAttachment {
+    pub name: String,
+    pub digest: String,
+    pub size: u64,
+    pub media_type: String,
+    pub created_ms: i64,
+}
+
+impl From<StoredAttachment> for Attachment {
+    fn from(attachment: StoredAttachment) -> Self {
+        Attachment {
+            name: attachment.name,
+            digest: attachment.digest,
+            size: attachment.size,
+            media_type: attachment.media_type,
+            created_ms: attachment.created_ms,
+        }
+    }
+}
+
+impl From<StoredNote> for Note {
+    fn from(note: StoredNote) -> Self {
+        Note {
+            id: note.id,
+            title: note.title,
+            content: note.content,
+            encrypted: note.encrypted,
+            notebook: note.notebook,
+            tags: note.tags,
+            created_ms: note.created_ms,
+            updated_ms: note.updated_ms,
+            attachments: note
+                .attachments
+                .into_iter()
+                .map(StoredAttachment::into)
+                .collect(),
+        }
+    }
+}
+
+/// What `NoteDao::merge` did with a note
+#[derive(Debug, Clone, PartialEq)]
+pub enum Merged {
+    Added(StoredNote),
+    /// Same title and content as the note with this id
+    Duplicate(String),
+    /// Encrypted note whose id is taken. It cannot be given another one as
+    /// its ciphertext is bound to the id
+    IdTaken(String),
+}
+
+/// Outcome of `NoteDao::migrate`
+#[derive(Debug)]
+pub struct MigrationReport {
+    pub from: u32,
+    pub to: u32,
+    /// Description of every step applied, empty when the store was current
+    pub steps: Vec<&'static str>,
+    /// Copy of the notes file before the migration, None in a dry run
+    pub previous_file: Option<PathBuf>,
+}
+
+/// A link of a note, see `LinkGraph`
+#[derive(Debug, Clone)]
+pub struct ResolvedLink {
+    /// As written in the content
+    pub target: String,
+    /// None when the link is broken
+    pub note: Option<StoredNote>,
+}
+
+/// A note found by `NoteDao::search`
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub note: StoredNote,
+    /// Names of the attachments whose text matched
+    pub attachments: Vec<String>,
+}
+
+/// Notes selected by `NoteDao::graph` and the links between them
+#[derive(Debug, Default)]
+pub struct NoteGraph {
+    pub notes: Vec<StoredNote>,
+    /// Source and target ids, each pair once
+    pub links: Vec<(String, String)>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct NotesFile {
+    version: u32,
+    notes: Vec<StoredNote>,
+}
+
+/// Portable dump of every note, see `NoteDao::export`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportFile {
+    pub version: u32,
+    pub notes: Vec<StoredNote>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    notes: Vec<StoredNote>,
+    index: SearchIndex,
+    links: LinkGraph,
+    /// Set by `NoteDao::flush`, writes are rejected afterwards
+    closed: bool,
+}
+
+/// Bounds on the size of the store, checked when notes are added or files
+/// attached. Imports are not limited
+#[derive(Debug, Clone, Copy)]
+pub struct Quota {
+    pub max_notes: usize,
+    pub max_storage_bytes: usize,
+}
+
+impl Default for Quota {
+    fn default() -> Self {
+        Quota {
+            max_notes: usize::MAX,
+            max_storage_bytes: usize::MAX,
+        }
+    }
+}
+
+impl From<&LimitsConfig> for Quota {
+    fn from(limits: &LimitsConfig) -> Self {
+        Quota {
+            max_notes: limits.max_notes,
+            max_storage_bytes: limits.max_storage_bytes,
+        }
+    }
+}
+
+/// Notes store backed by json files in `database.data_dir`:
+///  - notes.json: every note, in insertion order
+///  - index.json: the search index, rebuilt when missing or stale
+///  - blobs/: the data of attachments, see `BlobStore`
+///  - texts/: the text extracted from attachments, see `TextStore`
+///  - keys.json: what the keys of an encrypted store derive from, see
+///    `Keyring`
+///  - .lock: locked by the process writing to the store, see `DataDirLock`
+///
+/// Files are replaced atomically on every write. When encrypted, the
+/// titles and contents of notes.json and the whole of index.json are
+/// sealed, the notes in memory are not.
+#[derive(Debug)]
+pub struct NoteDao {
+    data_dir: PathBuf,
+    /// None when opened read only
+    _lock: Option<DataDirLock>,
+    state: RwLock<State>,
+    /// None when the store is not encrypted. Replaced by `rotate_key` and
+    /// by `restore`, which adds the keys of the backup
+    keys: RwLock<Option<Arc<Keyring>>>,
+    blobs: BlobStore,
+    texts: TextStore,
+    quota: Quota,
+}
+
+impl FromConfig<BppStoreError> for NoteDao {
+    fn from_config(config: &Config) -> Result<Self, BppStoreError> {
+        let data_dir = &config.database.data_dir;
+        // Held from the migration on, so no other process opens the store
+        // in between
+        let lock = DataDirLock::acquire(data_dir)?;
+        if config.database.auto_migrate {
+            let report = NoteDao::migrate_locked(data_dir, false)?;
+            if let Some(previous_file) = &report.previous_file {
+                info!(
+                    steps = ?report.steps,
+                    "Migrated store from version {} to {}, the previous notes are in {}",
+                    report.from,
+                    report.to,
+                    previous_file.display()
+                );
+            }
+        }
+        let passphrase = config
+            .database
+            .passphrase_file
+            .as_deref()
+            .map(Passphrase::read)
+            .transpose()?;
+        let keys = match Keyring::load(data_dir, passphrase.as_ref())? {
+            Some(keys) => Some(keys),
+            // The store is encrypted from the first start with a passphrase
+            None => passphrase
+                .map(|passphrase| Keyring::create(data_dir, &passphrase))
+                .transpose()?,
+        };
+        Ok(NoteDao::open(data_dir, lock, keys)?.with_quota(Quota::from(&config.limits)))
+    }
+}
+
+impl NoteDao {
+    /// Open the store in `data_dir`, locked with `lock` and encrypted with
+    /// `keys`. Stores at an older version must be migrated first, see
+    /// `NoteDao::migrate`
+    fn open(
+        data_dir: &Path,
+        lock: DataDirLock,
+        keys: Option<Keyring>,
+    ) -> Result<Self, BppStoreError> {
+        let notes_file = Self::read_notes_file(&data_dir.join(NOTES_FILE))?;
+        if notes_file.version > STORE_VERSION {
+            return Err(report!(BppStoreError::UnsupportedVersion(
+                notes_file.version
+            )));
+        }
+        if notes_file.version < STORE_VERSION {
+            return Err(report!(BppStoreError::MigrationRequired(
+                notes_file.version
+            )));
+        }
+        let keys = keys.map(Arc::new);
+        // Written before the passphrase was set
+        let plaintext = keys.is_some()
+            && notes_file
+                .notes
+                .iter()
+                .any(|note| !keys::is_sealed(&note.title) || !keys::is_sealed(&note.content));
+        let notes = unsealed(keys.as_deref(), notes_file.notes)?;
+
+        let blobs = BlobStore::new(data_dir);
+        blobs.init(&lock)?;
+        let dao = NoteDao {
+            data_dir: data_dir.to_path_buf(),
+            _lock: Some(lock),
+            blobs,
+            texts: TextStore::new(data_dir),
+            state: RwLock::new(State {
+                links: LinkGraph::build(&notes),
+                notes,
+                index: SearchIndex::default(),
+                closed: false,
+            }),
+            keys: RwLock::new(keys),
+            quota: Quota::default(),
+        };
+        if plaintext {
+            let state = dao.read();
+            dao.save_notes(&state.notes)?;
+            info!("Encrypted the {} notes of the store", state.notes.len());
+        }
+
+        let index = fs::read(dao.data_dir.join(INDEX_FILE))
+            .ok()
+            .and_then(|bytes| dao.read_index(bytes));
+        match index {
+            Some(index) if index.matches(&dao.read().notes) => {
+                let bytes = fs::metadata(dao.data_dir.join(INDEX_FILE)).map_or(0, |m| m.len());
+                metrics::record_index(index.note_count(), index.term_count(), bytes as usize);
+                dao.write().index = index
+            }
+            _ => {
+                dao.reindex()?;
+            }
+        }
+        Ok(dao)
+    }
+
+    /// Open the store in `data_dir` without its search index, rejecting
+    /// writes. The notes file is only ever replaced atomically, so this is
+    /// safe while a server runs on the same directory, eg. to take a backup.
+    /// `passphrase_file` is needed when the store is encrypted
+    pub fn open_read_only(
+        data_dir: &Path,
+        passphrase_file: Option<&Path>,
+    ) -> Result<Self, BppStoreError> {
+        let notes_file = Self::read_notes_file(&data_dir.join(NOTES_FILE))?;
+        if notes_file.version > STORE_VERSION {
+            return Err(report!(BppStoreError::UnsupportedVersion(
+                notes_file.version
+            )));
+        }
+        let passphrase = passphrase_file.map(Passphrase::read).transpose()?;
+        let keys = Keyring::load(data_dir, passphrase.as_ref())?.map(Arc::new);
+        Ok(NoteDao {
+            data_dir: data_dir.to_path_buf(),
+            _lock: None,
+            blobs: BlobStore::new(data_dir),
+            texts: TextStore::new(data_dir),
+            state: RwLock::new(State {
+                notes: unsealed(keys.as_deref(), notes_file.notes)?,
+                index: SearchIndex::default(),
+                links: LinkGraph::default(),
+                closed: true,
+            }),
+            keys: RwLock::new(keys),
+            quota: Quota::default(),
+        })
+    }
+
+    pub fn with_quota(mut self, quota: Quota) -> Self {
+        self.quota = quota;
+        self
+    }
+
+    /// Add a note, unless the store is at its quota
+    pub fn add(
+        &self,
+        id: Option<String>,
+        title: &str,
+        content: &str,
+        encrypted: bool,
+    ) -> Result<StoredNote, BppStoreError> {
+        let now = unix_millis();
+        let note = StoredNote {
+            id: id.unwrap_or_else(|| Uuid::new_v4().to_string()),
+            title: title.to_string(),
+            content: content.to_string(),
+            encrypted,
+            notebook: String::new(),
+            tags: vec![],
+            created_ms: now,
+            updated_ms: now,
+            attachments: vec![],
+        };
+
+        let mut state = self.write_open()?;
+        if state.notes.iter().any(|existing| existing.id == note.id) {
+            return Err(report!(BppStoreError::NoteExists(note.id)));
+        }
+        if state.notes.len() >= self.quota.max_notes {
+            return Err(report!(BppStoreError::QuotaExceeded(format!(
+                "the store already holds the maximum of {} notes",
+                self.quota.max_notes
+            ))));
+        }
+        let storage_bytes = storage_bytes(state.notes.iter().chain([&note]));
+        if storage_bytes > self.quota.max_storage_bytes {
+            return Err(report!(BppStoreError::QuotaExceeded(format!(
+                "notes would take {storage_bytes} bytes, the maximum is {}",
+                self.quota.max_storage_bytes
+            ))));
+        }
+
+        state.notes.push(note.clone());
+        if let Err(err) = self.save_notes(&state.notes) {
+            state.notes.pop();
+            return Err(err);
+        }
+        state.index.insert(&note);
+        state.links.insert(&note);
+        self.save_index_after_notes(&state.index);
+        Ok(note)
+    }
+
+    /// Remove a note, returning it if it existed
+    pub fn remove(&self, id: &str) -> Result<Option<StoredNote>, BppStoreError> {
+        let mut state = self.write_open()?;
+        let position = match state.notes.iter().position(|note| note.id == id) {
+            Some(position) => position,
+            None => return Ok(None),
+        };
+
+        let note = state.notes.remove(position);
+        if let Err(err) = self.save_notes(&state.notes) {
+            state.notes.insert(position, note);
+            return Err(err);
+        }
+        state.index.remove(&note);
+        self.unindex_attachments(&mut state.index, &note);
+        state.links.remove(&note);
+        self.save_index_after_notes(&state.index);
+        for attachment in &note.attachments {
+            self.release_blob(&state.notes, &attachment.digest);
+        }
+        Ok(Some(note))
+    }
+
+    /// Start the upload of an attachment, see `NoteDao::attach`
+    pub fn blob_writer(&self) -> Result<BlobWriter, BppStoreError> {
+        self.blobs.writer()
+    }
+
+    /// Store the data of `blob` and attach it to the note `note_id` as
+    /// `name`, replacing the attachment with that name. Returns the note,
+    /// the attachment and whether the data was already stored. None when
+    /// there is no such note, the data is then discarded
+    pub fn attach(
+        &self,
+        note_id: &str,
+        name: &str,
+        media_type: &str,
+        blob: BlobWriter,
+    ) -> Result<Option<(StoredNote, StoredAttachment, bool)>, BppStoreError> {
+        // Committed before taking the lock, writing the data to disk can
+        // take a while. The blob is kept until attached, see `BlobStore`
+        let committed = self.blobs.commit(blob)?;
+        let deduplicated = committed.deduplicated;
+        let mut state = self.write_open()?;
+        let position = match state.notes.iter().position(|note| note.id == note_id) {
+            Some(position) => position,
+            None => {
+                let digest = committed.digest.clone();
+                drop(committed);
+                self.release_blob(&state.notes, &digest);
+                return Ok(None);
+            }
+        };
+
+        let now = unix_millis();
+        let attachment = StoredAttachment {
+            name: name.to_string(),
+            digest: committed.digest.clone(),
+            size: committed.size,
+            media_type: media_type.to_string(),
+            created_ms: now,
+        };
+        let previous = state.notes[position].clone();
+        let note = &mut state.notes[position];
+        let replaced = note
+            .attachments
+            .iter()
+            .position(|other| other.name == name)
+            .map(|position| note.attachments.remove(position));
+        note.attachments.push(attachment.clone());
+        note.updated_ms = now;
+        let note = note.clone();
+
+        let storage_bytes = storage_bytes(&state.notes);
+        let saved = if storage_bytes > self.quota.max_storage_bytes {
+            Err(report!(BppStoreError::QuotaExceeded(format!(
+                "notes and attachments would take {storage_bytes} bytes, the maximum is {}",
+                self.quota.max_storage_bytes
+            ))))
+        } else {
+            self.save_notes(&state.notes)
+        };
+        if let Err(err) = saved {
+            state.notes[position] = previous;
+            drop(committed);
+            self.release_blob(&state.notes, &attachment.digest);
+            return Err(err);
+        }
+        drop(committed);
+        if !note.encrypted {
+            if let Some(replaced) = &replaced {
+                if let Some(text) = self.texts.get(&replaced.digest) {
+                    state
+                        .index
+                        .remove_attachment(&note.id, &replaced.name, &text);
+                }
+            }
+            // Known when the same data was attached before, otherwise
+            // indexed once extracted, see `extract::run`
+            if let Some(text) = self.texts.get(&attachment.digest) {
+                state
+                    .index
+                    .insert_attachment(&note.id, &attachment.name, &text);
+            }
+            self.save_index_after_notes(&state.index);
+        }
+        if let Some(replaced) = replaced {
+            self.release_blob(&state.notes, &replaced.digest);
+        }
+        Ok(Some((note, attachment, deduplicated)))
+    }
+
+    /// The attachment of the note `note_id` named `name`, or with the
+    /// digest `name`, and its data. None when there is no such note or
+    /// attachment
+    pub fn attachment(
+        &self,
+        note_id: &str,
+        name: &str,
+    ) -> Result<Option<(StoredAttachment, fs::File)>, BppStoreError> {
+        let state = self.read();
+        let attachment = state
+            .notes
+            .iter()
+            .find(|note| note.id == note_id)
+            .and_then(|note| {
+                note.attachments
+                    .iter()
+                    .rev()
+                    .find(|attachment| attachment.name == name)
+                    .or_else(|| {
+                        note.attachments
+                            .iter()
+                            .find(|attachment| attachment.digest == name)
+                    })
+            });
+        match attachment {
+            // Opened under the lock, an open file outlives its removal
+            Some(attachment) => Ok(Some((
+                attachment.clone(),
+                self.blobs.open_blob(&attachment.digest)?,
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    /// Delete the blob with `digest` and its text unless one of `notes`
+    /// still refers to it. A failure only leaves an unused file behind, so
+    /// it is logged
+    fn release_blob(&self, notes: &[StoredNote], digest: &str) {
+        let in_use = notes
+            .iter()
+            .flat_map(|note| &note.attachments)
+            .any(|attachment| attachment.digest == digest);
+        if !in_use {
+            let removed = self.blobs.remove(digest).and_then(|removed| match removed {
+                true => self.texts.remove(digest),
+                false => Ok(()),
+            });
+            if let Err(err) = removed {
+                warn!("{:?}", err);
+            }
+        }
+    }
+
+    /// Attachments of notes that are not encrypted whose text was not
+    /// extracted yet, one per blob
+    pub fn pending_texts(&self) -> Vec<StoredAttachment> {
+        let state = self.read();
+        let mut pending: Vec<StoredAttachment> = vec![];
+        for attachment in state
+            .notes
+            .iter()
+            .filter(|note| !note.encrypted)
+            .flat_map(|note| &note.attachments)
+        {
+            if TextFormat::of(attachment).is_some()
+                && !self.texts.contains(&attachment.digest)
+                && !pending
+                    .iter()
+                    .any(|other| other.digest == attachment.digest)
+            {
+                pending.push(attachment.clone());
+            }
+        }
+        pending
+    }
+
+    /// The data of the blob with `digest`
+    pub fn read_blob(&self, digest: &str) -> Result<Vec<u8>, BppStoreError> {
+        let mut data = vec![];
+        self.blobs
+            .open_blob(digest)?
+            .read_to_end(&mut data)
+            .into_report()
+            .change_context_lazy(|| {
+                BppStoreError::FailedToOpenStore(format!("Could not read blob {digest}"))
+            })?;
+        Ok(data)
+    }
+
+    /// Store `text`, extracted from the blob with `digest`, and index it
+    /// for every attachment with this data. Nothing is stored when the
+    /// blob was removed meanwhile. Returns the number of notes indexed
+    pub fn store_text(&self, digest: &str, text: &str) -> Result<usize, BppStoreError> {
+        let mut state = self.write_open()?;
+        let attached: Vec<(String, String)> = state
+            .notes
+            .iter()
+            .filter(|note| !note.encrypted)
+            .flat_map(|note| {
+                note.attachments
+                    .iter()
+                    .filter(|attachment| attachment.digest == digest)
+                    .map(|attachment| (note.id.clone(), attachment.name.clone()))
+            })
+            .collect();
+        if attached.is_empty() {
+            return Ok(0);
+        }
+
+        self.texts.put(digest, text)?;
+        for (id, name) in &attached {
+            state.index.insert_attachment(id, name, text);
+        }
+        self.save_index(&state.index)?;
+        Ok(attached
+            .iter()
+            .map(|(id, _)| id)
+            .collect::<BTreeSet<_>>()
+            .len())
+    }
+
+    /// The search index of `notes`, with the text extracted from their
+    /// attachments
+    fn build_index(&self, notes: &[StoredNote]) -> SearchIndex {
+        let mut index = SearchIndex::build(notes);
+        for note in notes.iter().filter(|note| !note.encrypted) {
+            for attachment in &note.attachments {
+                if let Some(text) = self.texts.get(&attachment.digest) {
+                    index.insert_attachment(&note.id, &attachment.name, &text);
+                }
+            }
+        }
+        index
+    }
+
+    fn unindex_attachments(&self, index: &mut SearchIndex, note: &StoredNote) {
+        for attachment in &note.attachments {
+            if let Some(text) = self.texts.get(&attachment.digest) {
+                index.remove_attachment(&note.id, &attachment.name, &text);
+            }
+        }
+    }
+
+    /// The note `id`, None when there is no such note
+    pub fn note(&self, id: &str) -> Option<StoredNote> {
+        self.read().notes.iter().find(|note| note.id == id).cloned()
+    }
+
+    /// Notes matching `query`, see `SearchIndex::search`. With
+    /// `attachments`, each with the attachments containing a term of it
+    pub fn search(&self, query: &str, all: bool, attachments: bool) -> Vec<SearchHit> {
+        let state = self.read();
+        let ids = state.index.search(query, all, attachments);
+        let mut matches = if attachments {
+            state.index.matching_attachments(query, &ids)
+        } else {
+            BTreeMap::new()
+        };
+        state
+            .notes
+            .iter()
+            .filter(|note| ids.contains(&note.id))
+            .map(|note| SearchHit {
+                attachments: matches
+                    .remove(&note.id)
+                    .map(|names| {
+                        // In the order they were attached
+                        note.attachments
+                            .iter()
+                            .filter(|attachment| names.contains(&attachment.name))
+                            .map(|attachment| attachment.name.clone())
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                note: note.clone(),
+            })
+            .collect()
+    }
+
+    /// The note `id` and the targets of its links, each with the note it
+    /// resolves to. None when there is no such note
+    pub fn links(&self, id: &str) -> Option<(StoredNote, Vec<ResolvedLink>)> {
+        let state = self.read();
+        let note = state.notes.iter().find(|note| note.id == id)?.clone();
+        let links = state
+            .links
+            .links(id)
+            .into_iter()
+            .map(|(target, resolved)| ResolvedLink {
+                note: resolved
+                    .and_then(|id| state.notes.iter().find(|note| note.id == id).cloned()),
+                target,
+            })
+            .collect();
+        Some((note, links))
+    }
+
+    /// Notes linking to the note `id`, in insertion order. None when there
+    /// is no such note
+    pub fn backlinks(&self, id: &str) -> Option<Vec<StoredNote>> {
+        let state = self.read();
+        let note = state.notes.iter().find(|note| note.id == id)?;
+        let ids = state.links.backlinks(note);
+        Some(
+            state
+                .notes
+                .iter()
+                .filter(|note| ids.contains(&note.id))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Notes filed in `notebook` or below it and tagged `tag`, ignoring
+    /// case and a leading `#`, with the links between them. Empty filters
+    /// match every note
+    pub fn graph(&self, notebook: &str, tag: &str) -> NoteGraph {
+        let notebook = notebook.trim().trim_matches('/');
+        let tag = tag.trim().trim_start_matches('#').to_lowercase();
+        let in_notebook = |note: &StoredNote| {
+            notebook.is_empty()
+                || note.notebook == notebook
+                || note
+                    .notebook
+                    .strip_prefix(notebook)
+                    .is_some_and(|rest| rest.starts_with('/'))
+        };
+        let tagged = |note: &StoredNote| {
+            tag.is_empty() || note.tags.iter().any(|other| other.to_lowercase() == tag)
+        };
+
+        let state = self.read();
+        let notes: Vec<StoredNote> = state
+            .notes
+            .iter()
+            .filter(|note| in_notebook(note) && tagged(note))
+            .cloned()
+            .collect();
+        let ids: HashSet<&str> = notes.iter().map(|note| note.id.as_str()).collect();
+        let mut links = vec![];
+        let mut seen = HashSet::new();
+        for note in &notes {
+            for (_, target) in state.links.links(&note.id) {
+                let target = match target {
+                    Some(target) if ids.contains(target.as_str()) => target,
+                    _ => continue,
+                };
+                if seen.insert((note.id.clone(), target.clone())) {
+                    links.push((note.id.clone(), target));
+                }
+            }
+        }
+        NoteGraph { notes, links }
+    }
+
+    pub fn notes(&self) -> Vec<StoredNote> {
+        self.read().notes.clone()
+    }
+
+    /// Rebuild the search index from the stored notes.
+    /// Returns the number of notes indexed
+    pub fn reindex(&self) -> Result<usize, BppStoreError> {
+        let mut state = self.write_open()?;
+        state.index = self.build_index(&state.notes);
+        self.save_index(&state.index)?;
+        Ok(state.notes.len())
+    }
+
+    /// Encrypt the store with a new key derived from `passphrase`, see
+    /// `Keyring::rotate`, and write the notes and search index again with
+    /// it. The audit log is never rewritten, its records are opened with
+    /// the retired keys.
+    /// Returns the ids of the previous and new keys
+    pub fn rotate_key(&self, passphrase: &Passphrase) -> Result<(String, String), BppStoreError> {
+        let state = self.write_open()?;
+        let keys = self.keys().ok_or_else(|| {
+            report!(BppStoreError::Encryption(
+                "the store is not encrypted, set database.passphrase_file and start the \
+                 server first"
+                    .to_string()
+            ))
+        })?;
+        let rotated = Arc::new(keys.rotate(&self.data_dir, passphrase)?);
+        *self.keys.write().unwrap_or_else(|err| err.into_inner()) = Some(rotated.clone());
+        // The notes still open with the retired key should this fail
+        self.save_notes(&state.notes)?;
+        self.save_index(&state.index)?;
+        Ok((keys.id().to_string(), rotated.id().to_string()))
+    }
+
+    /// Upgrade the notes file in `data_dir` to `STORE_VERSION` with the
+    /// steps of `migrations`. The previous file is kept as
+    /// `notes.v<version>.json`. With `dry_run` the steps are applied and
+    /// checked in memory only. Stores newer than this server are refused,
+    /// and so are stores open for writing by another process
+    pub fn migrate(data_dir: &Path, dry_run: bool) -> Result<MigrationReport, BppStoreError> {
+        let _lock = match dry_run || !data_dir.join(NOTES_FILE).exists() {
+            true => None,
+            false => Some(DataDirLock::acquire(data_dir)?),
+        };
+        Self::migrate_locked(data_dir, dry_run)
+    }
+
+    fn migrate_locked(data_dir: &Path, dry_run: bool) -> Result<MigrationReport, BppStoreError> {
+        let path = data_dir.join(NOTES_FILE);
+        let mut report = MigrationReport {
+            from: STORE_VERSION,
+            to: STORE_VERSION,
+            steps: vec![],
+            previous_file: None,
+        };
+        if !path.exists() {
+            return Ok(report);
+        }
+
+        let _timer = metrics::store_timer("migrate");
+        let mut document: serde_json::Value = fs::read(&path)
+            .into_report()
+            .change_context_lazy(|| {
+                BppStoreError::FailedToOpenStore(format!("Could not read {}", path.display()))
+            })
+            .and_then(|bytes| {
+                serde_json::from_slice(&bytes)
+                    .into_report()
+                    .change_context_lazy(|| {
+                        BppStoreError::FailedToOpenStore(format!("{} is corrupted", path.display()))
+                    })
+            })?;
+        let version = document
+            .get("version")
+            .and_then(serde_json::Value::as_u64)
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| {
+                report!(BppStoreError::FailedToOpenStore(format!(
+                    "{} has no version",
+                    path.display()
+                )))
+            })?;
+        if version > STORE_VERSION {
+            return Err(report!(BppStoreError::UnsupportedVersion(version)));
+        }
+        report.from = version;
+        if version == STORE_VERSION {
+            return Ok(report);
+        }
+
+        let steps = migrations::steps(version, STORE_VERSION).ok_or_else(|| {
+            report!(BppStoreError::FailedToMigrate(format!(
+                "no migration from version {version}"
+            )))
+        })?;
+        for step in steps {
+            step.apply(&mut document).map_err(|err| {
+                report!(BppStoreError::FailedToMigrate(format!(
+                    "{} -> {}: {err}",
+                    step.from,
+                    step.from + 1
+                )))
+            })?;
+            report.steps.push(step.description);
+        }
+        // The result must read as the current format before replacing
+        // anything
+        let notes_file: NotesFile = serde_json::from_value(document)
+            .into_report()
+            .change_context(BppStoreError::FailedToMigrate(
+                "the migrated notes are not valid".to_string(),
+            ))?;
+        if dry_run {
+            return Ok(report);
+        }
+
+        let previous_file = data_dir.join(format!("notes.v{version}.json"));
+        fs::copy(&path, &previous_file)
+            .into_report()
+            .change_context_lazy(|| {
+                BppStoreError::FailedToMigrate(format!(
+                    "Could not copy {} to {}",
+                    path.display(),
+                    previous_file.display()
+                ))
+            })?;
+        let bytes = serde_json::to_vec_pretty(&notes_file)
+            .into_report()
+            .change_context(BppStoreError::FailedToWriteStore(
+                "Could not serialize notes".to_string(),
+            ))?;
+        write_atomic(&path, &bytes)?;
+        report.previous_file = Some(previous_file);
+        Ok(report)
+    }
+
+    /// Write every note as a versioned json document
+    pub fn export(&self, writer: impl Write) -> Result<usize, BppStoreError> {
+        let export = ExportFile {
+            version: EXPORT_VERSION,
+            notes: self.notes(),
+        };
+        serde_json::to_writer_pretty(writer, &export)
+            .into_report()
+            .change_context(BppStoreError::FailedToWriteStore(
+                "Could not write export".to_string(),
+            ))?;
+        Ok(export.notes.len())
+    }
+
+    /// Add the notes of a document produced by `NoteDao::export`. Notes
+    /// whose id already exists are skipped.
+    /// Returns the imported notes and the number of skipped notes
+    pub fn import(&self, reader: impl Read) -> Result<(Vec<StoredNote>, usize), BppStoreError> {
+        let export: ExportFile = serde_json::from_reader(reader)
+            .into_report()
+            .change_context(BppStoreError::FailedToWriteStore(
+                "Import is not a valid bpp export".to_string(),
+            ))?;
+        if export.version > EXPORT_VERSION {
+            return Err(report!(BppStoreError::UnsupportedVersion(export.version)));
+        }
+        for note in &export.notes {
+            if let Some(attachment) = note
+                .attachments
+                .iter()
+                .find(|attachment| !is_digest(&attachment.digest))
+            {
+                return Err(report!(BppStoreError::FailedToWriteStore(format!(
+                    "Attachment {} of note {} has an invalid digest {:?}",
+                    attachment.name, note.id, attachment.digest
+                ))));
+            }
+        }
+
+        let mut state = self.write_open()?;
+        let previous = state.notes.clone();
+        let mut skipped = 0;
+        for note in export.notes {
+            if state.notes.iter().any(|existing| existing.id == note.id) {
+                skipped += 1;
+            } else {
+                state.notes.push(note);
+            }
+        }
+        let imported = state.notes[previous.len()..].to_vec();
+
+        if let Err(err) = self.save_notes(&state.notes) {
+            state.notes = previous;
+            return Err(err);
+        }
+        state.index = self.build_index(&state.notes);
+        state.links = LinkGraph::build(&state.notes);
+        self.save_index_after_notes(&state.index);
+        Ok((imported, skipped))
+    }
+
+    /// The keys of the store, None when it is not encrypted
+    pub fn keys(&self) -> Option<Arc<Keyring>> {
+        self.keys
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+
+    /// Add the `notes` that do not duplicate a stored note or an earlier
+    /// one of `notes`, comparing titles and contents. Ids are kept unless
+    /// taken, encrypted notes with a taken id are not added. Missing
+    /// timestamps are set to now. With `dry_run` nothing is stored. Unlike
+    /// `NoteDao::import`, the quota applies to the result. Returns the
+    /// outcome of every note, in order
+    pub fn merge(
+        &self,
+        notes: Vec<StoredNote>,
+        dry_run: bool,
+    ) -> Result<Vec<Merged>, BppStoreError> {
+        let mut state = self.write_open()?;
+        let mut digests: HashMap<[u8; 32], String> = state
+            .notes
+            .iter()
+            .map(|note| (content_digest(note), note.id.clone()))
+            .collect();
+        let mut ids: HashSet<String> = state.notes.iter().map(|note| note.id.clone()).collect();
+
+        let now = unix_millis();
+        let mut merged = Vec::with_capacity(notes.len());
+        let mut added = vec![];
+        for mut note in notes {
+            let digest = content_digest(&note);
+            if let Some(id) = digests.get(&digest) {
+                merged.push(Merged::Duplicate(id.clone()));
+                continue;
+            }
+            if note.encrypted && ids.contains(&note.id) {
+                merged.push(Merged::IdTaken(note.id));
+                continue;
+            }
+            if note.id.is_empty() || ids.contains(&note.id) {
+                note.id = Uuid::new_v4().to_string();
+            }
+            if note.created_ms == 0 {
+                note.created_ms = now;
+            }
+            if note.updated_ms == 0 {
+                note.updated_ms = note.created_ms;
+            }
+            digests.insert(digest, note.id.clone());
+            ids.insert(note.id.clone());
+            merged.push(Merged::Added(note.clone()));
+            added.push(note);
+        }
+
+        let note_count = state.notes.len() + added.len();
+        if note_count > self.quota.max_notes {
+            return Err(report!(BppStoreError::QuotaExceeded(format!(
+                "the import would bring the store to {note_count} notes, the maximum is {}",
+                self.quota.max_notes
+            ))));
+        }
+        let storage_bytes = storage_bytes(state.notes.iter().chain(&added));
+        if storage_bytes > self.quota.max_storage_bytes {
+            return Err(report!(BppStoreError::QuotaExceeded(format!(
+                "notes would take {storage_bytes} bytes, the maximum is {}",
+                self.quota.max_storage_bytes
+            ))));
+        }
+        if dry_run || added.is_empty() {
+            return Ok(merged);
+        }
+
+        let previous = state.notes.len();
+        state.notes.extend(added.iter().cloned());
+        if let Err(err) = self.save_notes(&state.notes) {
+            state.notes.truncate(previous);
+            return Err(err);
+        }
+        for note in &added {
+            state.index.insert(note);
+            state.links.insert(note);
+        }
+        self.save_index_after_notes(&state.index);
+        Ok(merged)
+    }
+
+    /// Write a backup of the store to `path`, see `backup::encode`. The
+    /// lock is only held while the notes are copied and the attachments
+    /// they refer to linked into a `Snapshot`, so writes are not blocked
+    /// while the backup is compressed and written. The file is read back
+    /// and compared to the snapshot before returning
+    pub fn backup(&self, path: &Path) -> Result<BackupHeader, BppStoreError> {
+        let _timer = metrics::store_timer("backup");
+        let mut snapshot = Snapshot::new(self.blobs.tmp_path())?;
+        let notes = {
+            let state = self.read();
+            let digests: BTreeSet<&str> = state
+                .notes
+                .iter()
+                .flat_map(|note| &note.attachments)
+                .map(|attachment| attachment.digest.as_str())
+                .collect();
+            for digest in digests {
+                snapshot.link(&self.data_dir, &self.blobs.path(digest)?)?;
+                let text = self.texts.path(digest)?;
+                if text.exists() {
+                    snapshot.link(&self.data_dir, &text)?;
+                }
+            }
+            state.notes.clone()
+        };
+        let notes = self.sealed(&notes)?;
+        for name in [TEMPLATES_FILE, KEYS_FILE] {
+            let path = self.data_dir.join(name);
+            if path.exists() {
+                snapshot.link(&self.data_dir, &path)?;
+            }
+        }
+        let audit = self.data_dir.join(AUDIT_FILE);
+        if audit.exists() {
+            snapshot.copy(&self.data_dir, &audit)?;
+        }
+
+        let not_written =
+            || BppStoreError::FailedToWriteStore(format!("Could not write {}", path.display()));
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .into_report()
+                .change_context_lazy(|| {
+                    BppStoreError::FailedToWriteStore(format!("Could not create {}", dir.display()))
+                })?;
+        }
+        let tmp_path = path.with_extension("tmp");
+        let written = fs::File::create(&tmp_path)
+            .into_report()
+            .change_context_lazy(not_written)
+            .and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                let header = backup::encode(&notes, &snapshot, unix_millis(), &mut writer)?;
+                writer
+                    .into_inner()
+                    .map_err(IntoInnerError::into_error)
+                    .and_then(|file| file.sync_all())
+                    .and_then(|_| fs::rename(&tmp_path, path))
+                    .into_report()
+                    .change_context_lazy(not_written)?;
+                Ok(header)
+            });
+        let header = written.inspect_err(|_| {
+            fs::remove_file(&tmp_path).ok();
+        })?;
+
+        if backup::read(path)?.notes != notes {
+            return Err(report!(BppStoreError::InvalidBackup(format!(
+                "{} does not hold the notes that were backed up",
+                path.display()
+            ))));
+        }
+        Ok(header)
+    }
+
+    /// Replace every note with the notes of `backup`, write its files, see
+    /// `backup::restore_files`, and rebuild the search index. The keys of
+    /// an encrypted backup are added to the keys of the store, see
+    /// `Keyring::merge`. The notes file is read back and compared to the
+    /// backup before returning
+    pub fn restore(&self, backup: &Backup) -> Result<Restored, BppStoreError> {
+        if backup.header.store_version > STORE_VERSION {
+            return Err(report!(BppStoreError::UnsupportedVersion(
+                backup.header.store_version
+            )));
+        }
+
+        let _timer = metrics::store_timer("restore");
+        let mut state = self.write_open()?;
+        // They also open the audit log the backup may hold
+        if let Some(bytes) = backup::read_file(backup, KEYS_FILE)? {
+            let keys = self.keys().ok_or_else(|| {
+                report!(BppStoreError::Encryption(format!(
+                    "{} is encrypted, set database.passphrase_file",
+                    backup.path.display()
+                )))
+            })?;
+            let merged = keys.merge(&self.data_dir, &bytes)?;
+            *self.keys.write().unwrap_or_else(|err| err.into_inner()) = Some(Arc::new(merged));
+        }
+        let notes = unsealed(self.keys().as_deref(), backup.notes.clone())?;
+        // Before the index is built, it holds the texts of the attachments
+        let files = backup::restore_files(backup, &self.data_dir)?;
+        self.save_notes(&notes)?;
+        state.notes = notes;
+        state.index = self.build_index(&state.notes);
+        state.links = LinkGraph::build(&state.notes);
+        self.save_index_after_notes(&state.index);
+
+        let stored = Self::read_notes_file(&self.data_dir.join(NOTES_FILE))?.notes;
+        if unsealed(self.keys().as_deref(), stored)? != state.notes {
+            return Err(report!(BppStoreError::InvalidBackup(
+                "the restored notes differ from the backup".to_string()
+            )));
+        }
+        let missing_blobs = state
+            .notes
+            .iter()
+            .flat_map(|note| &note.attachments)
+            .map(|attachment| attachment.digest.as_str())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .filter(|digest| self.blobs.path(digest).map_or(true, |path| !path.exists()))
+            .map(str::to_string)
+            .collect();
+        Ok(Restored {
+            notes: state.notes.len(),
+            files,
+            missing_blobs,
+        })
+    }
+
+    /// Check that the notes file can still be read and the data directory
+    /// written to, eg. it was not removed or the disk is not full
+    pub fn check(&self) -> Result<(), BppStoreError> {
+        Self::read_notes_file(&self.data_dir.join(NOTES_FILE))?;
+        let probe = self.data_dir.join(PROBE_FILE);
+        write_atomic(&probe, b"")?;
+        fs::remove_file(&probe)
+            .into_report()
+            .change_context_lazy(|| {
+                BppStoreError::FailedToWriteStore(format!("Could not remove {}", probe.display()))
+            })
+    }
+
+    /// Wait for writes in progress, persist the search index and sync the
+    /// data directory so the last renames survive a crash. The store
+    /// rejects writes afterwards
+    pub fn flush(&self) -> Result<(), BppStoreError> {
+        let mut state = self.write();
+        state.closed = true;
+        self.save_index(&state.index)?;
+
+        let _timer = metrics::store_timer("flush");
+        fs::File::open(&self.data_dir)
+            .and_then(|dir| dir.sync_all())
+            .into_report()
+            .change_context_lazy(|| {
+                BppStoreError::FailedToWriteStore(format!(
+                    "Could not sync {}",
+                    self.data_dir.display()
+                ))
+            })
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, State> {
+        // A panic while holding the lock never leaves the state half
+        // written, see `add` and `remove`
+        self.state.read().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, State> {
+        self.state.write().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Lock the state for a write, unless the store was flushed
+    fn write_open(&self) -> Result<RwLockWriteGuard<'_, State>, BppStoreError> {
+        let state = self.write();
+        if state.closed {
+            return Err(report!(BppStoreError::Closed));
+        }
+        Ok(state)
+    }
+
+    fn read_notes_file(path: &Path) -> Result<NotesFile, BppStoreError> {
+        if !path.exists() {
+            return Ok(NotesFile {
+                version: STORE_VERSION,
+                notes: vec![],
+            });
+        }
+
+        let _timer = metrics::store_timer("read_notes");
+        let bytes = fs::read(path).into_report().change_context_lazy(|| {
+            BppStoreError::FailedToOpenStore(format!("Could not read {}", path.display()))
+        })?;
+        serde_json::from_slice(&bytes)
+            .into_report()
+            .change_context_lazy(|| {
+                BppStoreError::FailedToOpenStore(format!("{} is corrupted", path.display()))
+            })
+    }
+
+    fn save_notes(&self, notes: &[StoredNote]) -> Result<(), BppStoreError> {
+        let _timer = metrics::store_timer("save_notes");
+        let notes_file = NotesFile {
+            version: STORE_VERSION,
+            notes: self.sealed(notes)?,
+        };
+        let bytes = serde_json::to_vec_pretty(&notes_file)
+            .into_report()
+            .change_context(BppStoreError::FailedToWriteStore(
+                "Could not serialize notes".to_string(),
+            ))?;
+        write_atomic(&self.data_dir.join(NOTES_FILE), &bytes)
+    }
+
+    fn save_index(&self, index: &SearchIndex) -> Result<(), BppStoreError> {
+        let _timer = metrics::store_timer("save_index");
+        let mut bytes = serde_json::to_vec(index).into_report().change_context(
+            BppStoreError::FailedToWriteStore("Could not serialize search index".to_string()),
+        )?;
+        if let Some(keys) = self.keys() {
+            bytes = keys.seal(&bytes, INDEX_FILE)?.into_bytes();
+        }
+        write_atomic(&self.data_dir.join(INDEX_FILE), &bytes)?;
+        metrics::record_index(index.note_count(), index.term_count(), bytes.len());
+        Ok(())
+    }
+
+    /// Save `index` once the notes it was updated for are saved. Failing
+    /// the write at this point would report stored notes as not stored, so
+    /// the error is logged instead and the index file removed, to be
+    /// rebuilt when the store is next opened. The index in memory is up to
+    /// date either way
+    fn save_index_after_notes(&self, index: &SearchIndex) {
+        let Err(err) = self.save_index(index) else {
+            return;
+        };
+        error!("Failed to save the search index, it is rebuilt on the next start\n{err:?}");
+        let path = self.data_dir.join(INDEX_FILE);
+        if let Err(err) = fs::remove_file(&path) {
+            if err.kind() != io::ErrorKind::NotFound {
+                error!("Failed to remove the stale {}: {err}", path.display());
+            }
+        }
+    }
+
+    /// The search index as written by `save_index`. None when it cannot be
+    /// read, or is not sealed while the store is encrypted
+    fn read_index(&self, bytes: Vec<u8>) -> Option<SearchIndex> {
+        let bytes = match self.keys() {
+            Some(keys) => {
+                let sealed = String::from_utf8(bytes)
+                    .ok()
+                    .filter(|bytes| keys::is_sealed(bytes))?;
+                keys.open(&sealed, INDEX_FILE).ok()?
+            }
+            None => bytes,
+        };
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    /// `notes` as written to disk, titles and contents sealed when the
+    /// store is encrypted
+    fn sealed(&self, notes: &[StoredNote]) -> Result<Vec<StoredNote>, BppStoreError> {
+        let Some(keys) = self.keys() else {
+            return Ok(notes.to_vec());
+        };
+        notes
+            .iter()
+            .map(|note| {
+                Ok(StoredNote {
+                    title: keys.seal(note.title.as_bytes(), &note_aad(&note.id, "title"))?,
+                    content: keys.seal(note.content.as_bytes(), &note_aad(&note.id, "content"))?,
+                    ..note.clone()
+                })
+            })
+            .collect()
+    }
+}
+
+/// `notes` as read from disk with their titles and contents opened, see
+/// `NoteDao::sealed`
+fn unsealed(
+    keys: Option<&Keyring>,
+    notes: Vec<StoredNote>,
+) -> Result<Vec<StoredNote>, BppStoreError> {
+    notes
+        .into_iter()
+        .map(|mut note| {
+            match keys {
+                Some(keys) => {
+                    note.title = keys.open_str(&note.title, &note_aad(&note.id, "title"))?;
+                    note.content = keys.open_str(&note.content, &note_aad(&note.id, "content"))?;
+                }
+                None if keys::is_sealed(&note.title) || keys::is_sealed(&note.content) => {
+                    return Err(report!(BppStoreError::Encryption(format!(
+                        "note {} is encrypted but the store has no {KEYS_FILE}",
+                        note.id
+                    ))));
+                }
+                None => {}
+            }
+            Ok(note)
+        })
+        .collect()
+}
+
+/// Authenticated with a field of a note when sealed, so it cannot be moved
+/// to another field or note
+fn note_aad(id: &str, field: &str) -> String {
+    format!("note {id} {field}")
+}
+
+/// Exclusive lock on the `LOCK_FILE` of a data directory, held by the
+/// process writing to the store until dropped. Readers do not take it, as
+/// files are only ever replaced atomically
+#[derive(Debug)]
+pub struct DataDirLock {
+    _file: fs::File,
+}
+
+impl DataDirLock {
+    /// Lock `data_dir`, creating it if needed. Fails right away when
+    /// another process holds the lock
+    pub fn acquire(data_dir: &Path) -> Result<Self, BppStoreError> {
+        fs::create_dir_all(data_dir)
+            .into_report()
+            .change_context_lazy(|| {
+                BppStoreError::FailedToOpenStore(format!(
+                    "Could not create data dir {}",
+                    data_dir.display()
+                ))
+            })?;
+        let path = data_dir.join(LOCK_FILE);
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .into_report()
+            .change_context_lazy(|| {
+                BppStoreError::FailedToOpenStore(format!("Could not open {}", path.display()))
+            })?;
+        match file.try_lock() {
+            Ok(()) => Ok(DataDirLock { _file: file }),
+            Err(fs::TryLockError::WouldBlock) => Err(report!(BppStoreError::Locked(
+                data_dir.display().to_string()
+            ))),
+            Err(fs::TryLockError::Error(err)) => Err(report!(err).change_context(
+                BppStoreError::FailedToOpenStore(format!("Could not lock {}", path.display())),
+            )),
+        }
+    }
+}
+
+/// Milliseconds since the unix epoch
+pub fn unix_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as i64)
+}
+
+/// Identifies notes with the same title and content. A sha256 rather than
+/// a 64 bits hash, a collision would drop an imported note as a duplicate.
+/// The title is prefixed with its length so no two notes hash the same
+/// input
+fn content_digest(note: &StoredNote) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update((note.title.len() as u64).to_le_bytes());
+    hasher.update(&note.title);
+    hasher.update(&note.content);
+    hasher.update([note.encrypted as u8]);
+    hasher.finalize().into()
+}
+
+/// Size counted against `Quota::max_storage_bytes`: titles, contents and
+/// attached data, counted once however many times it is attached
+fn storage_bytes<'a>(notes: impl IntoIterator<Item = &'a StoredNote>) -> usize {
+    let mut blobs = HashMap::new();
+    let mut bytes = 0;
+    for note in notes {
+        bytes += note.title.len() + note.content.len();
+        for attachment in &note.attachments {
+            blobs.insert(&attachment.digest, attachment.size as usize);
+        }
+    }
+    bytes + blobs.values().sum::<usize>()
+}
+
+/// Write `bytes` to a temporary file and rename it over `path`, so readers
+/// never observe a partially written file
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> Result<(), BppStoreError> {
+    let tmp_path = path.with_extension("tmp");
+    let write = || -> std::io::Result<()> {
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)
+    };
+
+    write().into_report().change_context_lazy(|| {
+        BppStoreError::FailedToWriteStore(format!("Could not write {}", path.display()))
+    })
+}
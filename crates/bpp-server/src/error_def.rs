@@ -1,10 +1,14 @@
 use std::error::Error;
 use std::fmt;
 use std::fmt::Formatter;
+use std::time::Duration;
+use tonic::Code;
 
 #[derive(Debug)]
 pub enum BppConfigError {
     FailedToLoadConfigOptions(String),
+    FailedToResolveSecret(String),
+    InvalidConfigValue(String),
 }
 
 impl fmt::Display for BppConfigError {
@@ -13,8 +17,207 @@ impl fmt::Display for BppConfigError {
             BppConfigError::FailedToLoadConfigOptions(msg) => {
                 f.write_str(format!("Invalid config options: {msg}").as_str())
             }
+            BppConfigError::FailedToResolveSecret(msg) => {
+                f.write_str(format!("Failed to resolve secret: {msg}").as_str())
+            }
+            BppConfigError::InvalidConfigValue(msg) => {
+                f.write_str(format!("Invalid config value: {msg}").as_str())
+            }
         }
     }
 }
 
 impl Error for BppConfigError {}
+
+#[derive(Debug)]
+pub enum BppStoreError {
+    FailedToOpenStore(String),
+    FailedToWriteStore(String),
+    UnsupportedVersion(u32),
+    MigrationRequired(u32),
+    FailedToMigrate(String),
+    Closed,
+    QuotaExceeded(String),
+    InvalidBackup(String),
+    NoteExists(String),
+    Locked(String),
+    Encryption(String),
+}
+
+impl fmt::Display for BppStoreError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BppStoreError::FailedToOpenStore(msg) => {
+                f.write_str(format!("Failed to open store: {msg}").as_str())
+            }
+            BppStoreError::FailedToWriteStore(msg) => {
+                f.write_str(format!("Failed to write store: {msg}").as_str())
+            }
+            BppStoreError::UnsupportedVersion(version) => f.write_str(
+                format!("Store version {version} is newer than this server supports").as_str(),
+            ),
+            BppStoreError::MigrationRequired(version) => f.write_str(
+                format!("Store is at version {version}, run `bpp-server migrate` to upgrade it")
+                    .as_str(),
+            ),
+            BppStoreError::FailedToMigrate(msg) => {
+                f.write_str(format!("Failed to migrate store: {msg}").as_str())
+            }
+            BppStoreError::Closed => f.write_str("Store is closed"),
+            BppStoreError::QuotaExceeded(msg) => {
+                f.write_str(format!("Quota exceeded: {msg}").as_str())
+            }
+            BppStoreError::InvalidBackup(msg) => {
+                f.write_str(format!("Invalid backup: {msg}").as_str())
+            }
+            BppStoreError::NoteExists(id) => {
+                f.write_str(format!("Note {id} already exists").as_str())
+            }
+            BppStoreError::Locked(data_dir) => f.write_str(
+                format!("{data_dir} is in use by another process, stop bpp-server first").as_str(),
+            ),
+            BppStoreError::Encryption(msg) => {
+                f.write_str(format!("Encryption failed: {msg}").as_str())
+            }
+        }
+    }
+}
+
+impl Error for BppStoreError {}
+
+#[derive(Debug)]
+pub enum BppServerError {
+    FailedToStart(String),
+    FailedToServe(String),
+    FailedToStop(String),
+    FailedToExport(String),
+    FailedToImport(String),
+    FailedToBackup(String),
+    FailedToRestore(String),
+    FailedToRotateKey(String),
+}
+
+impl fmt::Display for BppServerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BppServerError::FailedToStart(msg) => {
+                f.write_str(format!("Failed to start: {msg}").as_str())
+            }
+            BppServerError::FailedToServe(msg) => {
+                f.write_str(format!("Failed to serve: {msg}").as_str())
+            }
+            BppServerError::FailedToStop(msg) => {
+                f.write_str(format!("Failed to stop cleanly: {msg}").as_str())
+            }
+            BppServerError::FailedToExport(msg) => {
+                f.write_str(format!("Failed to export notes: {msg}").as_str())
+            }
+            BppServerError::FailedToImport(msg) => {
+                f.write_str(format!("Failed to import notes: {msg}").as_str())
+            }
+            BppServerError::FailedToBackup(msg) => {
+                f.write_str(format!("Failed to back up notes: {msg}").as_str())
+            }
+            BppServerError::FailedToRestore(msg) => {
+                f.write_str(format!("Failed to restore notes: {msg}").as_str())
+            }
+            BppServerError::FailedToRotateKey(msg) => {
+                f.write_str(format!("Failed to rotate the key: {msg}").as_str())
+            }
+        }
+    }
+}
+
+impl Error for BppServerError {}
+
+/// Errors returned by the rpcs. Each variant maps to a grpc code, see
+/// `BppServiceError::code`
+#[derive(Debug)]
+pub enum BppServiceError {
+    NoteNotFound(String),
+    NoteExists(String),
+    AttachmentNotFound(String),
+    TemplateNotFound(String),
+    TemplateExists(String),
+    InvalidArgument { field: String, description: String },
+    RateLimited(Duration),
+    QuotaExceeded(String),
+    Unavailable(String),
+    Internal(String),
+}
+
+impl BppServiceError {
+    pub fn code(&self) -> Code {
+        match self {
+            BppServiceError::NoteNotFound(_) => Code::NotFound,
+            BppServiceError::NoteExists(_) => Code::AlreadyExists,
+            BppServiceError::AttachmentNotFound(_) => Code::NotFound,
+            BppServiceError::TemplateNotFound(_) => Code::NotFound,
+            BppServiceError::TemplateExists(_) => Code::AlreadyExists,
+            BppServiceError::InvalidArgument { .. } => Code::InvalidArgument,
+            BppServiceError::RateLimited(_) => Code::ResourceExhausted,
+            BppServiceError::QuotaExceeded(_) => Code::ResourceExhausted,
+            BppServiceError::Unavailable(_) => Code::Unavailable,
+            BppServiceError::Internal(_) => Code::Internal,
+        }
+    }
+
+    /// Machine readable cause, sent in the `x-bpp-error-reason` metadata
+    pub fn reason(&self) -> &'static str {
+        match self {
+            BppServiceError::NoteNotFound(_) => "NOTE_NOT_FOUND",
+            BppServiceError::NoteExists(_) => "NOTE_EXISTS",
+            BppServiceError::AttachmentNotFound(_) => "ATTACHMENT_NOT_FOUND",
+            BppServiceError::TemplateNotFound(_) => "TEMPLATE_NOT_FOUND",
+            BppServiceError::TemplateExists(_) => "TEMPLATE_EXISTS",
+            BppServiceError::InvalidArgument { .. } => "INVALID_ARGUMENT",
+            BppServiceError::RateLimited(_) => "RATE_LIMITED",
+            BppServiceError::QuotaExceeded(_) => "QUOTA_EXCEEDED",
+            BppServiceError::Unavailable(_) => "UNAVAILABLE",
+            BppServiceError::Internal(_) => "INTERNAL",
+        }
+    }
+}
+
+impl fmt::Display for BppServiceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BppServiceError::NoteNotFound(id) => {
+                f.write_str(format!("Note {id} not found").as_str())
+            }
+            BppServiceError::NoteExists(id) => {
+                f.write_str(format!("Note {id} already exists").as_str())
+            }
+            BppServiceError::AttachmentNotFound(name) => {
+                f.write_str(format!("Attachment {name} not found").as_str())
+            }
+            BppServiceError::TemplateNotFound(name) => {
+                f.write_str(format!("Template {name} not found").as_str())
+            }
+            BppServiceError::TemplateExists(name) => {
+                f.write_str(format!("Template {name} already exists").as_str())
+            }
+            BppServiceError::InvalidArgument { field, description } => {
+                f.write_str(format!("Invalid {field}: {description}").as_str())
+            }
+            BppServiceError::RateLimited(retry_after) => f.write_str(
+                format!(
+                    "Too many requests, retry in {:.1}s",
+                    retry_after.as_secs_f64()
+                )
+                .as_str(),
+            ),
+            BppServiceError::QuotaExceeded(msg) => {
+                f.write_str(format!("Quota exceeded: {msg}").as_str())
+            }
+            BppServiceError::Unavailable(msg) => {
+                f.write_str(format!("Service unavailable: {msg}").as_str())
+            }
+            BppServiceError::Internal(msg) => {
+                f.write_str(format!("Internal error: {msg}").as_str())
+            }
+        }
+    }
+}
+
+impl Error for BppServiceError {}
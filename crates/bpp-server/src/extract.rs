@@ -0,0 +1,345 @@
+use crate::blobs::is_digest;
+use crate::dao::{write_atomic, NoteDao, StoredAttachment};
+use crate::error_def::BppStoreError;
+use error_stack::{report, IntoReport, Result, ResultExt};
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Notify;
+use tracing::{debug, error, info, warn};
+
+pub static TEXTS_DIR: &str = "texts";
+
+/// Tags whose content is not text of the document
+static HTML_SKIPPED_TAGS: [&str; 3] = ["script", "style", "template"];
+
+/// Text extracted from attachments under `<data_dir>/texts`, one file per
+/// blob digest. Data without text, or whose text could not be extracted,
+/// gets an empty file so it is not extracted again
+#[derive(Debug)]
+pub struct TextStore {
+    dir: PathBuf,
+}
+
+impl TextStore {
+    pub fn new(data_dir: &Path) -> Self {
+        TextStore {
+            dir: data_dir.join(TEXTS_DIR),
+        }
+    }
+
+    /// The text of the blob with `digest`, None when not extracted yet
+    pub fn get(&self, digest: &str) -> Option<String> {
+        fs::read_to_string(self.path(digest).ok()?).ok()
+    }
+
+    pub fn contains(&self, digest: &str) -> bool {
+        self.path(digest).is_ok_and(|path| path.exists())
+    }
+
+    pub fn put(&self, digest: &str, text: &str) -> Result<(), BppStoreError> {
+        fs::create_dir_all(&self.dir)
+            .into_report()
+            .change_context_lazy(|| {
+                BppStoreError::FailedToWriteStore(format!(
+                    "Could not create {}",
+                    self.dir.display()
+                ))
+            })?;
+        write_atomic(&self.path(digest)?, text.as_bytes())
+    }
+
+    /// Delete the text of the blob with `digest`, along with the blob
+    pub fn remove(&self, digest: &str) -> Result<(), BppStoreError> {
+        let path = self.path(digest)?;
+        match fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(report!(err)
+                .change_context(BppStoreError::FailedToWriteStore(format!(
+                    "Could not remove {}",
+                    path.display()
+                )))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Where the text of the blob with `digest` is stored. Fails unless
+    /// `digest` is a blob digest, see `BlobStore::path`
+    pub fn path(&self, digest: &str) -> Result<PathBuf, BppStoreError> {
+        if !is_digest(digest) {
+            return Err(report!(BppStoreError::FailedToOpenStore(format!(
+                "{digest:?} is not a blob digest"
+            ))));
+        }
+        Ok(self.dir.join(format!("{digest}.txt")))
+    }
+}
+
+/// Attachments text is extracted from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextFormat {
+    /// Plain text, markdown, logs, csv...
+    Plain,
+    Html,
+    Pdf,
+}
+
+impl TextFormat {
+    /// From the media type of `attachment`, or the extension of its name
+    /// when the media type is unknown. None when there is no text to get
+    pub fn of(attachment: &StoredAttachment) -> Option<Self> {
+        let media_type = attachment
+            .media_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        match media_type.as_str() {
+            "text/html" | "application/xhtml+xml" => return Some(TextFormat::Html),
+            "application/pdf" => return Some(TextFormat::Pdf),
+            "" | "application/octet-stream" => {}
+            other if other.starts_with("text/") => return Some(TextFormat::Plain),
+            _ => return None,
+        }
+
+        let extension = Path::new(&attachment.name)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("txt" | "text" | "log" | "md" | "markdown" | "csv") => Some(TextFormat::Plain),
+            Some("html" | "htm" | "xhtml") => Some(TextFormat::Html),
+            Some("pdf") => Some(TextFormat::Pdf),
+            _ => None,
+        }
+    }
+}
+
+/// The text of `data`, cut to at most `max_bytes`
+pub fn extract(
+    format: TextFormat,
+    data: &[u8],
+    max_bytes: usize,
+) -> std::result::Result<String, String> {
+    let text = match format {
+        TextFormat::Plain => String::from_utf8_lossy(data).to_string(),
+        TextFormat::Html => html_to_text(&String::from_utf8_lossy(data)),
+        TextFormat::Pdf => {
+            pdf_extract::extract_text_from_mem(data).map_err(|err| err.to_string())?
+        }
+    };
+    Ok(truncate(text, max_bytes))
+}
+
+/// Extract the text of the attachments waiting for it, then wait for
+/// `wake`, notified when files are attached. Attachments whose text fails
+/// to extract are logged and indexed without text
+pub async fn run(dao: Arc<NoteDao>, wake: Arc<Notify>, max_text_bytes: usize) {
+    loop {
+        for attachment in dao.pending_texts() {
+            let digest = attachment.digest.clone();
+            let dao = dao.clone();
+            let extracted = tokio::task::spawn_blocking(move || {
+                let data = dao.read_blob(&attachment.digest)?;
+                let format = TextFormat::of(&attachment).unwrap_or(TextFormat::Plain);
+                // Parsers of complex formats may panic on malformed files
+                let text = panic::catch_unwind(AssertUnwindSafe(|| {
+                    extract(format, &data, max_text_bytes)
+                }))
+                .unwrap_or_else(|_| Err("the parser panicked".to_string()))
+                .unwrap_or_else(|reason| {
+                    warn!(
+                        digest = %attachment.digest,
+                        "Could not extract the text of {}: {reason}", attachment.name
+                    );
+                    String::new()
+                });
+                let notes = dao.store_text(&attachment.digest, &text)?;
+                Ok::<_, error_stack::Report<BppStoreError>>((text.len(), notes))
+            })
+            .await;
+
+            match extracted {
+                Ok(Ok((bytes, notes))) => {
+                    info!(%digest, bytes, notes, "Attachment text indexed");
+                }
+                Ok(Err(err)) => error!("Text extraction failed\n{:?}", err),
+                Err(err) => error!("Text extraction failed: {err}"),
+            }
+        }
+        debug!("Waiting for attachments to extract");
+        wake.notified().await;
+    }
+}
+
+/// Text content of an html document, without tags, scripts and styles
+fn html_to_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut rest = html;
+    let mut skipped: Option<String> = None;
+    while let Some(start) = rest.find('<') {
+        if skipped.is_none() {
+            text.push_str(&decode_entities(&rest[..start]));
+        }
+        if rest[start..].starts_with("<!--") {
+            rest = rest[start..]
+                .find("-->")
+                .map_or("", |end| &rest[start + end + 3..]);
+            continue;
+        }
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end,
+            // Not a tag, eg. `a < b`, the rest is text
+            None => {
+                rest = &rest[start..];
+                break;
+            }
+        };
+        let tag = rest[start + 1..end].trim();
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        match &skipped {
+            Some(skipped_name) if closing && *skipped_name == name => skipped = None,
+            None if !closing && HTML_SKIPPED_TAGS.contains(&name.as_str()) => skipped = Some(name),
+            _ => {}
+        }
+        // Tags separate words, eg. <td>a</td><td>b</td>
+        text.push(' ');
+        rest = &rest[end + 1..];
+    }
+    if skipped.is_none() {
+        text.push_str(&decode_entities(rest));
+    }
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        let entity = rest[start + 1..]
+            .find(';')
+            .filter(|end| *end <= 10)
+            .map(|end| &rest[start + 1..start + 1 + end]);
+        let character = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                .and_then(char::from_u32),
+        });
+        match (entity, character) {
+            (Some(entity), Some(character)) => {
+                decoded.push(character);
+                rest = &rest[start + entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// `text` cut to at most `max_bytes`, on a character boundary
+fn truncate(mut text: String, max_bytes: usize) -> String {
+    if text.len() > max_bytes {
+        let mut end = max_bytes;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attachment(name: &str, media_type: &str) -> StoredAttachment {
+        StoredAttachment {
+            name: name.to_string(),
+            digest: "0".repeat(64),
+            size: 0,
+            media_type: media_type.to_string(),
+            created_ms: 0,
+        }
+    }
+
+    #[test]
+    fn formats_come_from_the_media_type_then_the_extension() {
+        for (name, media_type, format) in [
+            ("page", "text/html; charset=utf-8", Some(TextFormat::Html)),
+            ("scan", "application/pdf", Some(TextFormat::Pdf)),
+            ("notes.pdf", "text/plain", Some(TextFormat::Plain)),
+            ("REPORT.PDF", "", Some(TextFormat::Pdf)),
+            (
+                "index.htm",
+                "application/octet-stream",
+                Some(TextFormat::Html),
+            ),
+            ("todo.md", "", Some(TextFormat::Plain)),
+            ("photo.jpg", "", None),
+            ("notes.txt", "image/png", None),
+            ("README", "", None),
+        ] {
+            assert_eq!(
+                TextFormat::of(&attachment(name, media_type)),
+                format,
+                "{name} {media_type}"
+            );
+        }
+    }
+
+    #[test]
+    fn html_is_reduced_to_its_text() {
+        let html = "<html><head><title>Notes</title><style>p { color: red }</style></head>\
+                    <body><!-- draft --><p>Fish &amp; chips</p><table><tr><td>a</td><td>b</td>\
+                    </tr></table><SCRIPT>alert('<p>')</SCRIPT>end</body></html>";
+        assert_eq!(html_to_text(html), "Notes Fish & chips a b end");
+        assert_eq!(html_to_text("<p>a < b &amp; c</p"), "a < b & c</p");
+        assert_eq!(html_to_text("before <!-- unclosed"), "before");
+    }
+
+    #[test]
+    fn entities_are_decoded() {
+        assert_eq!(
+            decode_entities("&lt;a&gt; &quot;b&apos; c&nbsp;d &#233;&#x2014;&#X41;"),
+            "<a> \"b' c d é—A"
+        );
+    }
+
+    #[test]
+    fn unknown_entities_are_kept() {
+        assert_eq!(
+            decode_entities("AT&T &unknown; &#xZZ; &#1114112; & trailing&"),
+            "AT&T &unknown; &#xZZ; &#1114112; & trailing&"
+        );
+    }
+
+    #[test]
+    fn text_is_truncated_on_a_character_boundary() {
+        assert_eq!(truncate("short".to_string(), 10), "short");
+        assert_eq!(truncate("exactly".to_string(), 7), "exactly");
+        // "é" takes 2 bytes
+        assert_eq!(truncate("café".to_string(), 4), "caf");
+        assert_eq!(truncate("café".to_string(), 5), "café");
+        assert_eq!(truncate("é".to_string(), 0), "");
+    }
+}
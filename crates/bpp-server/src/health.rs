@@ -0,0 +1,53 @@
+use crate::dao::NoteDao;
+use crate::service::NoteService;
+use bpp_proto::bpp::api_server::ApiServer;
+use std::sync::Arc;
+use std::time::Duration;
+use tonic::transport::NamedService;
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+use tracing::{error, info};
+
+/// How often the store is probed
+static CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Keep the `grpc.health.v1` status of the `bpp.Api` service, and of the
+/// server as a whole (empty service name), in line with the availability of
+/// the store. The status is only updated and logged when a check gives a
+/// different result than the previous one
+pub async fn watch_store(dao: Arc<NoteDao>, mut reporter: HealthReporter) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    let mut serving = None;
+
+    loop {
+        interval.tick().await;
+        let check = dao.check();
+        if serving == Some(check.is_ok()) {
+            continue;
+        }
+        serving = Some(check.is_ok());
+
+        let status = match check {
+            Ok(()) => {
+                info!("Store available, serving");
+                ServingStatus::Serving
+            }
+            Err(err) => {
+                error!("Store unavailable, not serving\n{:?}", err);
+                ServingStatus::NotServing
+            }
+        };
+        for service in ["", ApiServer::<NoteService>::NAME] {
+            reporter.set_service_status(service, status).await;
+        }
+    }
+}
+
+/// Report every service as not serving, used once the server shuts down
+pub async fn set_not_serving(mut reporter: HealthReporter) {
+    for service in ["", ApiServer::<NoteService>::NAME] {
+        reporter
+            .set_service_status(service, ServingStatus::NotServing)
+            .await;
+    }
+}
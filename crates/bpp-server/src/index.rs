@@ -0,0 +1,158 @@
+use crate::dao::StoredNote;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Inverted index from lowercase terms to note ids, kept separately for
+/// titles and contents so searches can be restricted to titles. Text
+/// extracted from attachments is a third field, mapping terms to the note
+/// id and the name of the attachment, see `TextStore`.
+///
+/// Content of encrypted notes is opaque to the server and never indexed.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct SearchIndex {
+    title: BTreeMap<String, BTreeSet<String>>,
+    content: BTreeMap<String, BTreeSet<String>>,
+    attachment: BTreeMap<String, BTreeSet<(String, String)>>,
+    /// Ids of every indexed note, used to detect a stale index
+    ids: BTreeSet<String>,
+}
+
+impl SearchIndex {
+    pub fn build<'a>(notes: impl IntoIterator<Item = &'a StoredNote>) -> Self {
+        let mut index = SearchIndex::default();
+        for note in notes {
+            index.insert(note);
+        }
+        index
+    }
+
+    pub fn insert(&mut self, note: &StoredNote) {
+        for term in tokenize(&note.title) {
+            self.title.entry(term).or_default().insert(note.id.clone());
+        }
+        if !note.encrypted {
+            for term in tokenize(&note.content) {
+                self.content
+                    .entry(term)
+                    .or_default()
+                    .insert(note.id.clone());
+            }
+        }
+        self.ids.insert(note.id.clone());
+    }
+
+    /// Attachment terms of the note are removed with `remove_attachment`
+    pub fn remove(&mut self, note: &StoredNote) {
+        remove_terms(&mut self.title, &note.title, &note.id);
+        remove_terms(&mut self.content, &note.content, &note.id);
+        self.ids.remove(&note.id);
+    }
+
+    /// Index `text`, extracted from the attachment `name` of the note `id`
+    pub fn insert_attachment(&mut self, id: &str, name: &str, text: &str) {
+        for term in tokenize(text) {
+            self.attachment
+                .entry(term)
+                .or_default()
+                .insert((id.to_string(), name.to_string()));
+        }
+    }
+
+    pub fn remove_attachment(&mut self, id: &str, name: &str, text: &str) {
+        remove_terms(
+            &mut self.attachment,
+            text,
+            &(id.to_string(), name.to_string()),
+        );
+    }
+
+    /// Whether the index covers exactly the given notes
+    pub fn matches<'a>(&self, notes: impl IntoIterator<Item = &'a StoredNote>) -> bool {
+        let ids: BTreeSet<&String> = notes.into_iter().map(|note| &note.id).collect();
+        ids.len() == self.ids.len() && self.ids.iter().all(|id| ids.contains(id))
+    }
+
+    /// Ids of the notes containing every term of `query`. Terms match as
+    /// prefixes of indexed words, on titles and, if `all` is set, contents.
+    /// If `attachments` is set, on the text of attachments too.
+    /// An empty query matches every note.
+    pub fn search(&self, query: &str, all: bool, attachments: bool) -> BTreeSet<String> {
+        let mut result: Option<BTreeSet<String>> = None;
+
+        for term in tokenize(query) {
+            let mut found = prefix_matches(&self.title, &term);
+            if all {
+                found.extend(prefix_matches(&self.content, &term));
+            }
+            if attachments {
+                found.extend(
+                    prefix_matches(&self.attachment, &term)
+                        .into_iter()
+                        .map(|(id, _)| id),
+                );
+            }
+            result = Some(match result {
+                Some(previous) => previous.intersection(&found).cloned().collect(),
+                None => found,
+            });
+        }
+
+        result.unwrap_or_else(|| self.ids.clone())
+    }
+
+    /// Names of the attachments of the notes `ids` containing a term of
+    /// `query`, by note id
+    pub fn matching_attachments(
+        &self,
+        query: &str,
+        ids: &BTreeSet<String>,
+    ) -> BTreeMap<String, BTreeSet<String>> {
+        let mut matches: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for term in tokenize(query) {
+            for (id, name) in prefix_matches(&self.attachment, &term) {
+                if ids.contains(&id) {
+                    matches.entry(id).or_default().insert(name);
+                }
+            }
+        }
+        matches
+    }
+
+    /// Number of distinct terms, all fields combined
+    pub fn term_count(&self) -> usize {
+        self.title.len() + self.content.len() + self.attachment.len()
+    }
+
+    pub fn note_count(&self) -> usize {
+        self.ids.len()
+    }
+}
+
+/// Split `text` into lowercase alphanumeric terms
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(|term| term.to_lowercase())
+}
+
+fn prefix_matches<T: Ord + Clone>(
+    terms: &BTreeMap<String, BTreeSet<T>>,
+    prefix: &str,
+) -> BTreeSet<T> {
+    terms
+        .range(prefix.to_string()..)
+        .take_while(|(term, _)| term.starts_with(prefix))
+        .flat_map(|(_, ids)| ids.iter().cloned())
+        .collect()
+}
+
+fn remove_terms<T: Ord>(terms: &mut BTreeMap<String, BTreeSet<T>>, text: &str, id: &T) {
+    for term in tokenize(text) {
+        if let Some(ids) = terms.get_mut(&term) {
+            ids.remove(id);
+            if ids.is_empty() {
+                terms.remove(&term);
+            }
+        }
+    }
+}
@@ -0,0 +1,504 @@
+use crate::dao::write_atomic;
+use crate::error_def::BppStoreError;
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use error_stack::{report, IntoReport, Result, ResultExt};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+pub static KEYS_FILE: &str = "keys.json";
+const KEYS_VERSION: u32 = 1;
+/// Prefix of sealed values, followed by the id of the key they are sealed
+/// with and the base64 of `nonce || ciphertext`
+const SEALED_PREFIX: &str = "sealed:";
+const NONCE_LEN: usize = 12;
+const SALT_LEN: usize = 16;
+/// Sealed into `KeysFile::check`, so a wrong passphrase is reported as
+/// such rather than as corrupted notes
+const CHECK: &[u8] = b"bpp";
+
+/// Argon2id parameters a key is derived with. Kept in the keys file, so
+/// they can be raised for new keys without breaking existing stores
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct KdfParams {
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams {
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+/// Content of `KEYS_FILE`. It holds what is needed to derive the current
+/// key from the passphrase, never the key itself
+#[derive(Debug, Serialize, Deserialize)]
+struct KeysFile {
+    version: u32,
+    id: String,
+    /// Base64 encoded
+    salt: String,
+    kdf: KdfParams,
+    /// `CHECK` sealed with the current key
+    check: String,
+    #[serde(default)]
+    retired: Vec<RetiredKey>,
+}
+
+/// A key replaced by `Keyring::rotate`, sealed with the current key. It
+/// still opens what was sealed before the rotation, eg. backups
+#[derive(Debug, Serialize, Deserialize)]
+struct RetiredKey {
+    id: String,
+    key: String,
+}
+
+/// Passphrase the keys of an encrypted store are derived from, read from
+/// `database.passphrase_file`. It is kept apart from the database
+/// password, so changing that password never re-keys the store
+#[derive(Clone)]
+pub struct Passphrase(String);
+
+impl Passphrase {
+    /// Read the passphrase from `path`. Trailing whitespace is ignored, so
+    /// the file can end with a newline
+    pub fn read(path: &Path) -> Result<Self, BppStoreError> {
+        let passphrase = fs::read_to_string(path)
+            .into_report()
+            .change_context_lazy(|| {
+                BppStoreError::Encryption(format!(
+                    "could not read the passphrase from {}",
+                    path.display()
+                ))
+            })?;
+        Ok(Passphrase(passphrase.trim_end().to_string()))
+    }
+
+    fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Passphrase {
+    fn from(passphrase: &str) -> Self {
+        Passphrase(passphrase.to_string())
+    }
+}
+
+impl fmt::Debug for Passphrase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+#[derive(Clone)]
+struct StoreKey {
+    id: String,
+    key: Key,
+    kdf: KdfParams,
+    salt: Vec<u8>,
+}
+
+/// Keys note titles and contents are encrypted with at rest, derived from
+/// a `Passphrase`. Values are sealed with the current key, and opened with
+/// whichever key they name
+#[derive(Clone)]
+pub struct Keyring {
+    passphrase: Passphrase,
+    current: StoreKey,
+    retired: Vec<StoreKey>,
+}
+
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keyring")
+            .field("current", &self.current.id)
+            .field(
+                "retired",
+                &self.retired.iter().map(|key| &key.id).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl Keyring {
+    /// Derive the keys of `data_dir` from `passphrase`. None when the store
+    /// is not encrypted
+    pub fn load(
+        data_dir: &Path,
+        passphrase: Option<&Passphrase>,
+    ) -> Result<Option<Self>, BppStoreError> {
+        let path = data_dir.join(KEYS_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let passphrase = passphrase.ok_or_else(|| {
+            report!(BppStoreError::Encryption(format!(
+                "{} is encrypted, set database.passphrase_file",
+                data_dir.display()
+            )))
+        })?;
+        let bytes = fs::read(&path).into_report().change_context_lazy(|| {
+            BppStoreError::FailedToOpenStore(format!("Could not read {}", path.display()))
+        })?;
+        Self::unlock(&bytes, passphrase).map(Some)
+    }
+
+    /// Create the keys of `data_dir` from `passphrase`. Notes are encrypted
+    /// from the next write on
+    pub fn create(data_dir: &Path, passphrase: &Passphrase) -> Result<Self, BppStoreError> {
+        let keyring = Keyring {
+            passphrase: passphrase.clone(),
+            current: StoreKey::generate(passphrase)?,
+            retired: vec![],
+        };
+        keyring.save(data_dir)?;
+        Ok(keyring)
+    }
+
+    /// Replace the current key with a new one derived from `passphrase`,
+    /// retiring the previous keys. Once written to `data_dir`, the store
+    /// can only be opened with the new passphrase
+    pub fn rotate(&self, data_dir: &Path, passphrase: &Passphrase) -> Result<Self, BppStoreError> {
+        let keyring = Keyring {
+            passphrase: passphrase.clone(),
+            current: StoreKey::generate(passphrase)?,
+            retired: [&self.current]
+                .into_iter()
+                .chain(&self.retired)
+                .cloned()
+                .collect(),
+        };
+        keyring.save(data_dir)?;
+        Ok(keyring)
+    }
+
+    /// Add the keys of the keys file `bytes`, eg. the one of a backup taken
+    /// of another store, as retired keys. Unless its current key is known
+    /// already, it must derive from the same passphrase. Written to
+    /// `data_dir` when keys were added
+    pub fn merge(&self, data_dir: &Path, bytes: &[u8]) -> Result<Self, BppStoreError> {
+        if self.key(&parse_keys_file(bytes)?.id).is_some() {
+            return Ok(self.clone());
+        }
+        let other =
+            Self::unlock(bytes, &self.passphrase).change_context(BppStoreError::Encryption(
+                "the keys of the backup do not derive from the passphrase of the store".to_string(),
+            ))?;
+        let mut keyring = self.clone();
+        for key in [other.current].into_iter().chain(other.retired) {
+            if keyring.key(&key.id).is_none() {
+                keyring.retired.push(key);
+            }
+        }
+        if keyring.retired.len() > self.retired.len() {
+            keyring.save(data_dir)?;
+        }
+        Ok(keyring)
+    }
+
+    /// Id of the key values are sealed with
+    pub fn id(&self) -> &str {
+        &self.current.id
+    }
+
+    /// Encrypt `plaintext` with the current key. `aad` is authenticated
+    /// with it, so a value cannot be moved elsewhere, eg. to another note
+    pub fn seal(&self, plaintext: &[u8], aad: &str) -> Result<String, BppStoreError> {
+        self.current.seal(plaintext, aad)
+    }
+
+    /// Decrypt a value produced by `Keyring::seal` with the same `aad`.
+    /// Values that are not sealed are returned as they are, they were
+    /// stored before the store was encrypted
+    pub fn open(&self, value: &str, aad: &str) -> Result<Vec<u8>, BppStoreError> {
+        let Some((id, _)) = parse(value) else {
+            return Ok(value.as_bytes().to_vec());
+        };
+        let key = self.key(id).ok_or_else(|| {
+            report!(BppStoreError::Encryption(format!(
+                "{aad} is encrypted with key {id:?}, missing from {KEYS_FILE}"
+            )))
+        })?;
+        key.open(value, aad)
+    }
+
+    /// Like `Keyring::open`, for values that were text
+    pub fn open_str(&self, value: &str, aad: &str) -> Result<String, BppStoreError> {
+        String::from_utf8(self.open(value, aad)?)
+            .into_report()
+            .change_context_lazy(|| {
+                BppStoreError::Encryption(format!("{aad} is not valid UTF-8 once decrypted"))
+            })
+    }
+
+    fn key(&self, id: &str) -> Option<&StoreKey> {
+        [&self.current]
+            .into_iter()
+            .chain(&self.retired)
+            .find(|key| key.id == id)
+    }
+
+    fn unlock(bytes: &[u8], passphrase: &Passphrase) -> Result<Self, BppStoreError> {
+        let file = parse_keys_file(bytes)?;
+        let salt = base64::decode(&file.salt)
+            .into_report()
+            .change_context_lazy(|| {
+                BppStoreError::Encryption(format!("{KEYS_FILE} holds an invalid salt"))
+            })?;
+        let current = StoreKey::derive(file.id, passphrase, salt, file.kdf)?;
+        if current.open(&file.check, KEYS_FILE).ok().as_deref() != Some(CHECK) {
+            return Err(report!(BppStoreError::Encryption(
+                "wrong passphrase, the keys do not match database.passphrase_file".to_string()
+            )));
+        }
+
+        let mut retired = vec![];
+        for key in file.retired {
+            let bytes = current.open(&key.key, &key.id)?;
+            if bytes.len() != 32 {
+                return Err(report!(BppStoreError::Encryption(format!(
+                    "retired key {:?} is not a 32 bytes key",
+                    key.id
+                ))));
+            }
+            retired.push(StoreKey {
+                id: key.id,
+                key: *Key::from_slice(&bytes),
+                kdf: KdfParams::default(),
+                salt: vec![],
+            });
+        }
+        Ok(Keyring {
+            passphrase: passphrase.clone(),
+            current,
+            retired,
+        })
+    }
+
+    fn save(&self, data_dir: &Path) -> Result<(), BppStoreError> {
+        let mut retired = vec![];
+        for key in &self.retired {
+            retired.push(RetiredKey {
+                id: key.id.clone(),
+                key: self.current.seal(&key.key, &key.id)?,
+            });
+        }
+        let file = KeysFile {
+            version: KEYS_VERSION,
+            id: self.current.id.clone(),
+            salt: base64::encode(&self.current.salt),
+            kdf: self.current.kdf,
+            check: self.current.seal(CHECK, KEYS_FILE)?,
+            retired,
+        };
+        let bytes = serde_json::to_vec_pretty(&file)
+            .into_report()
+            .change_context(BppStoreError::FailedToWriteStore(format!(
+                "Could not serialize {KEYS_FILE}"
+            )))?;
+        write_atomic(&data_dir.join(KEYS_FILE), &bytes)
+    }
+}
+
+impl StoreKey {
+    /// A key derived from `passphrase` and a new random salt
+    fn generate(passphrase: &Passphrase) -> Result<Self, BppStoreError> {
+        if passphrase.expose().is_empty() {
+            return Err(report!(BppStoreError::Encryption(
+                "the passphrase is empty".to_string()
+            )));
+        }
+        let mut salt = vec![0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let id = format!("{:08x}", OsRng.next_u32());
+        Self::derive(id, passphrase, salt, KdfParams::default())
+    }
+
+    fn derive(
+        id: String,
+        passphrase: &Passphrase,
+        salt: Vec<u8>,
+        kdf: KdfParams,
+    ) -> Result<Self, BppStoreError> {
+        let mut key = Key::default();
+        Params::new(kdf.m_cost, kdf.t_cost, kdf.p_cost, Some(key.len()))
+            .and_then(|params| {
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params).hash_password_into(
+                    passphrase.expose().as_bytes(),
+                    &salt,
+                    &mut key,
+                )
+            })
+            .map_err(|err| {
+                report!(BppStoreError::Encryption(format!(
+                    "could not derive key {id:?}: {err}"
+                )))
+            })?;
+        Ok(StoreKey { id, key, kdf, salt })
+    }
+
+    fn seal(&self, plaintext: &[u8], aad: &str) -> Result<String, BppStoreError> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: plaintext,
+            aad: aad.as_bytes(),
+        };
+        let ciphertext = ChaCha20Poly1305::new(&self.key)
+            .encrypt(&nonce, payload)
+            .map_err(|_| {
+                report!(BppStoreError::Encryption(format!(
+                    "could not encrypt {aad}"
+                )))
+            })?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        Ok(format!(
+            "{SEALED_PREFIX}{}:{}",
+            self.id,
+            base64::encode(sealed)
+        ))
+    }
+
+    fn open(&self, value: &str, aad: &str) -> Result<Vec<u8>, BppStoreError> {
+        let corrupted = || BppStoreError::Encryption(format!("{aad} is corrupted"));
+        let bytes = parse(value)
+            .filter(|(id, _)| *id == self.id)
+            .and_then(|(_, encoded)| base64::decode(encoded).ok())
+            .filter(|bytes| bytes.len() >= NONCE_LEN)
+            .ok_or_else(|| report!(corrupted()))?;
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        ChaCha20Poly1305::new(&self.key)
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| report!(corrupted()))
+    }
+}
+
+fn parse_keys_file(bytes: &[u8]) -> Result<KeysFile, BppStoreError> {
+    let file: KeysFile = serde_json::from_slice(bytes)
+        .into_report()
+        .change_context_lazy(|| BppStoreError::Encryption(format!("{KEYS_FILE} is corrupted")))?;
+    if file.version > KEYS_VERSION {
+        return Err(report!(BppStoreError::Encryption(format!(
+            "{KEYS_FILE} is at version {}, newer than this server supports",
+            file.version
+        ))));
+    }
+    Ok(file)
+}
+
+/// Whether `value` was produced by `Keyring::seal`
+pub fn is_sealed(value: &str) -> bool {
+    parse(value).is_some()
+}
+
+/// The key id and encoded data of a sealed value
+fn parse(value: &str) -> Option<(&str, &str)> {
+    let (id, encoded) = value.strip_prefix(SEALED_PREFIX)?.split_once(':')?;
+    (id.len() == 8 && id.bytes().all(|byte| byte.is_ascii_hexdigit())).then_some((id, encoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data_dir() -> tempfile::TempDir {
+        tempfile::tempdir().unwrap()
+    }
+
+    #[test]
+    fn sealed_values_open_with_their_aad() {
+        let dir = data_dir();
+        let keys = Keyring::create(dir.path(), &Passphrase::from("passphrase")).unwrap();
+        let sealed = keys.seal(b"launch codes", "note 1 content").unwrap();
+        assert!(is_sealed(&sealed));
+        assert!(!sealed.contains("launch"));
+        assert_eq!(
+            keys.open_str(&sealed, "note 1 content").unwrap(),
+            "launch codes"
+        );
+        assert!(keys.open(&sealed, "note 2 content").is_err());
+    }
+
+    #[test]
+    fn plaintext_is_returned_as_is() {
+        let dir = data_dir();
+        let keys = Keyring::create(dir.path(), &Passphrase::from("passphrase")).unwrap();
+        for value in ["launch codes", "sealed: not really", ""] {
+            assert!(!is_sealed(value));
+            assert_eq!(keys.open_str(value, "note 1 title").unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn keys_load_with_the_passphrase_only() {
+        let dir = data_dir();
+        assert!(Keyring::load(dir.path(), None).unwrap().is_none());
+        let keys = Keyring::create(dir.path(), &Passphrase::from("passphrase")).unwrap();
+        let sealed = keys.seal(b"launch codes", "aad").unwrap();
+
+        let loaded = Keyring::load(dir.path(), Some(&Passphrase::from("passphrase")))
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.id(), keys.id());
+        assert_eq!(loaded.open(&sealed, "aad").unwrap(), b"launch codes");
+        assert!(Keyring::load(dir.path(), Some(&Passphrase::from("wrong"))).is_err());
+        assert!(Keyring::load(dir.path(), None).is_err());
+    }
+
+    #[test]
+    fn rotated_keys_open_what_retired_keys_sealed() {
+        let dir = data_dir();
+        let keys = Keyring::create(dir.path(), &Passphrase::from("old")).unwrap();
+        let sealed = keys.seal(b"launch codes", "aad").unwrap();
+        let rotated = keys.rotate(dir.path(), &Passphrase::from("new")).unwrap();
+        assert_ne!(rotated.id(), keys.id());
+        assert!(rotated.seal(b"x", "aad").unwrap().contains(rotated.id()));
+
+        assert!(Keyring::load(dir.path(), Some(&Passphrase::from("old"))).is_err());
+        let loaded = Keyring::load(dir.path(), Some(&Passphrase::from("new")))
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.open(&sealed, "aad").unwrap(), b"launch codes");
+    }
+
+    #[test]
+    fn merged_keys_open_what_the_other_store_sealed() {
+        let (dir, other_dir) = (data_dir(), data_dir());
+        let passphrase = Passphrase::from("passphrase");
+        let keys = Keyring::create(dir.path(), &passphrase).unwrap();
+        let other = Keyring::create(other_dir.path(), &passphrase).unwrap();
+        let sealed = other.seal(b"launch codes", "aad").unwrap();
+        assert!(keys.open(&sealed, "aad").is_err());
+
+        let other_file = fs::read(other_dir.path().join(KEYS_FILE)).unwrap();
+        let merged = keys.merge(dir.path(), &other_file).unwrap();
+        assert_eq!(merged.id(), keys.id());
+        let loaded = Keyring::load(dir.path(), Some(&passphrase))
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.open(&sealed, "aad").unwrap(), b"launch codes");
+
+        let stranger_dir = data_dir();
+        Keyring::create(stranger_dir.path(), &Passphrase::from("another")).unwrap();
+        let stranger_file = fs::read(stranger_dir.path().join(KEYS_FILE)).unwrap();
+        assert!(keys.merge(dir.path(), &stranger_file).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use crate::dao::StoredNote;
+use std::collections::{BTreeSet, HashMap};
+use unicode_normalization::UnicodeNormalization;
+
+/// Links between notes, `[[target]]` or `[[target|label]]` in contents.
+/// A target is the id of a note or else the title of one, compared
+/// ignoring case. When several notes share a title, the oldest one wins.
+///
+/// Links are kept as written and resolved when queried, so a broken link
+/// is repaired by adding a note with its target as title. Content of
+/// encrypted notes is opaque to the server and never parsed.
+#[derive(Debug, Default)]
+pub struct LinkGraph {
+    /// Link targets of every note, in the order they appear in
+    links: HashMap<String, Vec<String>>,
+    /// Ids of the notes linking to a target, by `key` of the target
+    linked_from: HashMap<String, BTreeSet<String>>,
+    /// Ids of the notes with a title, by `key` of the title, oldest first
+    titles: HashMap<String, Vec<String>>,
+}
+
+impl LinkGraph {
+    pub fn build<'a>(notes: impl IntoIterator<Item = &'a StoredNote>) -> Self {
+        let mut graph = LinkGraph::default();
+        for note in notes {
+            graph.insert(note);
+        }
+        graph
+    }
+
+    pub fn insert(&mut self, note: &StoredNote) {
+        let targets = if note.encrypted {
+            vec![]
+        } else {
+            parse_links(&note.content)
+        };
+        for target in &targets {
+            self.linked_from
+                .entry(key(target))
+                .or_default()
+                .insert(note.id.clone());
+        }
+        self.titles
+            .entry(key(&note.title))
+            .or_default()
+            .push(note.id.clone());
+        self.links.insert(note.id.clone(), targets);
+    }
+
+    pub fn remove(&mut self, note: &StoredNote) {
+        for target in self.links.remove(&note.id).unwrap_or_default() {
+            let target = key(&target);
+            if let Some(ids) = self.linked_from.get_mut(&target) {
+                ids.remove(&note.id);
+                if ids.is_empty() {
+                    self.linked_from.remove(&target);
+                }
+            }
+        }
+        let title = key(&note.title);
+        if let Some(ids) = self.titles.get_mut(&title) {
+            ids.retain(|id| id != &note.id);
+            if ids.is_empty() {
+                self.titles.remove(&title);
+            }
+        }
+    }
+
+    /// Id of the note `target` refers to, None for a broken link
+    pub fn resolve(&self, target: &str) -> Option<&str> {
+        let target = target.trim();
+        if let Some((id, _)) = self.links.get_key_value(target) {
+            return Some(id);
+        }
+        self.titles
+            .get(&key(target))
+            .and_then(|ids| ids.first())
+            .map(String::as_str)
+    }
+
+    /// Targets of the links of the note `id`, each with the id it resolves to
+    pub fn links(&self, id: &str) -> Vec<(String, Option<String>)> {
+        self.links
+            .get(id)
+            .map(|targets| {
+                targets
+                    .iter()
+                    .map(|target| (target.clone(), self.resolve(target).map(str::to_string)))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Ids of the other notes with a link resolving to `note`
+    pub fn backlinks(&self, note: &StoredNote) -> BTreeSet<String> {
+        [key(&note.id), key(&note.title)]
+            .iter()
+            .filter_map(|target| self.linked_from.get(target))
+            .flatten()
+            .filter(|source| *source != &note.id)
+            .filter(|source| {
+                self.links[source.as_str()]
+                    .iter()
+                    .any(|target| self.resolve(target) == Some(note.id.as_str()))
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// Targets of the `[[target]]` and `[[target|label]]` links of `text`,
+/// outside of code blocks and inline code. Empty targets are ignored
+pub fn parse_links(text: &str) -> Vec<String> {
+    let mut targets = vec![];
+    let mut in_code_block = false;
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code_block = !in_code_block;
+            continue;
+        }
+        if in_code_block {
+            continue;
+        }
+        // Odd segments between backticks are inline code
+        for (segment, text) in line.split('`').enumerate() {
+            if segment % 2 == 1 {
+                continue;
+            }
+            let mut rest = text;
+            while let Some(start) = rest.find("[[") {
+                rest = &rest[start + 2..];
+                let end = match rest.find("]]") {
+                    Some(end) => end,
+                    None => break,
+                };
+                let link = &rest[..end];
+                rest = &rest[end + 2..];
+                let target = link.split('|').next().unwrap_or_default().trim();
+                if !target.is_empty() && !target.contains('[') {
+                    targets.push(target.to_string());
+                }
+            }
+        }
+    }
+    targets
+}
+
+/// Targets and titles are compared after normalization, ignoring case
+fn key(text: &str) -> String {
+    text.nfc().collect::<String>().trim().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_and_labelled_links_are_parsed() {
+        assert_eq!(
+            parse_links("See [[Rust]] and [[ Tokio | the runtime ]], [[]] or [[a|]]."),
+            ["Rust", "Tokio", "a"]
+        );
+    }
+
+    #[test]
+    fn unclosed_and_nested_brackets_are_not_links() {
+        assert!(parse_links("[[open").is_empty());
+        assert!(parse_links("[[a [[b]]").is_empty());
+    }
+
+    #[test]
+    fn code_is_not_parsed() {
+        let text = "[[before]] `[[inline]]` [[after]]\n\
+                    ```\n[[block]]\n```\n\
+                    [[end]]";
+        assert_eq!(parse_links(text), ["before", "after", "end"]);
+    }
+}
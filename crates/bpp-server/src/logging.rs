@@ -0,0 +1,76 @@
+use crate::config::{LogFormat, LoggingConfig};
+use bpp_proto::REQUEST_ID_KEY;
+use std::str::FromStr;
+use tonic::codegen::http::Request;
+use tracing::{info_span, warn, Span};
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, Registry};
+use uuid::Uuid;
+
+/// Allows changing the log level of the running server
+pub struct LogHandle {
+    level: reload::Handle<Targets, Registry>,
+}
+
+impl LogHandle {
+    pub fn set_level(&self, config: &LoggingConfig) {
+        if let Err(err) = self.level.reload(targets(config)) {
+            warn!("Failed to change log level: {err}");
+        }
+    }
+}
+
+/// Install the global subscriber using the level and format from `config`.
+/// Logs are written to `writer`
+pub fn init<W>(config: &LoggingConfig, writer: W) -> LogHandle
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let (level, handle) = reload::Layer::new(targets(config));
+    let (json, text) = match config.format {
+        LogFormat::Json => (
+            Some(
+                fmt::layer()
+                    .json()
+                    .with_current_span(true)
+                    .with_writer(writer),
+            ),
+            None,
+        ),
+        LogFormat::Text => (None, Some(fmt::layer().with_writer(writer))),
+    };
+
+    tracing_subscriber::registry()
+        .with(level)
+        .with(json)
+        .with(text)
+        .init();
+
+    LogHandle { level: handle }
+}
+
+/// Span wrapping every rpc. Uses the request id sent by the client, or a
+/// new one if the client did not provide it
+pub fn rpc_span(request: &Request<()>) -> Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_KEY)
+        .and_then(|id| id.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    info_span!("rpc", method = %request.uri().path(), request_id = %request_id)
+}
+
+/// The configured level applies to the server itself. Dependencies such as
+/// hyper and h2 are very chatty, they only log warnings and errors
+fn targets(config: &LoggingConfig) -> Targets {
+    // The level is validated when the config is loaded
+    let level = LevelFilter::from_str(&config.level).unwrap_or(LevelFilter::INFO);
+    Targets::new()
+        .with_target(env!("CARGO_CRATE_NAME"), level)
+        .with_default(level.min(LevelFilter::WARN))
+}
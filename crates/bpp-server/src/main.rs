@@ -1,48 +1,679 @@
 extern crate core;
 
-use crate::config::{Config, FromConfig};
-use crate::error_def::BppConfigError;
+use crate::audit::{Actor, AuditKind, AuditLog, AuditRecord};
+use crate::config::{Config, ConfigOverrides, FromConfig};
+use crate::dao::NoteDao;
+use crate::error_def::BppServerError;
+use crate::keys::Passphrase;
+use crate::logging::LogHandle;
+use crate::metrics::MetricsLayer;
+use crate::ratelimit::RateLimitLayer;
+use crate::reload::LiveConfig;
+use crate::service::NoteService;
+use crate::shutdown::{InFlight, ShutdownSignals};
+use bpp_proto::bpp::api_server::ApiServer;
+use error_stack::{report, IntoReport, Result, ResultExt};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use structopt::StructOpt;
+use tokio::sync::oneshot;
+use tonic::transport::Server;
+use tracing::{error, info, warn};
 
+mod audit;
+mod backup;
+mod blobs;
 mod config;
+mod dao;
 pub mod error_def;
+mod extract;
+mod health;
+mod index;
+mod keys;
+mod links;
+mod logging;
+mod metrics;
+mod migrations;
+mod ratelimit;
+mod reload;
+mod service;
+mod shutdown;
+mod templates;
+mod validation;
 
-struct NoteService {
-    pub service_name: String,
-    node: NoteDao,
+/// BreadPaper notes server
+///
+/// Options are resolved in the following order, the first one set wins:
+///  command line flags > BPP_* environment variables > config file > defaults
+///
+/// bpp-server [serve]
+/// bpp-server --listen [::1]:9000 --data-dir /tmp/notes
+/// bpp-server export --output notes.json
+/// bpp-server import notes.json
+/// bpp-server backup
+/// bpp-server restore --at "2023-01-31 10:00:00"
+/// bpp-server rotate-key --new-passphrase-file new-passphrase
+/// bpp-server --check-config
+///
+/// While serving, the config is reloaded on SIGHUP and when the config file
+/// changes. The log level, rate limit and backup schedule are applied,
+/// other changes are reported and require a restart.
+///
+/// On SIGTERM or SIGINT the server stops accepting connections, waits up to
+/// service.shutdown_deadline_secs for the rpcs in flight and flushes the
+/// store before exiting. A second signal skips the wait.
+///
+/// Backups are taken every backup.interval_secs while serving, keeping the
+/// backup.keep most recent ones.
+///
+/// `bpp-server backup` can run alongside the server. The other subcommands
+/// write to the store and refuse to run while the server, or another of
+/// them, has it open
+#[derive(StructOpt, Debug)]
+struct BppServerOpts {
+    #[structopt(
+        long,
+        short = "c",
+        parse(from_os_str),
+        long_help = "Config file. Takes precedence over BPP_SERVER_CONFIG"
+    )]
+    config: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        short = "l",
+        long_help = "Address to listen on, eg. [::1]:8085 (service.listen)"
+    )]
+    listen: Option<String>,
+
+    #[structopt(
+        long,
+        short = "d",
+        parse(from_os_str),
+        long_help = "Directory notes are stored in (database.data_dir)"
+    )]
+    data_dir: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        long_help = "One of TRACE, DEBUG, INFO, WARN or ERROR (logging.level)"
+    )]
+    log_level: Option<String>,
+
+    #[structopt(
+        long,
+        long_help = "Validate the configuration, print the effective config and exit"
+    )]
+    check_config: bool,
+
+    #[structopt(subcommand)]
+    subcommands: Option<SubCommands>,
 }
 
-impl FromConfig for NoteService {
-    fn from_config(config: &Config) -> error_stack::Result<Self, BppConfigError> {
-        Ok(NoteService {
-            service_name: config.service.name.clone(),
-            node: NoteDao::from_config(&config)?,
-        })
+#[derive(StructOpt, Debug)]
+enum SubCommands {
+    #[structopt(about = "Run the notes server (default)")]
+    Serve,
+    #[structopt(about = "Upgrade the store in the data directory to the current format")]
+    Migrate(MigrateOpts),
+    #[structopt(about = "Rebuild the search index")]
+    Reindex,
+    #[structopt(about = "Export every note as json")]
+    Export(ExportOpts),
+    #[structopt(about = "Import notes from a json export")]
+    Import(ImportOpts),
+    #[structopt(about = "Write a compressed and checksummed backup of the store")]
+    Backup(BackupOpts),
+    #[structopt(about = "Replace every note with the notes of a backup")]
+    Restore(RestoreOpts),
+    #[structopt(about = "Encrypt the store with a key derived from a new passphrase")]
+    RotateKey(RotateKeyOpts),
+}
+
+#[derive(StructOpt, Debug)]
+struct MigrateOpts {
+    #[structopt(
+        long,
+        long_help = "Check the migrations and print them without changing the store"
+    )]
+    dry_run: bool,
+}
+
+#[derive(StructOpt, Debug)]
+struct ExportOpts {
+    #[structopt(
+        long,
+        short = "o",
+        parse(from_os_str),
+        long_help = "File to write to. Defaults to stdout"
+    )]
+    output: Option<PathBuf>,
+}
+
+#[derive(StructOpt, Debug)]
+struct ImportOpts {
+    #[structopt(parse(from_os_str), long_help = "File produced by `bpp-server export`")]
+    input: PathBuf,
+}
+
+#[derive(StructOpt, Debug)]
+struct BackupOpts {
+    #[structopt(
+        long,
+        short = "o",
+        parse(from_os_str),
+        long_help = "File to write to. Defaults to a new file in backup.dir"
+    )]
+    output: Option<PathBuf>,
+}
+
+#[derive(StructOpt, Debug)]
+struct RestoreOpts {
+    #[structopt(
+        parse(from_os_str),
+        required_unless_one = &["at", "list"],
+        conflicts_with_all = &["at", "list"],
+        long_help = "Backup file to restore"
+    )]
+    input: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        conflicts_with = "list",
+        long_help = "Restore the latest backup of backup.dir taken at or before this \
+                     utc time, eg. \"2023-01-31 10:00:00\" or 2023-01-31"
+    )]
+    at: Option<String>,
+
+    #[structopt(long, long_help = "List the backups of backup.dir and exit")]
+    list: bool,
+
+    #[structopt(long, long_help = "Only check that the backup is valid")]
+    verify: bool,
+}
+
+#[derive(StructOpt, Debug)]
+struct RotateKeyOpts {
+    #[structopt(
+        long,
+        parse(from_os_str),
+        long_help = "File containing the new passphrase. Set database.passphrase_file to it \
+                     once the key is rotated"
+    )]
+    new_passphrase_file: PathBuf,
+}
+
+impl BppServerOpts {
+    fn overrides(&self) -> ConfigOverrides {
+        ConfigOverrides {
+            config_file: self.config.clone(),
+            listen: self.listen.clone(),
+            data_dir: self.data_dir.clone(),
+            log_level: self.log_level.clone(),
+        }
+    }
+
+    async fn run(&self, config: &Config) -> Result<(), BppServerError> {
+        // Before the store is opened, so migrations are logged. The other
+        // subcommands log to stderr, `export` may write the notes to stdout
+        let log_handle = match &self.subcommands {
+            None | Some(SubCommands::Serve) => logging::init(&config.logging, io::stdout),
+            Some(_) => logging::init(&config.logging, io::stderr),
+        };
+        match &self.subcommands {
+            None | Some(SubCommands::Serve) => serve(config, self.overrides(), log_handle).await,
+            Some(SubCommands::Migrate(migrate_opts)) => {
+                let report = NoteDao::migrate(&config.database.data_dir, migrate_opts.dry_run)
+                    .change_context(BppServerError::FailedToStart(
+                        "Migration failed".to_string(),
+                    ))?;
+                if report.steps.is_empty() {
+                    println!("Store is already at version {}", report.to);
+                    return Ok(());
+                }
+                for (version, step) in (report.from..).zip(&report.steps) {
+                    println!("{version} -> {}: {step}", version + 1);
+                }
+                match &report.previous_file {
+                    Some(previous_file) => println!(
+                        "Store migrated from version {} to {}, the previous notes are in {}",
+                        report.from,
+                        report.to,
+                        previous_file.display()
+                    ),
+                    None => println!(
+                        "Dry run, the store would be migrated from version {} to {}",
+                        report.from, report.to
+                    ),
+                }
+                Ok(())
+            }
+            Some(SubCommands::Reindex) => {
+                let count = open_store(config)?
+                    .reindex()
+                    .change_context(BppServerError::FailedToStart("Reindex failed".to_string()))?;
+                println!("Indexed {count} notes");
+                Ok(())
+            }
+            Some(SubCommands::Export(export_opts)) => {
+                let dao = open_store(config)?;
+                let count = match &export_opts.output {
+                    Some(path) => {
+                        let file =
+                            fs::File::create(path)
+                                .into_report()
+                                .change_context_lazy(|| {
+                                    BppServerError::FailedToExport(format!(
+                                        "Could not create {}",
+                                        path.display()
+                                    ))
+                                })?;
+                        dao.export(file)
+                    }
+                    None => dao.export(io::stdout()),
+                }
+                .change_context(BppServerError::FailedToExport(
+                    "Could not write notes".to_string(),
+                ))?;
+                eprintln!("Exported {count} notes");
+                Ok(())
+            }
+            Some(SubCommands::Import(import_opts)) => {
+                let dao = open_store(config)?;
+                let file = fs::File::open(&import_opts.input)
+                    .into_report()
+                    .change_context_lazy(|| {
+                        BppServerError::FailedToImport(format!(
+                            "Could not open {}",
+                            import_opts.input.display()
+                        ))
+                    })?;
+                let (imported, skipped) =
+                    dao.import(file)
+                        .change_context(BppServerError::FailedToImport(
+                            "Could not import notes".to_string(),
+                        ))?;
+                let actor = Actor::local();
+                let records: Vec<_> = imported
+                    .iter()
+                    .map(|note| AuditRecord::new(AuditKind::Import, note, &actor))
+                    .collect();
+                // The notes are stored already, like the server does
+                if let Err(err) = AuditLog::open(&config.database.data_dir, dao.keys())
+                    .and_then(|audit| audit.record_all(&records))
+                {
+                    warn!("Imported notes not recorded in the audit log\n{:?}", err);
+                }
+                println!(
+                    "Imported {} notes, skipped {skipped} existing notes",
+                    imported.len()
+                );
+                Ok(())
+            }
+            Some(SubCommands::Backup(backup_opts)) => {
+                let dao = NoteDao::open_read_only(
+                    &config.database.data_dir,
+                    config.database.passphrase_file.as_deref(),
+                )
+                .change_context_lazy(|| {
+                    BppServerError::FailedToBackup(format!(
+                        "Could not open store in {}",
+                        config.database.data_dir.display()
+                    ))
+                })?;
+                let path = backup_opts.output.clone().unwrap_or_else(|| {
+                    config
+                        .backup_dir()
+                        .join(backup::file_name(dao::unix_millis()))
+                });
+                let header = dao.backup(&path).change_context_lazy(|| {
+                    BppServerError::FailedToBackup(format!("Could not write {}", path.display()))
+                })?;
+                println!(
+                    "Backed up {} notes to {} (sha256 {})",
+                    header.note_count,
+                    path.display(),
+                    header.sha256
+                );
+                Ok(())
+            }
+            Some(SubCommands::Restore(restore_opts)) => restore(config, restore_opts),
+            Some(SubCommands::RotateKey(rotate_key_opts)) => rotate_key(config, rotate_key_opts),
+        }
     }
 }
 
-struct NoteDao {
-    connection_str: String, // user@host.com:8080
-    password: String,
+fn open_store(config: &Config) -> Result<NoteDao, BppServerError> {
+    NoteDao::from_config(config).change_context_lazy(|| {
+        BppServerError::FailedToStart(format!(
+            "Could not open store in {}",
+            config.database.data_dir.display()
+        ))
+    })
 }
 
-impl FromConfig for NoteDao {
-    fn from_config(config: &Config) -> error_stack::Result<Self, BppConfigError> {
-        Ok(NoteDao {
-            connection_str: config.database.connection_str.clone(),
-            password: config.database.password_str.clone(),
-        })
+fn restore(config: &Config, opts: &RestoreOpts) -> Result<(), BppServerError> {
+    let backup_dir = config.backup_dir();
+    if opts.list {
+        let backups = backup::list(&backup_dir).change_context(BppServerError::FailedToRestore(
+            "Could not list backups".to_string(),
+        ))?;
+        for (path, header) in backups {
+            println!(
+                "{}\t{}\t{} notes",
+                backup::timestamp(header.created_ms),
+                path.display(),
+                header.note_count
+            );
+        }
+        return Ok(());
+    }
+
+    let path = match (&opts.input, &opts.at) {
+        (Some(path), _) => path.clone(),
+        (None, Some(at)) => latest_backup(&backup_dir, at)?,
+        (None, None) => unreachable!("structopt requires a file or --at"),
+    };
+    let backup = backup::read(&path).change_context_lazy(|| {
+        BppServerError::FailedToRestore(format!("Could not read {}", path.display()))
+    })?;
+    if opts.verify {
+        println!(
+            "{} is valid: {} notes backed up at {}",
+            path.display(),
+            backup.header.note_count,
+            backup::timestamp(backup.header.created_ms)
+        );
+        return Ok(());
+    }
+
+    // Fails while the server runs, it keeps the notes in memory and would
+    // overwrite the restore
+    let dao = open_store(config)?;
+    if !dao.notes().is_empty() {
+        let safety = backup_dir.join(backup::file_name(dao::unix_millis()));
+        let header = dao.backup(&safety).change_context_lazy(|| {
+            BppServerError::FailedToRestore(format!(
+                "Could not back up the current notes to {}",
+                safety.display()
+            ))
+        })?;
+        println!(
+            "Backed up the {} current notes to {}",
+            header.note_count,
+            safety.display()
+        );
+    }
+    let restored = dao
+        .restore(&backup)
+        .change_context(BppServerError::FailedToRestore(
+            "Could not replace the notes".to_string(),
+        ))?;
+    dao.flush().change_context(BppServerError::FailedToRestore(
+        "Could not flush the store".to_string(),
+    ))?;
+    println!(
+        "Restored {} notes and {} files from {}, backed up at {}",
+        restored.notes,
+        restored.files,
+        path.display(),
+        backup::timestamp(backup.header.created_ms)
+    );
+    let record = AuditRecord::store(AuditKind::Restore, &Actor::local()).with_detail(&format!(
+        "{} sha256 {}, {} notes",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        backup.header.sha256,
+        restored.notes
+    ));
+    // After the files, the audit log may come from the backup
+    if let Err(err) = AuditLog::open(&config.database.data_dir, dao.keys())
+        .and_then(|audit| audit.record(&record))
+    {
+        warn!("Restore not recorded in the audit log\n{:?}", err);
     }
+    if !restored.missing_blobs.is_empty() {
+        // Version 1 backups only hold the notes
+        eprintln!(
+            "Warning: {} attachments are missing, their downloads will fail:",
+            restored.missing_blobs.len()
+        );
+        for digest in &restored.missing_blobs {
+            eprintln!("  {digest}");
+        }
+    }
+    Ok(())
 }
 
-fn main() {
-    if let Ok(config) = Config::load() {
-        // TODO: Remove unwrap and handle errors
-        let service = NoteService::from_config(&config).unwrap();
-        println!("This is the service: {:}", service.service_name)
+/// The latest backup of `dir` taken at or before `at`
+fn latest_backup(dir: &Path, at: &str) -> Result<PathBuf, BppServerError> {
+    let at = at.trim();
+    let time = if at.len() == 10 {
+        humantime::parse_rfc3339_weak(&format!("{at} 00:00:00"))
     } else {
-        panic!("Failed to start!")
+        humantime::parse_rfc3339_weak(at)
+    }
+    .into_report()
+    .change_context_lazy(|| BppServerError::FailedToRestore(format!("Invalid time {at:?}")))?;
+    let time_ms = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as i64);
+
+    backup::latest_before(dir, time_ms)
+        .change_context(BppServerError::FailedToRestore(
+            "Could not list backups".to_string(),
+        ))?
+        .map(|(path, _)| path)
+        .ok_or_else(|| {
+            report!(BppServerError::FailedToRestore(format!(
+                "No backup in {} was taken at or before {at}",
+                dir.display()
+            )))
+        })
+}
+
+async fn serve(
+    config: &Config,
+    overrides: ConfigOverrides,
+    log_handle: LogHandle,
+) -> Result<(), BppServerError> {
+    let addr = config
+        .service
+        .listen_addr()
+        .change_context(BppServerError::FailedToStart(
+            "Invalid listen address".to_string(),
+        ))?;
+    let service = NoteService::from_config(config).change_context_lazy(|| {
+        BppServerError::FailedToStart(format!(
+            "Could not open store in {}",
+            config.database.data_dir.display()
+        ))
+    })?;
+
+    let metrics_addr =
+        config
+            .metrics
+            .listen_addr()
+            .change_context(BppServerError::FailedToStart(
+                "Invalid metrics address".to_string(),
+            ))?;
+
+    info!(
+        data_dir = %config.database.data_dir.display(),
+        "{:} listening on {addr}", service.service_name
+    );
+    if let Some(metrics_addr) = metrics_addr {
+        let metrics_server = metrics::bind(&metrics_addr)
+            .into_report()
+            .change_context(BppServerError::FailedToServe(metrics_addr.to_string()))?;
+        info!("Metrics available on http://{metrics_addr}/metrics");
+        tokio::spawn(async move {
+            if let Err(err) = metrics_server.await {
+                error!("Metrics endpoint stopped: {err}");
+            }
+        });
+    }
+    let live_config = Arc::new(LiveConfig::new(config.clone(), overrides, log_handle));
+    tokio::spawn(live_config.clone().watch());
+
+    let mut signals =
+        ShutdownSignals::new()
+            .into_report()
+            .change_context(BppServerError::FailedToStart(
+                "Could not listen for shutdown signals".to_string(),
+            ))?;
+    let dao = service.dao();
+
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
+    let health_watch = tokio::spawn(health::watch_store(dao.clone(), health_reporter.clone()));
+    if let Some(interval) = config.backup.interval() {
+        info!(
+            ?interval,
+            keep = config.backup.keep,
+            "Backing up the store to {}",
+            config.backup_dir().display()
+        );
+    }
+    let backups = tokio::spawn(backup::schedule(dao.clone(), live_config.subscribe()));
+    let extraction = config.extraction.enabled.then(|| {
+        tokio::spawn(extract::run(
+            dao.clone(),
+            service.extraction(),
+            config.extraction.max_text_bytes,
+        ))
+    });
+    let reflection_service = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(bpp_proto::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(
+            tonic_health::proto::GRPC_HEALTH_V1_FILE_DESCRIPTOR_SET,
+        )
+        .build()
+        .into_report()
+        .change_context(BppServerError::FailedToStart(
+            "Could not build the reflection service".to_string(),
+        ))?;
+
+    let (stop, stopped) = oneshot::channel();
+    let in_flight = InFlight::default();
+    let server = Server::builder()
+        .trace_fn(logging::rpc_span)
+        .layer(MetricsLayer)
+        .layer(in_flight.clone())
+        .layer(RateLimitLayer::new(live_config.subscribe()))
+        .add_service(health_service)
+        .add_service(reflection_service)
+        .add_service(ApiServer::new(service))
+        .serve_with_shutdown(addr, async {
+            stopped.await.ok();
+        });
+    tokio::pin!(server);
+
+    let signal = tokio::select! {
+        result = &mut server => {
+            return result
+                .into_report()
+                .change_context(BppServerError::FailedToServe(addr.to_string()));
+        }
+        signal = signals.recv() => signal,
+    };
+
+    // Stop accepting connections and rpcs, and let the rpcs in flight
+    // complete. Clients that keep an idle connection open are not waited
+    // for
+    let deadline = config.service.shutdown_deadline();
+    info!(?deadline, "Received {signal}, draining rpcs in flight");
+    health_watch.abort();
+    backups.abort();
+    if let Some(extraction) = &extraction {
+        extraction.abort();
     }
-    // NoteService::build(&config);
+    health::set_not_serving(health_reporter).await;
+    stop.send(()).ok();
+    in_flight.drain();
+    tokio::select! {
+        result = &mut server => {
+            result
+                .into_report()
+                .change_context(BppServerError::FailedToServe(addr.to_string()))?;
+        }
+        _ = in_flight.idle() => {}
+        _ = tokio::time::sleep(deadline) => {
+            warn!("Rpcs still in flight after {deadline:?}, closing their connections");
+        }
+        signal = signals.recv() => {
+            warn!("Received {signal} again, closing connections without draining");
+        }
+    }
+
+    // Writes of rpcs still in flight past the deadline complete before the
+    // flush or fail after it, the store rejects writes once flushed
+    dao.flush().change_context(BppServerError::FailedToStop(
+        "Could not flush the store".to_string(),
+    ))?;
+    info!("Shutdown complete");
+    Ok(())
+}
+
+fn rotate_key(config: &Config, opts: &RotateKeyOpts) -> Result<(), BppServerError> {
+    let passphrase = Passphrase::read(&opts.new_passphrase_file).change_context(
+        BppServerError::FailedToRotateKey("Could not read the new passphrase".to_string()),
+    )?;
 
-    println!("Hello, world!");
+    let dao = open_store(config)?;
+    let (previous, rotated) =
+        dao.rotate_key(&passphrase)
+            .change_context(BppServerError::FailedToRotateKey(
+                "Could not encrypt the store with the new key".to_string(),
+            ))?;
+    dao.flush()
+        .change_context(BppServerError::FailedToRotateKey(
+            "Could not flush the store".to_string(),
+        ))?;
+    println!(
+        "Encrypted {} notes with key {rotated}, replacing key {previous}",
+        dao.notes().len()
+    );
+    println!("Set database.passphrase_file to the new passphrase before starting the server");
+    Ok(())
+}
+
+/// Print the config file in use and the effective config after merging
+/// the environment and command line. Secrets are redacted
+fn check_config(config: &Config, overrides: &ConfigOverrides) {
+    if let Ok(config_file) = Config::config_file(overrides.config_file.as_deref()) {
+        println!("# Config file: {:}", config_file.display());
+    }
+    match serde_json::to_string_pretty(config) {
+        Ok(effective) => println!("{effective}"),
+        Err(err) => {
+            eprintln!("Failed to print config: {err}");
+            process::exit(1)
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let opts = BppServerOpts::from_args();
+    let overrides = opts.overrides();
+
+    let config = match Config::load(&overrides) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Failed to start!\n{:?}", err);
+            process::exit(1)
+        }
+    };
+
+    if opts.check_config {
+        check_config(&config, &overrides);
+        return;
+    }
+
+    if let Err(err) = opts.run(&config).await {
+        eprintln!("{:?}", err);
+        process::exit(1)
+    }
 }
@@ -0,0 +1,288 @@
+use hyper::body::{HttpBody, SizeHint};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, StatusCode};
+use once_cell::sync::Lazy;
+use prometheus::{
+    histogram_opts, opts, Encoder, HistogramTimer, HistogramVec, IntCounterVec, IntGauge, Registry,
+    TextEncoder,
+};
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tonic::codegen::http::{HeaderMap, Request, Response};
+use tonic::Code;
+use tower::{Layer, Service};
+
+/// Services whose methods get their own label. Requests to anything else
+/// are counted under `other`, so clients cannot grow the label set
+static SERVICES: [&str; 3] = [
+    "bpp.Api",
+    "grpc.health.v1.Health",
+    "grpc.reflection.v1alpha.ServerReflection",
+];
+static METRICS_PATH: &str = "/metrics";
+
+/// Every metric exported by the server, registered in their own registry
+/// rather than the prometheus default one
+struct Metrics {
+    registry: Registry,
+    rpc_requests: IntCounterVec,
+    rpc_duration: HistogramVec,
+    store_duration: HistogramVec,
+    notes: IntGauge,
+    index_terms: IntGauge,
+    index_bytes: IntGauge,
+}
+
+static METRICS: Lazy<Metrics> = Lazy::new(|| {
+    let metrics = Metrics {
+        registry: Registry::new_custom(Some("bpp".to_string()), None)
+            .expect("bpp is a valid prefix"),
+        rpc_requests: IntCounterVec::new(
+            opts!(
+                "rpc_requests_total",
+                "Rpcs handled, by method and grpc code"
+            ),
+            &["method", "code"],
+        )
+        .expect("valid metric"),
+        rpc_duration: HistogramVec::new(
+            histogram_opts!("rpc_duration_seconds", "Time taken to handle rpcs"),
+            &["method"],
+        )
+        .expect("valid metric"),
+        store_duration: HistogramVec::new(
+            histogram_opts!(
+                "store_operation_duration_seconds",
+                "Time taken by reads and writes of the store files"
+            ),
+            &["operation"],
+        )
+        .expect("valid metric"),
+        notes: IntGauge::new("notes", "Notes in the store").expect("valid metric"),
+        index_terms: IntGauge::new("index_terms", "Distinct terms in the search index")
+            .expect("valid metric"),
+        index_bytes: IntGauge::new("index_bytes", "Size of the search index file")
+            .expect("valid metric"),
+    };
+
+    let registry = &metrics.registry;
+    registry
+        .register(Box::new(metrics.rpc_requests.clone()))
+        .and_then(|_| registry.register(Box::new(metrics.rpc_duration.clone())))
+        .and_then(|_| registry.register(Box::new(metrics.store_duration.clone())))
+        .and_then(|_| registry.register(Box::new(metrics.notes.clone())))
+        .and_then(|_| registry.register(Box::new(metrics.index_terms.clone())))
+        .and_then(|_| registry.register(Box::new(metrics.index_bytes.clone())))
+        .expect("metric names are unique");
+    metrics
+});
+
+/// Time a store operation, the duration is recorded when the timer is dropped
+pub fn store_timer(operation: &str) -> HistogramTimer {
+    METRICS
+        .store_duration
+        .with_label_values(&[operation])
+        .start_timer()
+}
+
+/// Record the size of the store after the search index was written
+pub fn record_index(notes: usize, terms: usize, bytes: usize) {
+    METRICS.notes.set(notes as i64);
+    METRICS.index_terms.set(terms as i64);
+    METRICS.index_bytes.set(bytes as i64);
+}
+
+fn record_rpc(path: &str, code: &str, started: Instant) {
+    let method = method_label(path);
+    METRICS
+        .rpc_requests
+        .with_label_values(&[method, code])
+        .inc();
+    METRICS
+        .rpc_duration
+        .with_label_values(&[method])
+        .observe(started.elapsed().as_secs_f64());
+}
+
+/// Rpc paths look like /bpp.Api/Add
+fn method_label(path: &str) -> &str {
+    let service = path.trim_start_matches('/').split('/').next();
+    match service {
+        Some(service) if SERVICES.contains(&service) => path,
+        _ => "other",
+    }
+}
+
+/// Bind the http endpoint serving the metrics in the prometheus text format
+/// on /metrics. Binding happens right away so an unusable address is
+/// reported at startup, the returned future serves the requests
+pub fn bind(addr: &SocketAddr) -> hyper::Result<impl Future<Output = hyper::Result<()>>> {
+    let make_service =
+        make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle_metrics)) });
+    Ok(hyper::Server::try_bind(addr)?.serve(make_service))
+}
+
+async fn handle_metrics(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::GET || request.uri().path() != METRICS_PATH {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .expect("valid response"));
+    }
+
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    let response = match encoder.encode(&METRICS.registry.gather(), &mut buffer) {
+        Ok(()) => Response::builder()
+            .header("content-type", encoder.format_type())
+            .body(Body::from(buffer)),
+        Err(err) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(err.to_string())),
+    };
+    Ok(response.expect("valid response"))
+}
+
+/// Records the count, duration and status code of every rpc
+#[derive(Debug, Clone, Default)]
+pub struct MetricsLayer;
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = MetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MetricsService { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MetricsService<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for MetricsService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<MetricsBody<ResBody>>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        let rpc = PendingRpc {
+            path: request.uri().path().to_string(),
+            started: Instant::now(),
+        };
+        let response = self.inner.call(request);
+
+        Box::pin(async move {
+            let response = match response.await {
+                Ok(response) => response,
+                Err(err) => {
+                    rpc.record("TransportError");
+                    return Err(err);
+                }
+            };
+            // Rpcs failing before any message carry their status in the
+            // headers, the others in the trailers after the body
+            let rpc = match grpc_code(response.headers()) {
+                Some(code) => {
+                    rpc.record(&format!("{code:?}"));
+                    None
+                }
+                None => Some(rpc),
+            };
+            Ok(response.map(|inner| MetricsBody { inner, rpc }))
+        })
+    }
+}
+
+/// An rpc recorded once its status is known
+struct PendingRpc {
+    path: String,
+    started: Instant,
+}
+
+impl PendingRpc {
+    fn record(self, code: &str) {
+        record_rpc(&self.path, code, self.started);
+    }
+}
+
+/// Response body recording the rpc when the trailers are sent, so
+/// streaming rpcs are timed until their last message. Bodies dropped
+/// before, eg. when the client cancels, are recorded as `Cancelled`
+pub struct MetricsBody<B> {
+    inner: B,
+    rpc: Option<PendingRpc>,
+}
+
+impl<B: HttpBody + Unpin> HttpBody for MetricsBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let polled = Pin::new(&mut self.inner).poll_data(cx);
+        if let Poll::Ready(Some(Err(_))) = &polled {
+            if let Some(rpc) = self.rpc.take() {
+                rpc.record("TransportError");
+            }
+        }
+        polled
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let polled = Pin::new(&mut self.inner).poll_trailers(cx);
+        if let Poll::Ready(result) = &polled {
+            let code = match result {
+                Ok(trailers) => {
+                    let code = trailers.as_ref().and_then(grpc_code);
+                    format!("{:?}", code.unwrap_or(Code::Unknown))
+                }
+                Err(_) => "TransportError".to_string(),
+            };
+            if let Some(rpc) = self.rpc.take() {
+                rpc.record(&code);
+            }
+        }
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl<B> Drop for MetricsBody<B> {
+    fn drop(&mut self) {
+        if let Some(rpc) = self.rpc.take() {
+            rpc.record(&format!("{:?}", Code::Cancelled));
+        }
+    }
+}
+
+/// The `grpc-status` of response headers or trailers
+fn grpc_code(headers: &HeaderMap) -> Option<Code> {
+    headers
+        .get("grpc-status")
+        .map(|status| Code::from_bytes(status.as_bytes()))
+}
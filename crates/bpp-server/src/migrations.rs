@@ -0,0 +1,79 @@
+use serde_json::{json, Value};
+
+/// A step upgrading the notes file from version `from` to `from + 1`.
+/// Steps work on the raw json document so they do not depend on the
+/// current `StoredNote`, and must leave it readable by the next step
+pub struct Migration {
+    pub from: u32,
+    pub description: &'static str,
+    apply: fn(&mut Value) -> Result<(), String>,
+}
+
+/// Every migration, one per version up to `STORE_VERSION`, in order
+static MIGRATIONS: [Migration; 2] = [
+    Migration {
+        from: 1,
+        description: "Store the notebook, tags and timestamps of every note",
+        apply: note_metadata,
+    },
+    Migration {
+        from: 2,
+        description: "Store the attachments of every note",
+        apply: note_attachments,
+    },
+];
+
+impl Migration {
+    /// Apply the step and set the version of `document` to the next one
+    pub fn apply(&self, document: &mut Value) -> Result<(), String> {
+        (self.apply)(document)?;
+        document["version"] = json!(self.from + 1);
+        Ok(())
+    }
+}
+
+/// The migrations upgrading a notes file from `version` to `target`,
+/// in order. None when a step is missing
+pub fn steps(version: u32, target: u32) -> Option<Vec<&'static Migration>> {
+    (version..target)
+        .map(|from| MIGRATIONS.iter().find(|migration| migration.from == from))
+        .collect()
+}
+
+fn notes(document: &mut Value) -> Result<&mut Vec<Value>, String> {
+    document
+        .get_mut("notes")
+        .and_then(Value::as_array_mut)
+        .ok_or_else(|| "`notes` is not a list".to_string())
+}
+
+/// 1 -> 2: notes stored before notebooks, tags and timestamps existed get
+/// them explicitly. Timestamps are unknown, hence 0
+fn note_metadata(document: &mut Value) -> Result<(), String> {
+    for (position, note) in notes(document)?.iter_mut().enumerate() {
+        let note = note
+            .as_object_mut()
+            .ok_or_else(|| format!("note {position} is not an object"))?;
+        for (key, default) in [
+            ("encrypted", json!(false)),
+            ("notebook", json!("")),
+            ("tags", json!([])),
+            ("created_ms", json!(0)),
+            ("updated_ms", json!(0)),
+        ] {
+            note.entry(key).or_insert(default);
+        }
+    }
+    Ok(())
+}
+
+/// 2 -> 3: notes get an empty list of attachments
+fn note_attachments(document: &mut Value) -> Result<(), String> {
+    for (position, note) in notes(document)?.iter_mut().enumerate() {
+        note.as_object_mut()
+            .ok_or_else(|| format!("note {position} is not an object"))?
+            .entry("attachments")
+            .or_insert(json!([]));
+    }
+    Ok(())
+}
@@ -0,0 +1,239 @@
+use crate::config::{Config, RateLimitConfig};
+use crate::error_def::BppServiceError;
+use crate::service::to_status;
+use error_stack::report;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tonic::body::BoxBody;
+use tonic::codegen::http::{Request, Response};
+use tonic::transport::server::TcpConnectInfo;
+use tower::{Layer, Service};
+
+/// Health checks are never limited, load balancers probe often
+static EXEMPT_PREFIX: &str = "/grpc.health.v1.Health/";
+/// Past this many clients, buckets that are full again are forgotten
+static MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Token bucket per client ip, see `RateLimitConfig`. The rate and burst
+/// are given on every acquire, so they can change while serving
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Take a token for `ip`, or return how long until one is available.
+    /// Always succeeds when rate limiting is disabled
+    fn acquire(&self, ip: IpAddr, config: &RateLimitConfig) -> Result<(), Duration> {
+        if config.requests_per_second == 0 {
+            return Ok(());
+        }
+        let rate = f64::from(config.requests_per_second);
+        let burst = f64::from(config.burst);
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|err| err.into_inner());
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            buckets.retain(|_, bucket| bucket.refilled(now, rate, burst) < burst);
+        }
+
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        bucket.tokens = bucket.refilled(now, rate, burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+}
+
+impl Bucket {
+    fn refilled(&self, now: Instant, rate: f64, burst: f64) -> f64 {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * rate).min(burst)
+    }
+}
+
+/// Rejects rpcs of clients over their rate with `ResourceExhausted`. The
+/// rate is read from the live config, so a reload applies to the next rpc
+#[derive(Debug, Clone)]
+pub struct RateLimitLayer {
+    config: watch::Receiver<Arc<Config>>,
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimitLayer {
+    pub fn new(config: watch::Receiver<Arc<Config>>) -> Self {
+        RateLimitLayer {
+            config,
+            limiter: Arc::default(),
+        }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            config: self.config.clone(),
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    config: watch::Receiver<Arc<Config>>,
+    limiter: Arc<RateLimiter>,
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for RateLimitService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        let client_ip = request
+            .extensions()
+            .get::<TcpConnectInfo>()
+            .and_then(TcpConnectInfo::remote_addr)
+            .map(|addr| addr.ip());
+        let limited = match client_ip {
+            Some(ip) if !request.uri().path().starts_with(EXEMPT_PREFIX) => {
+                let config = self.config.borrow().clone();
+                self.limiter.acquire(ip, &config.rate_limit).err()
+            }
+            _ => None,
+        };
+
+        match limited {
+            Some(retry_after) => {
+                let status = to_status(report!(BppServiceError::RateLimited(retry_after)));
+                Box::pin(async move { Ok(status.to_http()) })
+            }
+            None => Box::pin(self.inner.call(request)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn config(requests_per_second: u32, burst: u32) -> RateLimitConfig {
+        RateLimitConfig {
+            requests_per_second,
+            burst,
+        }
+    }
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))
+    }
+
+    /// Move the last update of the bucket of `ip` back by `elapsed`
+    fn rewind(limiter: &RateLimiter, ip: IpAddr, elapsed: Duration) {
+        let mut buckets = limiter.buckets.lock().unwrap();
+        let bucket = buckets.get_mut(&ip).unwrap();
+        bucket.updated -= elapsed;
+    }
+
+    #[test]
+    fn bursts_are_allowed_then_limited() {
+        let limiter = RateLimiter::default();
+        let config = config(2, 3);
+        for _ in 0..3 {
+            assert_eq!(limiter.acquire(ip(1), &config), Ok(()));
+        }
+        let retry_after = limiter.acquire(ip(1), &config).unwrap_err();
+        assert!(retry_after > Duration::from_millis(400), "{retry_after:?}");
+        assert!(retry_after <= Duration::from_millis(500), "{retry_after:?}");
+    }
+
+    #[test]
+    fn buckets_refill_at_the_rate_up_to_the_burst() {
+        let limiter = RateLimiter::default();
+        let config = config(2, 3);
+        for _ in 0..3 {
+            limiter.acquire(ip(1), &config).unwrap();
+        }
+        rewind(&limiter, ip(1), Duration::from_secs(1));
+        assert_eq!(limiter.acquire(ip(1), &config), Ok(()));
+        assert_eq!(limiter.acquire(ip(1), &config), Ok(()));
+        assert!(limiter.acquire(ip(1), &config).is_err());
+
+        rewind(&limiter, ip(1), Duration::from_secs(60));
+        for _ in 0..3 {
+            limiter.acquire(ip(1), &config).unwrap();
+        }
+        assert!(limiter.acquire(ip(1), &config).is_err());
+    }
+
+    #[test]
+    fn clients_are_limited_separately() {
+        let limiter = RateLimiter::default();
+        let config = config(1, 1);
+        assert_eq!(limiter.acquire(ip(1), &config), Ok(()));
+        assert!(limiter.acquire(ip(1), &config).is_err());
+        assert_eq!(limiter.acquire(ip(2), &config), Ok(()));
+    }
+
+    #[test]
+    fn a_zero_rate_disables_the_limit() {
+        let limiter = RateLimiter::default();
+        for _ in 0..100 {
+            assert_eq!(limiter.acquire(ip(1), &config(0, 0)), Ok(()));
+        }
+        assert!(limiter.buckets.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn full_buckets_are_forgotten_past_the_tracked_clients() {
+        let limiter = RateLimiter::default();
+        let config = config(1, 1);
+        let now = Instant::now();
+        {
+            let mut buckets = limiter.buckets.lock().unwrap();
+            for n in 0..MAX_TRACKED_CLIENTS as u32 {
+                buckets.insert(
+                    IpAddr::V4(Ipv4Addr::from(n)),
+                    Bucket {
+                        tokens: 1.0,
+                        updated: now,
+                    },
+                );
+            }
+        }
+        limiter.acquire(ip(1), &config).unwrap();
+        assert!(limiter.acquire(ip(1), &config).is_err());
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 1);
+    }
+}
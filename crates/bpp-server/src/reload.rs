@@ -0,0 +1,201 @@
+use crate::config::{Config, ConfigOverrides};
+use crate::error_def::BppConfigError;
+use crate::logging::LogHandle;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+/// Config keys, or sections ending with `.`, applied without a restart.
+/// `LiveConfig::merge_reloadable` must copy exactly these
+static RELOADABLE: [&str; 3] = ["logging.level", "rate_limit.", "backup."];
+/// How often the config file is checked for changes
+static POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Config of a running server. Reloading swaps the whole config at once,
+/// so readers never observe a mix of old and new values. Parts of the
+/// server applying reloadable settings read them from `LiveConfig::subscribe`
+pub struct LiveConfig {
+    overrides: ConfigOverrides,
+    current: watch::Sender<Arc<Config>>,
+    log_handle: LogHandle,
+}
+
+/// Outcome of `LiveConfig::reload`
+#[derive(Debug, Default)]
+pub struct ReloadReport {
+    pub applied: Vec<String>,
+    pub restart_required: Vec<String>,
+}
+
+impl LiveConfig {
+    pub fn new(config: Config, overrides: ConfigOverrides, log_handle: LogHandle) -> Self {
+        LiveConfig {
+            overrides,
+            current: watch::channel(Arc::new(config)).0,
+            log_handle,
+        }
+    }
+
+    /// The current config, updated on every reload that applies settings
+    pub fn subscribe(&self) -> watch::Receiver<Arc<Config>> {
+        self.current.subscribe()
+    }
+
+    /// Load the config again and apply the settings listed in `RELOADABLE`.
+    /// Other settings that changed are reported and keep their current
+    /// value until the server restarts. An invalid config is not applied
+    pub fn reload(&self) -> error_stack::Result<ReloadReport, BppConfigError> {
+        let new = Config::load(&self.overrides)?;
+
+        let current = self.current.borrow().clone();
+        let mut report = ReloadReport::default();
+        for key in changed_keys(&current, &new) {
+            if RELOADABLE
+                .iter()
+                .any(|reloadable| is_under(&key, reloadable))
+            {
+                report.applied.push(key);
+            } else {
+                report.restart_required.push(key);
+            }
+        }
+
+        if !report.applied.is_empty() {
+            let merged = Self::merge_reloadable(&current, new);
+            self.log_handle.set_level(&merged.logging);
+            self.current.send_replace(Arc::new(merged));
+        }
+        Ok(report)
+    }
+
+    fn merge_reloadable(current: &Config, new: Config) -> Config {
+        let mut merged = current.clone();
+        merged.logging.level = new.logging.level;
+        merged.rate_limit = new.rate_limit;
+        merged.backup = new.backup;
+        merged
+    }
+
+    /// Reload on SIGHUP and whenever the config file is modified
+    pub async fn watch(self: Arc<Self>) {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => Some(hangup),
+            Err(err) => {
+                warn!("Reload on SIGHUP disabled: {err}");
+                None
+            }
+        };
+        let config_file = Config::config_file(self.overrides.config_file.as_deref()).ok();
+        let mut last_modified = config_file.as_deref().and_then(modified);
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+        loop {
+            tokio::select! {
+                Some(_) = recv(&mut hangup) => {
+                    info!("Received SIGHUP, reloading config");
+                }
+                _ = interval.tick() => {
+                    let modified = config_file.as_deref().and_then(modified);
+                    if modified == last_modified {
+                        continue;
+                    }
+                    last_modified = modified;
+                    info!("Config file changed, reloading config");
+                }
+            }
+            self.reload_and_report();
+        }
+    }
+
+    fn reload_and_report(&self) {
+        match self.reload() {
+            Ok(report) if report.applied.is_empty() && report.restart_required.is_empty() => {
+                info!("Config reloaded, nothing changed")
+            }
+            Ok(report) => {
+                if !report.applied.is_empty() {
+                    info!(applied = ?report.applied, "Config reloaded");
+                }
+                if !report.restart_required.is_empty() {
+                    warn!(
+                        restart_required = ?report.restart_required,
+                        "Changed settings require a restart to be applied"
+                    );
+                }
+            }
+            Err(err) => error!("Config not reloaded, keeping current config\n{:?}", err),
+        }
+    }
+}
+
+async fn recv(hangup: &mut Option<tokio::signal::unix::Signal>) -> Option<()> {
+    match hangup {
+        Some(hangup) => hangup.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+fn is_under(key: &str, reloadable: &str) -> bool {
+    match reloadable.strip_suffix('.') {
+        Some(section) => key.starts_with(reloadable) || key == section,
+        None => key == reloadable,
+    }
+}
+
+/// Dotted paths of the settings that differ between `old` and `new`
+fn changed_keys(old: &Config, new: &Config) -> Vec<String> {
+    let mut old_values = BTreeMap::new();
+    let mut new_values = BTreeMap::new();
+    flatten(
+        "",
+        &serde_json::to_value(old).unwrap_or_default(),
+        &mut old_values,
+    );
+    flatten(
+        "",
+        &serde_json::to_value(new).unwrap_or_default(),
+        &mut new_values,
+    );
+
+    let mut changed: Vec<String> = new_values
+        .iter()
+        .filter(|(key, value)| old_values.get(*key) != Some(value))
+        .map(|(key, _)| key.clone())
+        .collect();
+
+    // Secrets serialize redacted, compare their values directly
+    let old_password = old.database.password_str.as_ref().map(|p| p.expose());
+    let new_password = new.database.password_str.as_ref().map(|p| p.expose());
+    if old_password != new_password && !changed.iter().any(|k| k == "database.password_str") {
+        changed.push("database.password_str".to_string());
+    }
+    changed
+}
+
+fn flatten(prefix: &str, value: &Value, out: &mut BTreeMap<String, Value>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let path = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{prefix}.{key}")
+                };
+                flatten(&path, value, out);
+            }
+        }
+        _ => {
+            out.insert(prefix.to_string(), value.clone());
+        }
+    }
+}
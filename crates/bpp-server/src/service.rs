@@ -0,0 +1,725 @@
+use crate::audit::{Actor, AuditKind, AuditLog, AuditRecord};
+use crate::config::{Config, FromConfig, LimitsConfig};
+use crate::dao::{Merged, NoteDao, ResolvedLink, SearchHit, StoredNote};
+use crate::error_def::{BppServiceError, BppStoreError};
+use crate::templates::{StoredTemplate, TemplateStore};
+use crate::validation::{
+    validate_add, validate_attachment, validate_id, validate_import, validate_template,
+    validate_template_name,
+};
+use bpp_proto::bpp::api_server::Api;
+use bpp_proto::bpp::graph_response::Item;
+use bpp_proto::bpp::{
+    AddRequest, AddResponse, AttachRequest, AttachResponse, AttachmentsRequest,
+    AttachmentsResponse, AuditAction, AuditLogRequest, AuditLogResponse, BacklinksRequest,
+    BacklinksResponse, CreateTemplateRequest, CreateTemplateResponse, DeleteTemplateRequest,
+    DeleteTemplateResponse, ExportRequest, ExportResponse, FetchRequest, FetchResponse,
+    GetTemplateRequest, GetTemplateResponse, GraphEdge, GraphEdgeKind, GraphNode, GraphNodeKind,
+    GraphRequest, GraphResponse, ImportRequest, ImportResponse, ImportResult, ImportStatus, Link,
+    LinksRequest, LinksResponse, ListTemplatesRequest, ListTemplatesResponse, RmRequest,
+    RmResponse, SearchMatch, SearchRequest, SearchResponse, UpdateTemplateRequest,
+    UpdateTemplateResponse,
+};
+use bpp_proto::{ERROR_REASON_KEY, RESOURCE_NAME_KEY, RESOURCE_TYPE_KEY, RETRY_AFTER_KEY};
+use error_stack::{report, Report};
+use std::collections::BTreeSet;
+use std::io::Read;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Notify};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::Stream;
+use tonic::metadata::{MetadataMap, MetadataValue};
+use tonic::{Request, Response, Status, Streaming};
+use tonic_types::{BadRequest, ErrorDetail, StatusExt};
+use tracing::{debug, error, info, warn};
+
+/// Delay clients are asked to wait before retrying an unavailable service
+static RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Size of the chunks of data sent by `fetch`
+static FETCH_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Implementation of the `Api` grpc service on top of `NoteDao`
+pub struct NoteService {
+    pub service_name: String,
+    dao: Arc<NoteDao>,
+    audit: AuditLog,
+    templates: TemplateStore,
+    limits: LimitsConfig,
+    /// Wakes up `extract::run` when files are attached
+    extraction: Arc<Notify>,
+}
+
+impl FromConfig<BppStoreError> for NoteService {
+    fn from_config(config: &Config) -> error_stack::Result<Self, BppStoreError> {
+        let dao = NoteDao::from_config(config)?;
+        Ok(NoteService {
+            service_name: config.service.name.clone(),
+            audit: AuditLog::open(&config.database.data_dir, dao.keys())?,
+            dao: Arc::new(dao),
+            templates: TemplateStore::from_config(config)?,
+            limits: config.limits.clone(),
+            extraction: Arc::new(Notify::new()),
+        })
+    }
+}
+
+impl NoteService {
+    /// The store behind the service, shared with the health checks
+    pub fn dao(&self) -> Arc<NoteDao> {
+        self.dao.clone()
+    }
+
+    /// Notified when the text of new attachments is to be extracted
+    pub fn extraction(&self) -> Arc<Notify> {
+        self.extraction.clone()
+    }
+
+    /// The mutation already happened, so a failure to record it is logged
+    /// rather than failing the rpc
+    fn audit(&self, record: AuditRecord) {
+        if let Err(err) = self.audit.record(&record) {
+            error!("{:?}", err);
+        }
+    }
+}
+
+/// Tags are nodes of the graph too, with ids apart from note ids
+fn tag_node_id(tag: &str) -> String {
+    format!("#{tag}")
+}
+
+fn store_error(err: Report<BppStoreError>) -> Report<BppServiceError> {
+    let context = match err.current_context() {
+        BppStoreError::Closed => {
+            BppServiceError::Unavailable("Server is shutting down".to_string())
+        }
+        BppStoreError::QuotaExceeded(msg) => BppServiceError::QuotaExceeded(msg.clone()),
+        BppStoreError::NoteExists(id) => BppServiceError::NoteExists(id.clone()),
+        other => BppServiceError::Internal(other.to_string()),
+    };
+    err.change_context(context)
+}
+
+/// Log `err` and turn it into a status. The cause of the error is set in
+/// the `x-bpp-error-reason` metadata, along with the resource it was about
+/// or the delay before retrying when there is one. Invalid arguments are
+/// sent as a `BadRequest` detail. Internal details are logged but never
+/// sent to the client
+pub fn to_status(err: Report<BppServiceError>) -> Status {
+    let context = err.current_context();
+    match context {
+        BppServiceError::Internal(_) => error!("{:?}", err),
+        BppServiceError::Unavailable(_) | BppServiceError::QuotaExceeded(_) => warn!("{context}"),
+        _ => debug!("{context}"),
+    }
+
+    let mut metadata = MetadataMap::new();
+    metadata.insert(
+        ERROR_REASON_KEY,
+        MetadataValue::from_static(context.reason()),
+    );
+    let mut details = Vec::new();
+    let message = match context {
+        BppServiceError::NoteNotFound(id) | BppServiceError::NoteExists(id) => {
+            insert_resource(&mut metadata, "note", id);
+            context.to_string()
+        }
+        BppServiceError::AttachmentNotFound(name) => {
+            insert_resource(&mut metadata, "attachment", name);
+            context.to_string()
+        }
+        BppServiceError::TemplateNotFound(name) | BppServiceError::TemplateExists(name) => {
+            insert_resource(&mut metadata, "template", name);
+            context.to_string()
+        }
+        BppServiceError::InvalidArgument { field, description } => {
+            details.push(ErrorDetail::BadRequest(BadRequest::with_violation(
+                field.clone(),
+                description.clone(),
+            )));
+            context.to_string()
+        }
+        BppServiceError::RateLimited(retry_after) => {
+            insert_retry_after(&mut metadata, *retry_after);
+            context.to_string()
+        }
+        // The code already tells it is a quota, the message is shown as is
+        BppServiceError::QuotaExceeded(description) => description.clone(),
+        BppServiceError::Unavailable(_) => {
+            insert_retry_after(&mut metadata, RETRY_DELAY);
+            context.to_string()
+        }
+        BppServiceError::Internal(_) => "Internal error, see the server logs".to_string(),
+    };
+
+    Status::with_error_details_vec_and_metadata(context.code(), message, details, metadata)
+}
+
+fn insert_resource(metadata: &mut MetadataMap, resource_type: &'static str, name: &str) {
+    metadata.insert(RESOURCE_TYPE_KEY, MetadataValue::from_static(resource_type));
+    metadata.insert_bin(
+        RESOURCE_NAME_KEY,
+        MetadataValue::from_bytes(name.as_bytes()),
+    );
+}
+
+fn insert_retry_after(metadata: &mut MetadataMap, retry_after: Duration) {
+    // Rounded up, retrying early would be rejected again
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    metadata.insert(RETRY_AFTER_KEY, MetadataValue::from(seconds));
+}
+
+#[tonic::async_trait]
+impl Api for NoteService {
+    type ExportStream = Pin<Box<dyn Stream<Item = Result<ExportResponse, Status>> + Send>>;
+    type GraphStream = Pin<Box<dyn Stream<Item = Result<GraphResponse, Status>> + Send>>;
+    type FetchStream = Pin<Box<dyn Stream<Item = Result<FetchResponse, Status>> + Send>>;
+
+    async fn add(&self, request: Request<AddRequest>) -> Result<Response<AddResponse>, Status> {
+        let actor = Actor::from_request(&request);
+        let note = validate_add(request.into_inner(), &self.limits).map_err(to_status)?;
+        let note = self
+            .dao
+            .add(note.id, &note.title, &note.content, note.encrypted)
+            .map_err(store_error)
+            .map_err(to_status)?;
+        info!(id = %note.id, encrypted = note.encrypted, "Note added");
+        self.audit(AuditRecord::new(AuditKind::Add, &note, &actor));
+
+        let broken_links = self
+            .dao
+            .links(&note.id)
+            .map(|(_, links)| links)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|link| link.note.is_none())
+            .map(|link| link.target)
+            .collect();
+        Ok(Response::new(AddResponse {
+            note: Some(note.into()),
+            broken_links,
+        }))
+    }
+
+    async fn rm(&self, request: Request<RmRequest>) -> Result<Response<RmResponse>, Status> {
+        let actor = Actor::from_request(&request);
+        let id = validate_id(request.into_inner().id).map_err(to_status)?;
+        let note = self
+            .dao
+            .remove(&id)
+            .map_err(store_error)
+            .and_then(|note| note.ok_or_else(|| report!(BppServiceError::NoteNotFound(id.clone()))))
+            .map_err(to_status)?;
+        info!(id = %note.id, "Note removed");
+        self.audit(AuditRecord::new(AuditKind::Rm, &note, &actor));
+
+        Ok(Response::new(RmResponse {
+            note: Some(note.into()),
+        }))
+    }
+
+    async fn search(
+        &self,
+        request: Request<SearchRequest>,
+    ) -> Result<Response<SearchResponse>, Status> {
+        let request = request.into_inner();
+        let hits = self
+            .dao
+            .search(&request.query, request.all, request.attachments);
+        debug!(
+            all = request.all,
+            attachments = request.attachments,
+            found = hits.len(),
+            "Search"
+        );
+
+        let mut notes = vec![];
+        let mut matches = vec![];
+        for SearchHit { note, attachments } in hits {
+            if !attachments.is_empty() {
+                matches.push(SearchMatch {
+                    note_id: note.id.clone(),
+                    attachments,
+                });
+            }
+            notes.push(note.into());
+        }
+        Ok(Response::new(SearchResponse { notes, matches }))
+    }
+
+    async fn export(
+        &self,
+        request: Request<ExportRequest>,
+    ) -> Result<Response<Self::ExportStream>, Status> {
+        let filter = request.into_inner().filter.unwrap_or_default();
+        // A snapshot, notes added during the export are not part of it
+        let hits = self
+            .dao
+            .search(&filter.query, filter.all, filter.attachments);
+        debug!(all = filter.all, found = hits.len(), "Export");
+
+        let responses = hits
+            .into_iter()
+            .map(|hit| ExportResponse {
+                note: Some(hit.note.into()),
+            })
+            .map(Ok);
+        Ok(Response::new(Box::pin(tokio_stream::iter(responses))))
+    }
+
+    async fn import(
+        &self,
+        request: Request<Streaming<ImportRequest>>,
+    ) -> Result<Response<ImportResponse>, Status> {
+        let actor = Actor::from_request(&request);
+        let mut stream = request.into_inner();
+
+        let mut dry_run = None;
+        let mut results = vec![];
+        // Notes that passed validation, with the position of their result
+        let mut valid = vec![];
+        while let Some(message) = stream.message().await? {
+            dry_run.get_or_insert(message.dry_run);
+            match validate_import(message.note.unwrap_or_default(), &self.limits) {
+                Ok(note) => {
+                    valid.push((results.len(), note));
+                    results.push(ImportResult {
+                        source: message.source,
+                        ..Default::default()
+                    });
+                }
+                Err(err) => results.push(ImportResult {
+                    source: message.source,
+                    status: ImportStatus::Failed as i32,
+                    note_id: String::new(),
+                    error: err.current_context().to_string(),
+                }),
+            }
+        }
+        let dry_run = dry_run.unwrap_or_default();
+
+        let (positions, notes): (Vec<_>, Vec<_>) = valid.into_iter().unzip();
+        let merged = self
+            .dao
+            .merge(notes, dry_run)
+            .map_err(store_error)
+            .map_err(to_status)?;
+        let mut records = vec![];
+        for (position, merged) in positions.into_iter().zip(merged) {
+            let result = &mut results[position];
+            match merged {
+                Merged::Added(note) => {
+                    result.status = ImportStatus::Imported as i32;
+                    // A dry run stores nothing, the id would refer to no note
+                    if !dry_run {
+                        result.note_id = note.id.clone();
+                    }
+                    records.push(AuditRecord::new(AuditKind::Import, &note, &actor));
+                }
+                Merged::Duplicate(id) => {
+                    result.status = ImportStatus::Duplicate as i32;
+                    result.note_id = id;
+                }
+                Merged::IdTaken(id) => {
+                    result.status = ImportStatus::Failed as i32;
+                    result.error =
+                        format!("Encrypted note {id} cannot be imported, the id is taken");
+                }
+            }
+        }
+        info!(
+            dry_run,
+            received = results.len(),
+            imported = records.len(),
+            "Notes imported"
+        );
+        if !dry_run {
+            if let Err(err) = self.audit.record_all(&records) {
+                error!("{:?}", err);
+            }
+        }
+
+        Ok(Response::new(ImportResponse { results, dry_run }))
+    }
+
+    async fn links(
+        &self,
+        request: Request<LinksRequest>,
+    ) -> Result<Response<LinksResponse>, Status> {
+        let id = validate_id(request.into_inner().id).map_err(to_status)?;
+        let (note, links) = self
+            .dao
+            .links(&id)
+            .ok_or_else(|| to_status(report!(BppServiceError::NoteNotFound(id.clone()))))?;
+        debug!(id, links = links.len(), "Links");
+
+        Ok(Response::new(LinksResponse {
+            note: Some(note.into()),
+            links: links
+                .into_iter()
+                .map(|ResolvedLink { target, note }| match note {
+                    Some(note) => Link {
+                        target,
+                        note_id: note.id,
+                        note_title: note.title,
+                        broken: false,
+                    },
+                    None => Link {
+                        target,
+                        broken: true,
+                        ..Default::default()
+                    },
+                })
+                .collect(),
+        }))
+    }
+
+    async fn backlinks(
+        &self,
+        request: Request<BacklinksRequest>,
+    ) -> Result<Response<BacklinksResponse>, Status> {
+        let id = validate_id(request.into_inner().id).map_err(to_status)?;
+        let notes = self
+            .dao
+            .backlinks(&id)
+            .ok_or_else(|| to_status(report!(BppServiceError::NoteNotFound(id.clone()))))?;
+        debug!(id, found = notes.len(), "Backlinks");
+
+        Ok(Response::new(BacklinksResponse {
+            notes: notes.into_iter().map(StoredNote::into).collect(),
+        }))
+    }
+
+    async fn attach(
+        &self,
+        request: Request<Streaming<AttachRequest>>,
+    ) -> Result<Response<AttachResponse>, Status> {
+        let actor = Actor::from_request(&request);
+        let mut stream = request.into_inner();
+        let first = stream.message().await?.unwrap_or_default();
+        let note_id = validate_id(first.note_id).map_err(to_status)?;
+        let (name, media_type) =
+            validate_attachment(&first.name, &first.media_type, &self.limits).map_err(to_status)?;
+        // Checked upfront to not receive the data for nothing. `attach`
+        // checks again, the note may be removed meanwhile
+        match self.dao.note(&note_id) {
+            None => return Err(to_status(report!(BppServiceError::NoteNotFound(note_id)))),
+            // Attachments are stored as sent, the server cannot encrypt
+            // them with the key of the client
+            Some(note) if note.encrypted => {
+                return Err(to_status(report!(BppServiceError::InvalidArgument {
+                    field: "note_id".to_string(),
+                    description: format!(
+                        "note {note_id} is encrypted, its attachments would be stored in plaintext"
+                    ),
+                })))
+            }
+            Some(_) => {}
+        }
+
+        let mut blob = self
+            .dao
+            .blob_writer()
+            .map_err(store_error)
+            .map_err(to_status)?;
+        let mut data = first.data;
+        loop {
+            let size = blob.size() + data.len() as u64;
+            if size > self.limits.max_attachment_bytes as u64 {
+                return Err(to_status(report!(BppServiceError::InvalidArgument {
+                    field: "data".to_string(),
+                    description: format!(
+                        "must be at most {} bytes",
+                        self.limits.max_attachment_bytes
+                    ),
+                })));
+            }
+            blob.write(&data).map_err(store_error).map_err(to_status)?;
+            data = match stream.message().await? {
+                Some(message) => message.data,
+                None => break,
+            };
+        }
+
+        let (note, attachment, deduplicated) = self
+            .dao
+            .attach(&note_id, &name, &media_type, blob)
+            .map_err(store_error)
+            .and_then(|attached| {
+                attached.ok_or_else(|| report!(BppServiceError::NoteNotFound(note_id.clone())))
+            })
+            .map_err(to_status)?;
+        info!(
+            id = %note.id,
+            name = %attachment.name,
+            size = attachment.size,
+            deduplicated,
+            "Attachment added"
+        );
+        self.audit(
+            AuditRecord::new(AuditKind::Attach, &note, &actor).with_detail(&attachment.name),
+        );
+
+        self.extraction.notify_one();
+
+        Ok(Response::new(AttachResponse {
+            note: Some(note.into()),
+            attachment: Some(attachment.into()),
+            deduplicated,
+        }))
+    }
+
+    async fn attachments(
+        &self,
+        request: Request<AttachmentsRequest>,
+    ) -> Result<Response<AttachmentsResponse>, Status> {
+        let id = validate_id(request.into_inner().note_id).map_err(to_status)?;
+        let note = self
+            .dao
+            .note(&id)
+            .ok_or_else(|| to_status(report!(BppServiceError::NoteNotFound(id.clone()))))?;
+        debug!(id, found = note.attachments.len(), "Attachments");
+
+        Ok(Response::new(AttachmentsResponse {
+            attachments: note.attachments.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn fetch(
+        &self,
+        request: Request<FetchRequest>,
+    ) -> Result<Response<Self::FetchStream>, Status> {
+        let request = request.into_inner();
+        let id = validate_id(request.note_id).map_err(to_status)?;
+        if self.dao.note(&id).is_none() {
+            return Err(to_status(report!(BppServiceError::NoteNotFound(id))));
+        }
+        let (attachment, mut file) = self
+            .dao
+            .attachment(&id, request.name.trim())
+            .map_err(store_error)
+            .and_then(|found| {
+                found.ok_or_else(|| {
+                    report!(BppServiceError::AttachmentNotFound(
+                        request.name.trim().to_string()
+                    ))
+                })
+            })
+            .map_err(to_status)?;
+        debug!(id, name = %attachment.name, size = attachment.size, "Fetch");
+
+        // Read from a blocking thread, as fast as the client receives
+        let (sender, receiver) = mpsc::channel(4);
+        tokio::task::spawn_blocking(move || {
+            let mut response = FetchResponse {
+                attachment: Some(attachment.into()),
+                data: vec![],
+            };
+            loop {
+                let mut data = vec![0; FETCH_CHUNK_BYTES];
+                let read = match file.read(&mut data) {
+                    Ok(read) => read,
+                    Err(err) => {
+                        let err = report!(err).change_context(BppServiceError::Internal(
+                            "Could not read the attachment".to_string(),
+                        ));
+                        sender.blocking_send(Err(to_status(err))).ok();
+                        return;
+                    }
+                };
+                data.truncate(read);
+                // The first message is sent even for empty data
+                if read == 0 && response.attachment.is_none() {
+                    return;
+                }
+                response.data = data;
+                if sender.blocking_send(Ok(response)).is_err() || read == 0 {
+                    return;
+                }
+                response = FetchResponse::default();
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(receiver))))
+    }
+
+    async fn graph(
+        &self,
+        request: Request<GraphRequest>,
+    ) -> Result<Response<Self::GraphStream>, Status> {
+        let request = request.into_inner();
+        // A snapshot, like `export`
+        let graph = self.dao.graph(&request.notebook, &request.tag);
+        let tags: BTreeSet<String> = graph
+            .notes
+            .iter()
+            .flat_map(|note| note.tags.iter().cloned())
+            .collect();
+        debug!(
+            notes = graph.notes.len(),
+            tags = tags.len(),
+            links = graph.links.len(),
+            "Graph"
+        );
+
+        let tag_edges: Vec<GraphEdge> = graph
+            .notes
+            .iter()
+            .flat_map(|note| {
+                note.tags.iter().map(|tag| GraphEdge {
+                    source: note.id.clone(),
+                    target: tag_node_id(tag),
+                    kind: GraphEdgeKind::Tag as i32,
+                })
+            })
+            .collect();
+        let note_nodes = graph.notes.into_iter().map(|note| GraphNode {
+            id: note.id,
+            kind: GraphNodeKind::Note as i32,
+            title: note.title,
+            notebook: note.notebook,
+            tags: note.tags,
+        });
+        let tag_nodes = tags.into_iter().map(|tag| GraphNode {
+            id: tag_node_id(&tag),
+            kind: GraphNodeKind::Tag as i32,
+            title: tag,
+            ..Default::default()
+        });
+        let link_edges = graph.links.into_iter().map(|(source, target)| GraphEdge {
+            source,
+            target,
+            kind: GraphEdgeKind::Link as i32,
+        });
+
+        let responses = note_nodes
+            .chain(tag_nodes)
+            .map(Item::Node)
+            .chain(link_edges.chain(tag_edges).map(Item::Edge))
+            .map(|item| GraphResponse { item: Some(item) })
+            .map(Ok);
+        Ok(Response::new(Box::pin(tokio_stream::iter(responses))))
+    }
+
+    async fn audit_log(
+        &self,
+        request: Request<AuditLogRequest>,
+    ) -> Result<Response<AuditLogResponse>, Status> {
+        let request = request.into_inner();
+        let invalid = |field: &str, description: &str| {
+            to_status(report!(BppServiceError::InvalidArgument {
+                field: field.to_string(),
+                description: description.to_string(),
+            }))
+        };
+        if AuditAction::from_i32(request.action).is_none() {
+            return Err(invalid("action", "unknown action"));
+        }
+        if request.since_ms != 0 && request.until_ms != 0 && request.since_ms > request.until_ms {
+            return Err(invalid("since_ms", "cannot be after until_ms"));
+        }
+
+        let records = self
+            .audit
+            .query(&request)
+            .map_err(store_error)
+            .map_err(to_status)?;
+        debug!(found = records.len(), "Audit log");
+
+        Ok(Response::new(AuditLogResponse {
+            events: records.into_iter().map(AuditRecord::into).collect(),
+        }))
+    }
+
+    async fn create_template(
+        &self,
+        request: Request<CreateTemplateRequest>,
+    ) -> Result<Response<CreateTemplateResponse>, Status> {
+        let template = request.into_inner().template.unwrap_or_default();
+        let template = validate_template(template, &self.limits).map_err(to_status)?;
+        let name = template.name.clone();
+        let template = self
+            .templates
+            .create(template)
+            .map_err(store_error)
+            .and_then(|created| {
+                created.ok_or_else(|| report!(BppServiceError::TemplateExists(name.clone())))
+            })
+            .map_err(to_status)?;
+        info!(name, "Template created");
+
+        Ok(Response::new(CreateTemplateResponse {
+            template: Some(template.into()),
+        }))
+    }
+
+    async fn get_template(
+        &self,
+        request: Request<GetTemplateRequest>,
+    ) -> Result<Response<GetTemplateResponse>, Status> {
+        let name = validate_template_name(&request.into_inner().name).map_err(to_status)?;
+        let template = self
+            .templates
+            .get(&name)
+            .ok_or_else(|| to_status(report!(BppServiceError::TemplateNotFound(name))))?;
+
+        Ok(Response::new(GetTemplateResponse {
+            template: Some(template.into()),
+        }))
+    }
+
+    async fn list_templates(
+        &self,
+        _request: Request<ListTemplatesRequest>,
+    ) -> Result<Response<ListTemplatesResponse>, Status> {
+        let templates = self.templates.list();
+        debug!(found = templates.len(), "Templates");
+
+        Ok(Response::new(ListTemplatesResponse {
+            templates: templates.into_iter().map(StoredTemplate::into).collect(),
+        }))
+    }
+
+    async fn update_template(
+        &self,
+        request: Request<UpdateTemplateRequest>,
+    ) -> Result<Response<UpdateTemplateResponse>, Status> {
+        let template = request.into_inner().template.unwrap_or_default();
+        let template = validate_template(template, &self.limits).map_err(to_status)?;
+        let name = template.name.clone();
+        let template = self
+            .templates
+            .update(template)
+            .map_err(store_error)
+            .and_then(|updated| {
+                updated.ok_or_else(|| report!(BppServiceError::TemplateNotFound(name.clone())))
+            })
+            .map_err(to_status)?;
+        info!(name, "Template updated");
+
+        Ok(Response::new(UpdateTemplateResponse {
+            template: Some(template.into()),
+        }))
+    }
+
+    async fn delete_template(
+        &self,
+        request: Request<DeleteTemplateRequest>,
+    ) -> Result<Response<DeleteTemplateResponse>, Status> {
+        let name = validate_template_name(&request.into_inner().name).map_err(to_status)?;
+        let template = self
+            .templates
+            .remove(&name)
+            .map_err(store_error)
+            .and_then(|removed| {
+                removed.ok_or_else(|| report!(BppServiceError::TemplateNotFound(name.clone())))
+            })
+            .map_err(to_status)?;
+        info!(name, "Template deleted");
+
+        Ok(Response::new(DeleteTemplateResponse {
+            template: Some(template.into()),
+        }))
+    }
+}
@@ -0,0 +1,186 @@
+use crate::error_def::BppServiceError;
+use crate::service::to_status;
+use error_stack::report;
+use hyper::body::{HttpBody, SizeHint};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::Notify;
+use tonic::body::BoxBody;
+use tonic::codegen::http::{HeaderMap, Request, Response};
+use tower::{Layer, Service};
+
+/// Signals asking the server to stop. Installed when the server starts, so
+/// a signal received while draining is not lost
+pub struct ShutdownSignals {
+    terminate: Signal,
+    interrupt: Signal,
+}
+
+impl ShutdownSignals {
+    pub fn new() -> io::Result<Self> {
+        Ok(ShutdownSignals {
+            terminate: signal(SignalKind::terminate())?,
+            interrupt: signal(SignalKind::interrupt())?,
+        })
+    }
+
+    /// Wait for the next SIGTERM or SIGINT, returning its name
+    pub async fn recv(&mut self) -> &'static str {
+        tokio::select! {
+            _ = self.terminate.recv() => "SIGTERM",
+            _ = self.interrupt.recv() => "SIGINT",
+        }
+    }
+}
+
+/// Rpcs being handled, counted from their request until their response
+/// body is sent or dropped. Connections left open by idle clients do not
+/// count, so draining does not wait for them. Once draining, new rpcs are
+/// refused and the count only goes down
+#[derive(Debug, Clone, Default)]
+pub struct InFlight {
+    state: Arc<InFlightState>,
+}
+
+#[derive(Debug, Default)]
+struct InFlightState {
+    count: AtomicUsize,
+    draining: AtomicBool,
+    idle: Notify,
+}
+
+impl InFlight {
+    /// Refuse new rpcs with UNAVAILABLE from now on
+    pub fn drain(&self) {
+        self.state.draining.store(true, Ordering::SeqCst);
+    }
+
+    /// Wait until no rpc is in flight
+    pub async fn idle(&self) {
+        while self.state.count.load(Ordering::SeqCst) > 0 {
+            self.state.idle.notified().await;
+        }
+    }
+
+    /// Count an rpc until the guard is dropped. None once draining
+    fn enter(&self) -> Option<InFlightGuard> {
+        // Counted before checking, so `idle` never misses an rpc that got
+        // in just before `drain`
+        self.state.count.fetch_add(1, Ordering::SeqCst);
+        let guard = InFlightGuard {
+            state: self.state.clone(),
+        };
+        match self.state.draining.load(Ordering::SeqCst) {
+            true => None,
+            false => Some(guard),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct InFlightGuard {
+    state: Arc<InFlightState>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.state.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // Stores a permit when `idle` is not waiting yet
+            self.state.idle.notify_one();
+        }
+    }
+}
+
+impl<S> Layer<S> for InFlight {
+    type Service = InFlightService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        InFlightService {
+            inner,
+            in_flight: self.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InFlightService<S> {
+    inner: S,
+    in_flight: InFlight,
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for InFlightService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<InFlightBody<BoxBody>>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        let guard = match self.in_flight.enter() {
+            Some(guard) => guard,
+            None => {
+                let status = to_status(report!(BppServiceError::Unavailable(
+                    "Server is shutting down".to_string()
+                )));
+                let response = status.to_http().map(|inner| InFlightBody {
+                    inner,
+                    _guard: None,
+                });
+                return Box::pin(async move { Ok(response) });
+            }
+        };
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let response = response.await?;
+            Ok(response.map(|inner| InFlightBody {
+                inner,
+                _guard: Some(guard),
+            }))
+        })
+    }
+}
+
+/// Response body keeping its rpc counted in flight until dropped, once
+/// the last message and the trailers are sent
+pub struct InFlightBody<B> {
+    inner: B,
+    _guard: Option<InFlightGuard>,
+}
+
+impl<B: HttpBody + Unpin> HttpBody for InFlightBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,185 @@
+use crate::config::{Config, FromConfig};
+use crate::dao::{unix_millis, write_atomic};
+use crate::error_def::BppStoreError;
+use bpp_proto::bpp::Template;
+use error_stack::{report, IntoReport, Result, ResultExt};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+pub static TEMPLATES_FILE: &str = "templates.json";
+/// Version of the templates file format
+const TEMPLATES_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredTemplate {
+    pub name: String,
+    pub description: String,
+    pub title: String,
+    pub content: String,
+    pub created_ms: i64,
+    pub updated_ms: i64,
+}
+
+impl From<StoredTemplate> for Template {
+    fn from(template: StoredTemplate) -> Self {
+        Template {
+            name: template.name,
+            description: template.description,
+            title: template.title,
+            content: template.content,
+            created_ms: template.created_ms,
+            updated_ms: template.updated_ms,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TemplatesFile {
+    version: u32,
+    templates: Vec<StoredTemplate>,
+}
+
+/// Note templates in `templates.json` next to the notes, sorted by name.
+/// Placeholders are left as written, the client renders them
+#[derive(Debug)]
+pub struct TemplateStore {
+    path: PathBuf,
+    templates: RwLock<Vec<StoredTemplate>>,
+}
+
+impl FromConfig<BppStoreError> for TemplateStore {
+    fn from_config(config: &Config) -> Result<Self, BppStoreError> {
+        TemplateStore::open(&config.database.data_dir)
+    }
+}
+
+impl TemplateStore {
+    pub fn open(data_dir: &Path) -> Result<Self, BppStoreError> {
+        let path = data_dir.join(TEMPLATES_FILE);
+        let templates = if path.exists() {
+            let bytes = fs::read(&path).into_report().change_context_lazy(|| {
+                BppStoreError::FailedToOpenStore(format!("Could not read {}", path.display()))
+            })?;
+            let file: TemplatesFile = serde_json::from_slice(&bytes)
+                .into_report()
+                .change_context_lazy(|| {
+                    BppStoreError::FailedToOpenStore(format!("{} is corrupted", path.display()))
+                })?;
+            if file.version > TEMPLATES_VERSION {
+                return Err(report!(BppStoreError::FailedToOpenStore(format!(
+                    "{} is at version {}, newer than this server supports",
+                    path.display(),
+                    file.version
+                ))));
+            }
+            file.templates
+        } else {
+            vec![]
+        };
+        Ok(TemplateStore {
+            path,
+            templates: RwLock::new(templates),
+        })
+    }
+
+    pub fn list(&self) -> Vec<StoredTemplate> {
+        self.read().clone()
+    }
+
+    pub fn get(&self, name: &str) -> Option<StoredTemplate> {
+        self.read()
+            .iter()
+            .find(|template| template.name == name)
+            .cloned()
+    }
+
+    /// Store a new template. None when the name is taken
+    pub fn create(
+        &self,
+        mut template: StoredTemplate,
+    ) -> Result<Option<StoredTemplate>, BppStoreError> {
+        let mut templates = self.write();
+        let position = match templates.binary_search_by(|other| other.name.cmp(&template.name)) {
+            Ok(_) => return Ok(None),
+            Err(position) => position,
+        };
+        let now = unix_millis();
+        template.created_ms = now;
+        template.updated_ms = now;
+        templates.insert(position, template.clone());
+        if let Err(err) = self.save(&templates) {
+            templates.remove(position);
+            return Err(err);
+        }
+        Ok(Some(template))
+    }
+
+    /// Replace the description, title and content of the template with
+    /// the name of `template`. None when there is no such template
+    pub fn update(
+        &self,
+        template: StoredTemplate,
+    ) -> Result<Option<StoredTemplate>, BppStoreError> {
+        let mut templates = self.write();
+        let position = match templates
+            .iter()
+            .position(|other| other.name == template.name)
+        {
+            Some(position) => position,
+            None => return Ok(None),
+        };
+        let previous = templates[position].clone();
+        templates[position] = StoredTemplate {
+            created_ms: previous.created_ms,
+            updated_ms: unix_millis(),
+            ..template
+        };
+        let updated = templates[position].clone();
+        if let Err(err) = self.save(&templates) {
+            templates[position] = previous;
+            return Err(err);
+        }
+        Ok(Some(updated))
+    }
+
+    /// Remove a template, returning it if it existed
+    pub fn remove(&self, name: &str) -> Result<Option<StoredTemplate>, BppStoreError> {
+        let mut templates = self.write();
+        let position = match templates.iter().position(|template| template.name == name) {
+            Some(position) => position,
+            None => return Ok(None),
+        };
+        let template = templates.remove(position);
+        if let Err(err) = self.save(&templates) {
+            templates.insert(position, template);
+            return Err(err);
+        }
+        Ok(Some(template))
+    }
+
+    fn save(&self, templates: &[StoredTemplate]) -> Result<(), BppStoreError> {
+        let bytes = serde_json::to_vec_pretty(&TemplatesFile {
+            version: TEMPLATES_VERSION,
+            templates: templates.to_vec(),
+        })
+        .into_report()
+        .change_context(BppStoreError::FailedToWriteStore(
+            "Could not serialize templates".to_string(),
+        ))?;
+        write_atomic(&self.path, &bytes)
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Vec<StoredTemplate>> {
+        // Writes restore the previous templates on failure, never leaving
+        // them half updated
+        self.templates.read().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Vec<StoredTemplate>> {
+        self.templates
+            .write()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+}
@@ -0,0 +1,403 @@
+use crate::config::LimitsConfig;
+use crate::dao::StoredNote;
+use crate::error_def::BppServiceError;
+use crate::templates::StoredTemplate;
+use bpp_proto::bpp::{AddRequest, Note, Template};
+use error_stack::{report, Result};
+use unicode_normalization::UnicodeNormalization;
+use uuid::Uuid;
+
+/// Control characters allowed in contents
+static CONTENT_WHITESPACE: [char; 3] = ['\n', '\r', '\t'];
+/// Longest template name, they are typed on the command line
+const MAX_TEMPLATE_NAME_CHARS: usize = 64;
+
+/// A note accepted by `Api::add`, validated and normalized
+#[derive(Debug)]
+pub struct NewNote {
+    /// Id chosen by the client, if any
+    pub id: Option<String>,
+    pub title: String,
+    pub content: String,
+    pub encrypted: bool,
+}
+
+/// Check `request` against `limits`. Titles are NFC normalized and trimmed
+/// so that visually identical titles are stored, and searched, the same
+/// way. Contents are kept as sent, they may be encrypted. Ids chosen by
+/// the client must be uuids
+pub fn validate_add(
+    request: AddRequest,
+    limits: &LimitsConfig,
+) -> Result<NewNote, BppServiceError> {
+    let id = match request.id.trim() {
+        "" => None,
+        id => Some(
+            Uuid::parse_str(id)
+                .map_err(|_| invalid("id", format!("{id:?} is not a uuid")))?
+                .to_string(),
+        ),
+    };
+    Ok(NewNote {
+        id,
+        title: validate_title(&request.title, limits)?,
+        content: validate_content(request.content, limits)?,
+        encrypted: request.encrypted,
+    })
+}
+
+/// Ids are required by the rpcs acting on a single note
+pub fn validate_id(id: String) -> Result<String, BppServiceError> {
+    if id.trim().is_empty() {
+        return Err(report!(BppServiceError::InvalidArgument {
+            field: "id".to_string(),
+            description: "cannot be empty".to_string(),
+        }));
+    }
+    Ok(id)
+}
+
+/// Names of attachments are file names: no path separators, and not `.`
+/// or `..`. Media types default to application/octet-stream
+pub fn validate_attachment(
+    name: &str,
+    media_type: &str,
+    limits: &LimitsConfig,
+) -> Result<(String, String), BppServiceError> {
+    let name = normalize_title(name);
+    if name.is_empty() {
+        return Err(invalid("name", "cannot be empty".to_string()));
+    }
+    if name.contains(['/', '\\']) || name == "." || name == ".." {
+        return Err(invalid("name", format!("{name:?} is not a file name")));
+    }
+    check_name("name", &name, limits.max_title_chars)?;
+
+    let media_type = media_type.trim();
+    if media_type.is_empty() {
+        return Ok((name, "application/octet-stream".to_string()));
+    }
+    if !media_type.contains('/') || media_type.chars().any(|c| c.is_control()) {
+        return Err(invalid(
+            "media_type",
+            format!("{media_type:?} is not a media type"),
+        ));
+    }
+    Ok((name, media_type.to_string()))
+}
+
+/// Templates are checked like notes, placeholders being plain text. The
+/// timestamps sent are ignored
+pub fn validate_template(
+    template: Template,
+    limits: &LimitsConfig,
+) -> Result<StoredTemplate, BppServiceError> {
+    let description = normalize_title(&template.description);
+    check_name("description", &description, limits.max_title_chars)?;
+    Ok(StoredTemplate {
+        name: validate_template_name(&template.name)?,
+        description,
+        title: validate_title(&template.title, limits)?,
+        content: validate_content(template.content, limits)?,
+        created_ms: 0,
+        updated_ms: 0,
+    })
+}
+
+/// Names are lowercase letters, digits, `-` and `_`, starting with a
+/// letter or digit, eg. `standup` or `1-on-1`
+pub fn validate_template_name(name: &str) -> Result<String, BppServiceError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(invalid("name", "cannot be empty".to_string()));
+    }
+    if name.chars().count() > MAX_TEMPLATE_NAME_CHARS {
+        return Err(invalid(
+            "name",
+            format!("must be at most {MAX_TEMPLATE_NAME_CHARS} characters"),
+        ));
+    }
+    let valid = name.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+    if !valid {
+        return Err(invalid(
+            "name",
+            format!("{name:?} must be lowercase letters, digits, - and _"),
+        ));
+    }
+    Ok(name.to_string())
+}
+
+/// Check an imported `note` like `validate_add` does. Notebooks and tags
+/// are normalized like titles; a leading `#` is dropped from tags and
+/// repeated tags are removed
+pub fn validate_import(note: Note, limits: &LimitsConfig) -> Result<StoredNote, BppServiceError> {
+    let title = validate_title(&note.title, limits)?;
+    let content = validate_content(note.content, limits)?;
+
+    let notebook = normalize_title(&note.notebook);
+    check_name("notebook", &notebook, limits.max_title_chars)?;
+
+    let mut tags: Vec<String> = vec![];
+    for tag in &note.tags {
+        let tag = normalize_title(tag).trim_start_matches('#').to_string();
+        if tag.is_empty() {
+            continue;
+        }
+        if tag.chars().any(char::is_whitespace) {
+            return Err(invalid("tags", format!("{tag:?} cannot contain spaces")));
+        }
+        check_name("tags", &tag, limits.max_tag_chars)?;
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    if tags.len() > limits.max_tags {
+        return Err(invalid(
+            "tags",
+            format!(
+                "must be at most {} tags, got {}",
+                limits.max_tags,
+                tags.len()
+            ),
+        ));
+    }
+
+    for (field, timestamp) in [
+        ("created_ms", note.created_ms),
+        ("updated_ms", note.updated_ms),
+    ] {
+        if timestamp < 0 {
+            return Err(invalid(field, "cannot be before 1970".to_string()));
+        }
+    }
+
+    Ok(StoredNote {
+        id: note.id.trim().to_string(),
+        title,
+        content,
+        encrypted: note.encrypted,
+        notebook,
+        tags,
+        created_ms: note.created_ms,
+        updated_ms: note.updated_ms,
+        // Only the metadata would come along, not the data
+        attachments: vec![],
+    })
+}
+
+fn validate_title(title: &str, limits: &LimitsConfig) -> Result<String, BppServiceError> {
+    let title = normalize_title(title);
+    let title_chars = title.chars().count();
+    if title.is_empty() {
+        return Err(invalid("title", "cannot be empty".to_string()));
+    }
+    if title_chars > limits.max_title_chars {
+        return Err(invalid(
+            "title",
+            format!(
+                "must be at most {} characters, got {title_chars}",
+                limits.max_title_chars
+            ),
+        ));
+    }
+    if title.chars().any(char::is_control) {
+        return Err(invalid(
+            "title",
+            "cannot contain control characters".to_string(),
+        ));
+    }
+    Ok(title)
+}
+
+/// Contents are kept as sent, they may be encrypted
+fn validate_content(content: String, limits: &LimitsConfig) -> Result<String, BppServiceError> {
+    if content.len() > limits.max_content_bytes {
+        return Err(invalid(
+            "content",
+            format!(
+                "must be at most {} bytes, got {}",
+                limits.max_content_bytes,
+                content.len()
+            ),
+        ));
+    }
+    if content
+        .chars()
+        .any(|c| c.is_control() && !CONTENT_WHITESPACE.contains(&c))
+    {
+        return Err(invalid(
+            "content",
+            "cannot contain control characters other than tabs and line breaks".to_string(),
+        ));
+    }
+    Ok(content)
+}
+
+/// Notebooks and tags share the limits of titles, but may be empty
+fn check_name(field: &str, name: &str, max_chars: usize) -> Result<(), BppServiceError> {
+    let chars = name.chars().count();
+    if chars > max_chars {
+        return Err(invalid(
+            field,
+            format!("must be at most {max_chars} characters, got {chars}"),
+        ));
+    }
+    if name.chars().any(char::is_control) {
+        return Err(invalid(
+            field,
+            "cannot contain control characters".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn normalize_title(title: &str) -> String {
+    title.nfc().collect::<String>().trim().to_string()
+}
+
+fn invalid(field: &str, description: String) -> error_stack::Report<BppServiceError> {
+    report!(BppServiceError::InvalidArgument {
+        field: field.to_string(),
+        description,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> LimitsConfig {
+        LimitsConfig {
+            max_title_chars: 10,
+            max_content_bytes: 16,
+            max_tags: 2,
+            max_tag_chars: 5,
+            ..LimitsConfig::default()
+        }
+    }
+
+    fn field(err: error_stack::Report<BppServiceError>) -> String {
+        match err.current_context() {
+            BppServiceError::InvalidArgument { field, .. } => field.clone(),
+            other => panic!("not an invalid argument: {other}"),
+        }
+    }
+
+    fn add(title: &str, content: &str) -> AddRequest {
+        AddRequest {
+            title: title.to_string(),
+            content: content.to_string(),
+            ..AddRequest::default()
+        }
+    }
+
+    #[test]
+    fn titles_are_normalized_and_trimmed() {
+        // "e" followed by a combining acute accent
+        let note = validate_add(add("  cafe\u{301} \n", "x"), &limits()).unwrap();
+        assert_eq!(note.title, "caf\u{e9}");
+        assert_eq!(note.id, None);
+    }
+
+    #[test]
+    fn notes_over_the_limits_are_rejected() {
+        for (request, expected) in [
+            (add(" ", "x"), "title"),
+            (add("eleven char", "x"), "title"),
+            (add("a\u{7}b", "x"), "title"),
+            (add("title", "seventeen bytes!!"), "content"),
+            (add("title", "bell\u{7}"), "content"),
+        ] {
+            assert_eq!(
+                field(validate_add(request, &limits()).unwrap_err()),
+                expected
+            );
+        }
+        assert!(validate_add(add("title", "tab\tand\r\nlines"), &limits()).is_ok());
+    }
+
+    #[test]
+    fn client_ids_must_be_uuids() {
+        let id = Uuid::new_v4();
+        let request = AddRequest {
+            id: format!(" {} ", id.as_hyphenated().to_string().to_uppercase()),
+            ..add("title", "x")
+        };
+        let note = validate_add(request, &limits()).unwrap();
+        assert_eq!(note.id, Some(id.to_string()));
+
+        let request = AddRequest {
+            id: "1".to_string(),
+            ..add("title", "x")
+        };
+        assert_eq!(field(validate_add(request, &limits()).unwrap_err()), "id");
+        assert!(validate_id(" ".to_string()).is_err());
+    }
+
+    #[test]
+    fn attachments_are_file_names() {
+        assert_eq!(
+            validate_attachment(" scan.pdf ", "", &limits()).unwrap(),
+            (
+                "scan.pdf".to_string(),
+                "application/octet-stream".to_string()
+            )
+        );
+        for name in ["", "a/b", "a\\b", ".", ".."] {
+            assert!(validate_attachment(name, "", &limits()).is_err(), "{name}");
+        }
+        assert_eq!(
+            field(validate_attachment("scan.pdf", "pdf", &limits()).unwrap_err()),
+            "media_type"
+        );
+    }
+
+    #[test]
+    fn template_names_are_lowercase_words() {
+        for name in ["standup", "1-on-1", "weekly_review"] {
+            assert_eq!(validate_template_name(name).unwrap(), name);
+        }
+        for name in [
+            "",
+            "Standup",
+            "-standup",
+            "stand up",
+            "café",
+            &"a".repeat(65),
+        ] {
+            assert!(validate_template_name(name).is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn imported_tags_are_deduplicated() {
+        let note = Note {
+            title: "title".to_string(),
+            tags: vec!["#rust".to_string(), "rust".to_string(), " ".to_string()],
+            ..Note::default()
+        };
+        assert_eq!(validate_import(note, &limits()).unwrap().tags, ["rust"]);
+
+        for tags in [vec!["a b"], vec!["toolong"], vec!["a", "b", "c"]] {
+            let note = Note {
+                title: "title".to_string(),
+                tags: tags.iter().map(|tag| tag.to_string()).collect(),
+                ..Note::default()
+            };
+            assert_eq!(field(validate_import(note, &limits()).unwrap_err()), "tags");
+        }
+
+        let note = Note {
+            title: "title".to_string(),
+            created_ms: -1,
+            ..Note::default()
+        };
+        assert_eq!(
+            field(validate_import(note, &limits()).unwrap_err()),
+            "created_ms"
+        );
+    }
+}
@@ -0,0 +1,209 @@
+mod common;
+
+use bpp_proto::bpp::api_client::ApiClient;
+use bpp_proto::bpp::{
+    AddRequest, AttachRequest, CreateTemplateRequest, FetchRequest, SearchRequest, Template,
+};
+use common::{read_json, TestDir, TestServer};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+use tonic::transport::Channel;
+
+const ATTACHMENT: &[u8] = b"minutes of the quarterly planning";
+
+/// Add notes, an attachment to the first one and a template. Returns the
+/// ids of the notes
+async fn populate(client: &mut ApiClient<Channel>) -> Vec<String> {
+    let mut ids = vec![];
+    for i in 0..3 {
+        let request = AddRequest {
+            title: format!("note {i}"),
+            content: format!("content of note {i}"),
+            encrypted: false,
+            id: String::new(),
+        };
+        let response = client.add(request).await.unwrap().into_inner();
+        ids.push(response.note.unwrap().id);
+    }
+
+    // The first message names the note and the attachment
+    let requests: Vec<_> = ATTACHMENT
+        .chunks(8)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut request = AttachRequest {
+                data: chunk.to_vec(),
+                ..Default::default()
+            };
+            if i == 0 {
+                request.note_id = ids[0].clone();
+                request.name = "minutes.txt".to_string();
+                request.media_type = "text/plain".to_string();
+            }
+            request
+        })
+        .collect();
+    client.attach(tokio_stream::iter(requests)).await.unwrap();
+
+    client
+        .create_template(CreateTemplateRequest {
+            template: Some(Template {
+                name: "standup".to_string(),
+                title: "Standup {{date}}".to_string(),
+                content: "{{Yesterday}}".to_string(),
+                ..Default::default()
+            }),
+        })
+        .await
+        .unwrap();
+    ids
+}
+
+/// Ids of the notes whose attachments match `query`
+async fn attachment_matches(client: &mut ApiClient<Channel>, query: &str) -> Vec<String> {
+    let found = client
+        .search(SearchRequest {
+            query: query.to_string(),
+            all: false,
+            attachments: true,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    found
+        .matches
+        .into_iter()
+        .map(|found| found.note_id)
+        .collect()
+}
+
+/// Wait for the text of the attachment to be extracted in the background
+async fn wait_for_text(client: &mut ApiClient<Channel>) {
+    let started = Instant::now();
+    while attachment_matches(client, "quarterly planning")
+        .await
+        .is_empty()
+    {
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "attachment text not extracted"
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// Files under `dir` by path relative to it, uploads in progress excluded
+fn files(dir: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+    let mut files = BTreeMap::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                if path.file_name().unwrap() != "tmp" {
+                    pending.push(path);
+                }
+            } else {
+                let relative = path.strip_prefix(dir).unwrap().to_path_buf();
+                files.insert(relative, fs::read(&path).unwrap());
+            }
+        }
+    }
+    files
+}
+
+fn run(command: &mut Command) {
+    let status = command.status().unwrap();
+    assert!(status.success(), "{command:?} exited with {status}");
+}
+
+fn audit_lines(data_dir: &Path) -> Vec<Value> {
+    fs::read_to_string(data_dir.join("audit.jsonl"))
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn restored_backup_matches_the_store() {
+    let dir = TestDir::new("bpp-backup", "");
+    let data_dir = dir.path().join("data");
+    let mut server = TestServer::start(&dir, &data_dir);
+    let mut client = server.connect().await;
+    let mut ids = populate(&mut client).await;
+    wait_for_text(&mut client).await;
+    server.stop();
+
+    let backup = dir.path().join("backup.json.gz");
+    run(dir.command(&data_dir).arg("backup").arg("-o").arg(&backup));
+    let restored = dir.path().join("restored");
+    run(dir.command(&restored).arg("restore").arg(&backup));
+
+    assert_eq!(
+        read_json(&restored.join("notes.json")),
+        read_json(&data_dir.join("notes.json"))
+    );
+    assert_eq!(
+        fs::read(restored.join("templates.json")).unwrap(),
+        fs::read(data_dir.join("templates.json")).unwrap()
+    );
+    for store in ["blobs", "texts"] {
+        let backed_up = files(&data_dir.join(store));
+        assert!(!backed_up.is_empty(), "nothing in {store}");
+        assert_eq!(files(&restored.join(store)), backed_up, "{store} differ");
+    }
+
+    // The events of the store, then the restore
+    let mut events = audit_lines(&restored);
+    let restore = events.pop().unwrap();
+    assert_eq!(events, audit_lines(&data_dir));
+    assert_eq!(restore["action"], "restore");
+    let detail = restore["detail"].as_str().unwrap();
+    assert!(
+        detail.starts_with("backup.json.gz sha256 ") && detail.ends_with(", 3 notes"),
+        "unexpected detail {detail:?}"
+    );
+
+    // The restored store is searchable, its index rebuilt with the texts of
+    // the attachments, and serves the attachment
+    let mut server = TestServer::start(&dir, &restored);
+    let mut client = server.connect().await;
+    let found = client
+        .search(SearchRequest {
+            query: "content".to_string(),
+            all: true,
+            attachments: false,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let mut found: Vec<_> = found.notes.into_iter().map(|note| note.id).collect();
+    found.sort();
+    let note_id = ids[0].clone();
+    ids.sort();
+    assert_eq!(found, ids);
+    assert_eq!(
+        attachment_matches(&mut client, "quarterly planning").await,
+        vec![note_id.clone()]
+    );
+
+    let mut fetched = client
+        .fetch(FetchRequest {
+            note_id,
+            name: "minutes.txt".to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let mut data = vec![];
+    while let Some(response) = fetched.message().await.unwrap() {
+        data.extend(response.data);
+    }
+    assert_eq!(data, ATTACHMENT);
+    server.stop();
+}
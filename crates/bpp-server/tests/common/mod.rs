@@ -0,0 +1,145 @@
+// Each test binary only uses some of the helpers
+#![allow(dead_code)]
+
+use bpp_proto::bpp::api_client::ApiClient;
+use serde_json::Value;
+use std::fs;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus};
+use std::time::{Duration, Instant};
+use tonic::transport::Channel;
+use uuid::Uuid;
+
+/// Temporary directory holding the config and data directories of a test,
+/// removed on drop
+pub struct TestDir {
+    dir: PathBuf,
+}
+
+impl TestDir {
+    /// A new directory with a `server.yaml` of `config` added to the
+    /// settings every test uses
+    pub fn new(prefix: &str, config: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("{prefix}-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("server.yaml"),
+            format!(
+                "logging:\n  level: WARN\n\
+                 rate_limit:\n  requests_per_second: 0\n\
+                 service:\n  shutdown_deadline_secs: 2\n\
+                 {config}"
+            ),
+        )
+        .unwrap();
+        TestDir { dir }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn config(&self) -> PathBuf {
+        self.dir.join("server.yaml")
+    }
+
+    /// A `bpp-server` command using the config and `data_dir`
+    pub fn command(&self, data_dir: &Path) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_bpp-server"));
+        command
+            .arg("--config")
+            .arg(self.config())
+            .arg("--data-dir")
+            .arg(data_dir);
+        command
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+/// A server serving `data_dir` on a free port, killed on drop
+pub struct TestServer {
+    pub addr: String,
+    process: Child,
+}
+
+impl TestServer {
+    pub fn start(dir: &TestDir, data_dir: &Path) -> Self {
+        let addr = format!("127.0.0.1:{}", free_port());
+        let process = dir
+            .command(data_dir)
+            .arg("--listen")
+            .arg(&addr)
+            .spawn()
+            .unwrap();
+        TestServer { addr, process }
+    }
+
+    pub async fn connect(&self) -> ApiClient<Channel> {
+        let started = Instant::now();
+        loop {
+            match ApiClient::connect(format!("http://{}", self.addr)).await {
+                Ok(client) => return client,
+                Err(_) if started.elapsed() < Duration::from_secs(10) => {
+                    tokio::time::sleep(Duration::from_millis(50)).await
+                }
+                Err(err) => panic!("server did not start: {err}"),
+            }
+        }
+    }
+
+    pub fn signal(&self, signal: &str) {
+        let status = Command::new("kill")
+            .arg(format!("-{signal}"))
+            .arg(self.process.id().to_string())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    pub fn wait(&mut self, timeout: Duration) -> ExitStatus {
+        let started = Instant::now();
+        loop {
+            if let Some(status) = self.process.try_wait().unwrap() {
+                return status;
+            }
+            if started.elapsed() > timeout {
+                self.process.kill().ok();
+                panic!("server did not stop within {timeout:?}");
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    /// Stop with SIGTERM, asserting a clean exit
+    pub fn stop(&mut self) {
+        self.signal("TERM");
+        let status = self.wait(Duration::from_secs(10));
+        assert!(status.success(), "server exited with {status}");
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.process.kill().ok();
+    }
+}
+
+/// Let the os pick a free port
+pub fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+pub fn read_json(path: &Path) -> Value {
+    serde_json::from_slice(&fs::read(path).unwrap())
+        .unwrap_or_else(|err| panic!("{} is corrupted: {err}", path.display()))
+}
@@ -0,0 +1,133 @@
+mod common;
+
+use bpp_proto::bpp::api_client::ApiClient;
+use bpp_proto::bpp::{AddRequest, AuditLogRequest, SearchRequest};
+use common::{TestDir, TestServer};
+use flate2::read::GzDecoder;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::Command;
+use tonic::transport::Channel;
+
+const TITLE: &str = "quarterly secret";
+const CONTENT: &str = "launch codes";
+
+/// A test directory whose config reads the passphrase from `passphrase`
+fn encrypted_dir(passphrase: &str) -> TestDir {
+    let dir = TestDir::new("bpp-encryption", "");
+    let path = dir.path().join("passphrase");
+    fs::write(&path, passphrase).unwrap();
+    OpenOptions::new()
+        .append(true)
+        .open(dir.config())
+        .and_then(|mut config| writeln!(config, "database:\n  passphrase_file: {}", path.display()))
+        .unwrap();
+    dir
+}
+
+fn run(command: &mut Command) -> bool {
+    command.status().unwrap().success()
+}
+
+/// Titles of the notes whose contents match `query`
+async fn search(client: &mut ApiClient<Channel>, query: &str) -> Vec<String> {
+    let found = client
+        .search(SearchRequest {
+            query: query.to_string(),
+            all: true,
+            attachments: false,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    found.notes.into_iter().map(|note| note.title).collect()
+}
+
+fn contains(bytes: &[u8], text: &str) -> bool {
+    bytes
+        .windows(text.len())
+        .any(|window| window == text.as_bytes())
+}
+
+fn assert_sealed(path: &Path) {
+    let bytes = fs::read(path).unwrap();
+    assert!(
+        !contains(&bytes, TITLE) && !contains(&bytes, CONTENT),
+        "{} is not encrypted",
+        path.display()
+    );
+}
+
+#[tokio::test]
+async fn notes_are_encrypted_at_rest() {
+    let dir = encrypted_dir("correct horse battery staple");
+    let data_dir = dir.path().join("data");
+    let mut server = TestServer::start(&dir, &data_dir);
+    let mut client = server.connect().await;
+    client
+        .add(AddRequest {
+            title: TITLE.to_string(),
+            content: CONTENT.to_string(),
+            encrypted: false,
+            id: String::new(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(search(&mut client, "launch").await, vec![TITLE]);
+    server.stop();
+
+    for file in ["notes.json", "index.json", "audit.jsonl"] {
+        assert_sealed(&data_dir.join(file));
+    }
+    let backup = dir.path().join("backup.json.gz");
+    assert!(run(dir
+        .command(&data_dir)
+        .arg("backup")
+        .arg("-o")
+        .arg(&backup)));
+    let mut decompressed = vec![];
+    GzDecoder::new(fs::File::open(&backup).unwrap())
+        .read_to_end(&mut decompressed)
+        .unwrap();
+    assert!(!contains(&decompressed, TITLE) && !contains(&decompressed, CONTENT));
+
+    // The store only opens with the new passphrase once rotated
+    let new_passphrase = dir.path().join("new-passphrase");
+    fs::write(&new_passphrase, "tr0ub4dor&3").unwrap();
+    assert!(run(dir
+        .command(&data_dir)
+        .arg("rotate-key")
+        .arg("--new-passphrase-file")
+        .arg(&new_passphrase)));
+    assert!(!run(dir.command(&data_dir).arg("reindex")));
+    fs::copy(&new_passphrase, dir.path().join("passphrase")).unwrap();
+    assert_sealed(&data_dir.join("notes.json"));
+
+    // Backups taken before the rotation open with the retired key
+    assert!(run(dir.command(&data_dir).arg("restore").arg(&backup)));
+    let mut server = TestServer::start(&dir, &data_dir);
+    let mut client = server.connect().await;
+    assert_eq!(search(&mut client, "launch").await, vec![TITLE]);
+    let events = client
+        .audit_log(AuditLogRequest::default())
+        .await
+        .unwrap()
+        .into_inner()
+        .events;
+    assert_eq!(events[0].note_title, TITLE);
+    server.stop();
+}
+
+#[tokio::test]
+async fn wrong_passphrase_is_refused() {
+    let dir = encrypted_dir("correct horse battery staple");
+    let data_dir = dir.path().join("data");
+    assert!(run(dir.command(&data_dir).arg("reindex")));
+
+    fs::write(dir.path().join("passphrase"), "wrong").unwrap();
+    let output = dir.command(&data_dir).arg("reindex").output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("wrong passphrase"), "{stderr}");
+}
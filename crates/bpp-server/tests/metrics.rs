@@ -0,0 +1,173 @@
+mod common;
+
+use bpp_proto::bpp::{AddRequest, AttachRequest, FetchRequest, SearchRequest};
+use common::{free_port, TestDir, TestServer};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+/// Body of `GET /metrics`, retried until the endpoint is up
+fn scrape(addr: &str) -> String {
+    let started = Instant::now();
+    loop {
+        match TcpStream::connect(addr) {
+            Ok(mut stream) => {
+                stream
+                    .write_all(b"GET /metrics HTTP/1.0\r\nHost: localhost\r\n\r\n")
+                    .unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).unwrap();
+                let (head, body) = response.split_once("\r\n\r\n").unwrap();
+                assert!(head.starts_with("HTTP/1.0 200"), "{head}");
+                return body.to_string();
+            }
+            Err(_) if started.elapsed() < Duration::from_secs(10) => {
+                std::thread::sleep(Duration::from_millis(50))
+            }
+            Err(err) => panic!("metrics endpoint did not start: {err}"),
+        }
+    }
+}
+
+/// Value of the sample `name` with exactly `labels` once it reaches
+/// `expected`, scraping again until then
+async fn wait_for_sample(addr: &str, name: &str, labels: &str, expected: u64) -> u64 {
+    let started = Instant::now();
+    loop {
+        let value = sample(&scrape(addr), name, labels);
+        if value >= expected || started.elapsed() > Duration::from_secs(10) {
+            return value;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// Value of the sample `name` with exactly `labels`, 0 when missing
+fn sample(metrics: &str, name: &str, labels: &str) -> u64 {
+    let prefix = match labels {
+        "" => format!("{name} "),
+        labels => format!("{name}{{{labels}}} "),
+    };
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix(&prefix))
+        .map_or(0, |value| value.parse().unwrap())
+}
+
+#[tokio::test]
+async fn rpcs_are_counted() {
+    let metrics_addr = format!("127.0.0.1:{}", free_port());
+    let dir = TestDir::new(
+        "bpp-metrics",
+        &format!("metrics:\n  listen: {metrics_addr}\n"),
+    );
+    let mut server = TestServer::start(&dir, &dir.path().join("data"));
+    let mut client = server.connect().await;
+
+    let before = scrape(&metrics_addr);
+    for i in 0..3 {
+        client
+            .add(AddRequest {
+                title: format!("note {i}"),
+                content: "content".to_string(),
+                encrypted: false,
+                id: String::new(),
+            })
+            .await
+            .unwrap();
+    }
+    client
+        .search(SearchRequest {
+            query: "content".to_string(),
+            all: false,
+            attachments: false,
+        })
+        .await
+        .unwrap();
+    let after = scrape(&metrics_addr);
+
+    let counted = |metrics: &str, method: &str| {
+        sample(
+            metrics,
+            "bpp_rpc_requests_total",
+            &format!("code=\"Ok\",method=\"/bpp.Api/{method}\""),
+        )
+    };
+    assert_eq!(counted(&after, "Add") - counted(&before, "Add"), 3);
+    assert_eq!(counted(&after, "Search") - counted(&before, "Search"), 1);
+    assert_eq!(sample(&after, "bpp_notes", ""), 3, "{after}");
+    server.stop();
+}
+
+#[tokio::test]
+async fn streams_are_counted_with_their_final_status() {
+    let metrics_addr = format!("127.0.0.1:{}", free_port());
+    let dir = TestDir::new(
+        "bpp-metrics-streams",
+        &format!("metrics:\n  listen: {metrics_addr}\n"),
+    );
+    let mut server = TestServer::start(&dir, &dir.path().join("data"));
+    let mut client = server.connect().await;
+
+    let note_id = client
+        .add(AddRequest {
+            title: "with a large attachment".to_string(),
+            content: "content".to_string(),
+            encrypted: false,
+            id: String::new(),
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .note
+        .unwrap()
+        .id;
+    // Far more than the stream buffers, so fetching it stays in flight
+    let requests: Vec<_> = (0..64)
+        .map(|i| {
+            let mut request = AttachRequest {
+                data: vec![0; 64 * 1024],
+                ..Default::default()
+            };
+            if i == 0 {
+                request.note_id = note_id.clone();
+                request.name = "large.bin".to_string();
+            }
+            request
+        })
+        .collect();
+    client.attach(tokio_stream::iter(requests)).await.unwrap();
+
+    let fetch = |name: &str| FetchRequest {
+        note_id: note_id.clone(),
+        name: name.to_string(),
+    };
+    let labels = |code: &str| format!("code=\"{code}\",method=\"/bpp.Api/Fetch\"");
+
+    // Read to the end, the status comes in the trailers
+    let mut fetched = client.fetch(fetch("large.bin")).await.unwrap().into_inner();
+    while fetched.message().await.unwrap().is_some() {}
+    let name = "bpp_rpc_requests_total";
+    assert_eq!(
+        wait_for_sample(&metrics_addr, name, &labels("Ok"), 1).await,
+        1
+    );
+
+    // Dropped after the first message
+    let mut fetched = client.fetch(fetch("large.bin")).await.unwrap().into_inner();
+    fetched.message().await.unwrap().unwrap();
+    drop(fetched);
+    assert_eq!(
+        wait_for_sample(&metrics_addr, name, &labels("Cancelled"), 1).await,
+        1
+    );
+
+    // Failed before any message, the status comes in the headers
+    client.fetch(fetch("missing.bin")).await.unwrap_err();
+    assert_eq!(
+        wait_for_sample(&metrics_addr, name, &labels("NotFound"), 1).await,
+        1
+    );
+    assert_eq!(sample(&scrape(&metrics_addr), name, &labels("Ok")), 1);
+    server.stop();
+}
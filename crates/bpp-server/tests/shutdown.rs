@@ -0,0 +1,121 @@
+mod common;
+
+use bpp_proto::bpp::api_client::ApiClient;
+use bpp_proto::bpp::AddRequest;
+use common::{read_json, TestDir, TestServer};
+use std::collections::BTreeSet;
+use std::fs;
+use std::time::{Duration, Instant};
+use tonic::transport::Channel;
+
+const WRITERS: usize = 8;
+
+/// Keep adding notes until the server stops answering, returning the ids
+/// of the notes it acknowledged
+async fn write_until_stopped(mut client: ApiClient<Channel>, writer: usize) -> Vec<String> {
+    let mut added = vec![];
+    for i in 0.. {
+        let request = AddRequest {
+            title: format!("note {writer} {i}"),
+            content: "some content to index ".repeat(50),
+            encrypted: false,
+            id: String::new(),
+        };
+        match client.add(request).await {
+            Ok(response) => added.push(response.into_inner().note.unwrap().id),
+            Err(_) => break,
+        }
+    }
+    added
+}
+
+#[tokio::test]
+async fn sigterm_during_writes_leaves_a_consistent_store() {
+    let dir = TestDir::new("bpp-shutdown", "");
+    let data_dir = dir.path().join("data");
+    let mut server = TestServer::start(&dir, &data_dir);
+    let client = server.connect().await;
+
+    let writers: Vec<_> = (0..WRITERS)
+        .map(|writer| tokio::spawn(write_until_stopped(client.clone(), writer)))
+        .collect();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    server.signal("TERM");
+
+    // Rpcs in flight complete, later ones are refused with UNAVAILABLE
+    let status = server.wait(Duration::from_secs(10));
+    assert!(status.success(), "server exited with {status}");
+
+    let mut acknowledged = BTreeSet::new();
+    for writer in writers {
+        acknowledged.extend(writer.await.unwrap());
+    }
+    assert!(
+        !acknowledged.is_empty(),
+        "no note was written before the signal"
+    );
+
+    let notes = read_json(&data_dir.join("notes.json"));
+    let stored: BTreeSet<String> = notes["notes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|note| note["id"].as_str().unwrap().to_string())
+        .collect();
+    assert!(
+        acknowledged.is_subset(&stored),
+        "acknowledged notes were lost"
+    );
+
+    let index = read_json(&data_dir.join("index.json"));
+    let indexed: BTreeSet<String> = index["ids"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|id| id.as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(indexed, stored, "search index does not match the notes");
+
+    let leftovers: Vec<_> = fs::read_dir(&data_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .filter(|name| name.to_string_lossy().ends_with(".tmp"))
+        .collect();
+    assert!(leftovers.is_empty(), "partial writes left: {leftovers:?}");
+}
+
+#[tokio::test]
+async fn idle_connections_do_not_hold_up_shutdown() {
+    let deadline = Duration::from_secs(30);
+    let dir = TestDir::new(
+        "bpp-shutdown-idle",
+        &format!(
+            "service:\n  shutdown_deadline_secs: {}\n",
+            deadline.as_secs()
+        ),
+    );
+    let mut server = TestServer::start(&dir, &dir.path().join("data"));
+    let mut client = server.connect().await;
+    client
+        .add(AddRequest {
+            title: "note".to_string(),
+            content: "content".to_string(),
+            encrypted: false,
+            id: String::new(),
+        })
+        .await
+        .unwrap();
+
+    // Waiting blocks the runtime of the client, so its connection stays
+    // open and never answers the server, like a client that hung
+    let started = Instant::now();
+    server.signal("TERM");
+    let status = server.wait(Duration::from_secs(10));
+    assert!(status.success(), "server exited with {status}");
+    assert!(
+        started.elapsed() < deadline / 10,
+        "shutdown took {:?}",
+        started.elapsed()
+    );
+    drop(client);
+}